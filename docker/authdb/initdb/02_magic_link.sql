@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN IF NOT EXISTS magic_link_token VARCHAR(128);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS magic_link_sent_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,4 @@
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_change VARCHAR(128);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_change_token_new VARCHAR(128);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_change_token_current VARCHAR(128);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_change_sent_at TIMESTAMP WITH TIME ZONE;
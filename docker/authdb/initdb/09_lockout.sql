@@ -0,0 +1,4 @@
+ALTER TABLE users ADD COLUMN IF NOT EXISTS failed_sign_in_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS locked_until TIMESTAMP WITH TIME ZONE;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS unlock_token VARCHAR(128);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS unlock_sent_at TIMESTAMP WITH TIME ZONE;
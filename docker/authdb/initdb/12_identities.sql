@@ -0,0 +1,22 @@
+-- identities linked to a user, e.g. secondary email addresses and later external logins.
+-- The primary email address of a user stays in users.email.
+CREATE TABLE IF NOT EXISTS identities (
+  id VARCHAR(128) NOT NULL PRIMARY KEY,
+  user_id VARCHAR(128) NOT NULL,
+  provider VARCHAR(40) NOT NULL,
+  provider_id VARCHAR(255) NOT NULL,
+  email VARCHAR(128),
+  verified_at TIMESTAMP WITH TIME ZONE,
+  last_used_at TIMESTAMP WITH TIME ZONE,
+  confirmation_token VARCHAR(128),
+  confirmation_sent_at TIMESTAMP WITH TIME ZONE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CONSTRAINT uq_identities_provider UNIQUE (provider, provider_id),
+  CONSTRAINT fk_identities
+    FOREIGN KEY (user_id)
+      REFERENCES users(id)
+      ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS identities_user_id_idx ON identities (user_id);
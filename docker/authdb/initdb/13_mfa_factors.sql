@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS mfa_factors (
+  id VARCHAR(128) NOT NULL PRIMARY KEY,
+  user_id VARCHAR(128) NOT NULL,
+  factor_type VARCHAR(40) NOT NULL,
+  friendly_name VARCHAR(128),
+  status VARCHAR(40) NOT NULL,
+  -- encrypted with EZA_MFA_ENCRYPTION_KEY
+  secret VARCHAR(255) NOT NULL,
+  last_used_step BIGINT NOT NULL DEFAULT 0,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CONSTRAINT fk_mfa_factors
+    FOREIGN KEY (user_id)
+      REFERENCES users(id)
+      ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS mfa_factors_user_id_idx ON mfa_factors (user_id);
+
+-- authentication assurance level and methods of the session
+ALTER TABLE refresh_tokens ADD COLUMN IF NOT EXISTS aal VARCHAR(8) NOT NULL DEFAULT 'aal1';
+ALTER TABLE refresh_tokens ADD COLUMN IF NOT EXISTS amr TEXT[] NOT NULL DEFAULT '{}';
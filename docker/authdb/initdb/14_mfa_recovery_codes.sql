@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS mfa_recovery_codes (
+  id VARCHAR(128) NOT NULL PRIMARY KEY,
+  user_id VARCHAR(128) NOT NULL,
+  code VARCHAR(128) NOT NULL,
+  used_at TIMESTAMP WITH TIME ZONE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CONSTRAINT fk_mfa_recovery_codes
+    FOREIGN KEY (user_id)
+      REFERENCES users(id)
+      ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS mfa_recovery_codes_user_id_idx ON mfa_recovery_codes (user_id);
@@ -0,0 +1,40 @@
+-- a passkey is a verified mfa factor of type webauthn, the credential keeps its public key
+CREATE TABLE IF NOT EXISTS webauthn_credentials (
+  id VARCHAR(128) NOT NULL PRIMARY KEY,
+  factor_id VARCHAR(128) NOT NULL,
+  user_id VARCHAR(128) NOT NULL,
+  -- base64url, as sent by the authenticator
+  credential_id VARCHAR(1024) NOT NULL UNIQUE,
+  -- DER SubjectPublicKeyInfo
+  public_key BYTEA NOT NULL,
+  -- COSE algorithm identifier
+  algorithm INTEGER NOT NULL,
+  sign_count BIGINT NOT NULL DEFAULT 0,
+  transports TEXT[] NOT NULL DEFAULT '{}',
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  last_used_at TIMESTAMP WITH TIME ZONE,
+  CONSTRAINT fk_webauthn_credentials_factor
+    FOREIGN KEY (factor_id)
+      REFERENCES mfa_factors(id)
+      ON DELETE CASCADE,
+  CONSTRAINT fk_webauthn_credentials_user
+    FOREIGN KEY (user_id)
+      REFERENCES users(id)
+      ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS webauthn_credentials_user_id_idx ON webauthn_credentials (user_id);
+
+-- a challenge can be answered once, it is deleted when it is used
+CREATE TABLE IF NOT EXISTS webauthn_challenges (
+  id VARCHAR(128) NOT NULL PRIMARY KEY,
+  user_id VARCHAR(128),
+  challenge VARCHAR(128) NOT NULL,
+  ceremony VARCHAR(40) NOT NULL,
+  expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CONSTRAINT fk_webauthn_challenges
+    FOREIGN KEY (user_id)
+      REFERENCES users(id)
+      ON DELETE CASCADE
+);
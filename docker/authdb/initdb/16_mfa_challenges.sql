@@ -0,0 +1,17 @@
+-- a sign in which waits for a second factor, the challenge token can complete it once
+CREATE TABLE IF NOT EXISTS mfa_challenges (
+  id VARCHAR(128) NOT NULL PRIMARY KEY,
+  user_id VARCHAR(128) NOT NULL,
+  -- hashed challenge token
+  token VARCHAR(255) NOT NULL UNIQUE,
+  -- the methods of the first factor
+  amr TEXT[] NOT NULL DEFAULT '{}',
+  expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CONSTRAINT fk_mfa_challenges
+    FOREIGN KEY (user_id)
+      REFERENCES users(id)
+      ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS mfa_challenges_user_id_idx ON mfa_challenges (user_id);
@@ -0,0 +1,4 @@
+-- the one-time code of an email factor, hashed
+ALTER TABLE mfa_factors ADD COLUMN IF NOT EXISTS otp VARCHAR(128);
+ALTER TABLE mfa_factors ADD COLUMN IF NOT EXISTS otp_sent_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE mfa_factors ADD COLUMN IF NOT EXISTS otp_attempts INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,35 @@
+-- third-party applications which sign in users with OAuth 2.0
+CREATE TABLE IF NOT EXISTS oauth_clients (
+  id VARCHAR(128) NOT NULL PRIMARY KEY,
+  name VARCHAR(255) NOT NULL,
+  -- the authorization response is only sent to one of these URIs
+  redirect_uris TEXT[] NOT NULL DEFAULT '{}',
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- an authorization code can be exchanged for tokens once
+CREATE TABLE IF NOT EXISTS oauth_authorization_codes (
+  id VARCHAR(128) NOT NULL PRIMARY KEY,
+  -- hashed authorization code
+  code VARCHAR(255) NOT NULL UNIQUE,
+  client_id VARCHAR(128) NOT NULL,
+  user_id VARCHAR(128) NOT NULL,
+  redirect_uri TEXT NOT NULL,
+  scope TEXT NOT NULL DEFAULT '',
+  -- PKCE S256 challenge
+  code_challenge VARCHAR(128) NOT NULL,
+  -- the session which approved the authorization
+  amr TEXT[] NOT NULL DEFAULT '{}',
+  authenticated_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CONSTRAINT fk_oauth_authorization_codes_client
+    FOREIGN KEY (client_id)
+      REFERENCES oauth_clients(id)
+      ON DELETE CASCADE,
+  CONSTRAINT fk_oauth_authorization_codes_user
+    FOREIGN KEY (user_id)
+      REFERENCES users(id)
+      ON DELETE CASCADE
+);
@@ -0,0 +1,14 @@
+-- hashed client secret, NULL for a public client which only uses PKCE
+ALTER TABLE oauth_clients ADD COLUMN IF NOT EXISTS secret VARCHAR(255);
+ALTER TABLE oauth_clients ADD COLUMN IF NOT EXISTS grant_types TEXT[] NOT NULL
+  DEFAULT '{authorization_code,refresh_token}';
+ALTER TABLE oauth_clients ADD COLUMN IF NOT EXISTS scopes TEXT[] NOT NULL DEFAULT '{}';
+-- token lifetimes in seconds, a NULL refresh_token_exp never ends the session
+ALTER TABLE oauth_clients ADD COLUMN IF NOT EXISTS access_token_exp INTEGER NOT NULL DEFAULT 3600;
+ALTER TABLE oauth_clients ADD COLUMN IF NOT EXISTS refresh_token_exp INTEGER;
+-- aud of the access tokens, the aud setting when NULL
+ALTER TABLE oauth_clients ADD COLUMN IF NOT EXISTS aud VARCHAR(255);
+
+-- a session of an OAuth client is refreshed by the client only
+ALTER TABLE refresh_tokens ADD COLUMN IF NOT EXISTS client_id VARCHAR(128)
+  REFERENCES oauth_clients(id) ON DELETE CASCADE;
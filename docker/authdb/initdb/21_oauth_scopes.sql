@@ -0,0 +1,2 @@
+-- scopes granted to the OAuth client of the session
+ALTER TABLE refresh_tokens ADD COLUMN IF NOT EXISTS scopes TEXT[] NOT NULL DEFAULT '{}';
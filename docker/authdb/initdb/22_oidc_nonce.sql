@@ -0,0 +1,2 @@
+-- OpenID Connect nonce of the authorization request, it is put in the ID token
+ALTER TABLE oauth_authorization_codes ADD COLUMN IF NOT EXISTS nonce TEXT;
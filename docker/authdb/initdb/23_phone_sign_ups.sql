@@ -0,0 +1,20 @@
+-- a one-time code sent to a phone number without a user,
+-- the user is created when the code is verified
+CREATE TABLE IF NOT EXISTS phone_sign_ups (
+  phone VARCHAR(32) NOT NULL PRIMARY KEY,
+  otp VARCHAR(128),
+  otp_sent_at TIMESTAMP WITH TIME ZONE,
+  otp_attempts INTEGER NOT NULL DEFAULT 0,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- the text messages sent, they limit the messages per phone number and per IP address
+CREATE TABLE IF NOT EXISTS sms_sends (
+  id BIGSERIAL PRIMARY KEY,
+  phone VARCHAR(32) NOT NULL,
+  ip VARCHAR(64),
+  sent_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS sms_sends_phone_idx ON sms_sends (phone, sent_at);
+CREATE INDEX IF NOT EXISTS sms_sends_ip_idx ON sms_sends (ip, sent_at);
@@ -1,9 +1,76 @@
 use config::Config;
 
-const ENV_PREFIX: &'static str = "EZA";
+const ENV_PREFIX: &str = "EZA";
 
 pub fn init_config() -> Config {
   let mut settings = Config::default();
+  settings.set_default("recover_token_exp", 3600).unwrap();
+  settings.set_default("magic_link_exp", 600).unwrap();
+  settings.set_default("email_otp_length", 6).unwrap();
+  settings.set_default("email_otp_exp", 300).unwrap();
+  settings.set_default("email_otp_max_attempts", 5).unwrap();
+  settings
+    .set_default("email_otp_resend_interval", 60)
+    .unwrap();
+  settings.set_default("phone_otp_length", 6).unwrap();
+  settings.set_default("phone_otp_exp", 300).unwrap();
+  settings.set_default("phone_otp_max_attempts", 5).unwrap();
+  settings
+    .set_default("phone_otp_resend_interval", 60)
+    .unwrap();
+  settings.set_default("sms_send_window", 3600).unwrap();
+  settings.set_default("sms_max_sends_per_phone", 5).unwrap();
+  settings.set_default("sms_max_sends_per_ip", 20).unwrap();
+  settings
+    .set_default("email_change_token_exp", 86400)
+    .unwrap();
+  settings.set_default("secure_email_change", true).unwrap();
+  settings
+    .set_default("confirmation_token_exp", 86400)
+    .unwrap();
+  settings
+    .set_default("confirmation_resend_interval", 60)
+    .unwrap();
+  settings
+    .set_default("account_deletion_grace_period", 2592000)
+    .unwrap();
+  settings
+    .set_default("purge_deleted_users_interval", 3600)
+    .unwrap();
+  settings
+    .set_default("reauthentication_window", 300)
+    .unwrap();
+  settings
+    .set_default("max_failed_sign_in_attempts", 5)
+    .unwrap();
+  settings.set_default("account_lock_duration", 900).unwrap();
+  settings.set_default("unlock_token_exp", 3600).unwrap();
+  settings.set_default("mfa_challenge_exp", 300).unwrap();
+  settings.set_default("mfa_totp_issuer", "ez-auth").unwrap();
+  settings.set_default("mfa_email_otp_length", 6).unwrap();
+  settings.set_default("mfa_email_otp_exp", 300).unwrap();
+  settings
+    .set_default("mfa_email_otp_max_attempts", 5)
+    .unwrap();
+  settings
+    .set_default("mfa_email_otp_resend_interval", 60)
+    .unwrap();
+  settings.set_default("trusted_device_exp", 2592000).unwrap();
+  settings.set_default("webauthn_rp_id", "localhost").unwrap();
+  settings.set_default("webauthn_rp_name", "ez-auth").unwrap();
+  settings
+    .set_default("webauthn_origin", "http://localhost:3000")
+    .unwrap();
+  settings.set_default("webauthn_challenge_exp", 300).unwrap();
+  settings
+    .set_default("oauth_login_url", "http://localhost:3000/login")
+    .unwrap();
+  settings
+    .set_default("oauth_authorization_code_exp", 300)
+    .unwrap();
+  settings
+    .set_default("oidc_issuer", "http://localhost:3000")
+    .unwrap();
   settings
     .merge(config::Environment::with_prefix(ENV_PREFIX))
     .unwrap();
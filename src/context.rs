@@ -4,26 +4,40 @@ use crate::usecase::token_usecase::decode_access_token;
 use actix_web::HttpRequest;
 use anyhow::{bail, Result};
 use config::Config;
-use juniper;
+use log::debug;
 use sqlx::PgPool;
+use std::net::SocketAddr;
 
 pub struct Context {
   auth_db_pool: PgPool,
   pub settings: Config,
   pub access_token: Option<String>,
+  // IP address of the client, X-Forwarded-For is trusted when it is set by a proxy
+  pub ip: Option<String>,
 }
 
 impl Context {
   pub fn new(auth_db_pool: PgPool, settings: Config, req: &HttpRequest) -> Context {
     let authorization = req.headers().get::<String>("Authorization".to_string());
     let authorization = authorization.and_then(|x| x.to_str().ok());
-    let access_token =
-      authorization.and_then(|bearer_string| extract_bearer_token(&bearer_string.to_string()));
+    let access_token = authorization.and_then(extract_bearer_token);
+    let ip = req.connection_info().realip_remote_addr().map(strip_port);
 
     Context {
       auth_db_pool,
       settings,
       access_token,
+      ip,
+    }
+  }
+
+  // context for the work which does not come from a request, e.g. background jobs
+  pub fn without_request(auth_db_pool: PgPool, settings: Config) -> Context {
+    Context {
+      auth_db_pool,
+      settings,
+      access_token: None,
+      ip: None,
     }
   }
 
@@ -38,10 +52,35 @@ impl Context {
     }
 
     let access_token = self.access_token.clone().unwrap();
-    let verified_claims = decode_access_token(&self.settings, &access_token);
-    println!("claims: {:?}", verified_claims);
+    let claims = decode_access_token(&self.settings, &access_token)?;
+    debug!("claims: {:?}", claims);
+
+    // a token issued to an OAuth client only grants its scopes, not the whole API
+    if !claims.is_first_party() {
+      bail!(FormatError::Unauthenticated(
+        "a token of a client cannot be used as a user".to_string()
+      ));
+    }
 
-    verified_claims
+    Ok(claims)
+  }
+
+  // same as get_current_user_claims, but the user must have signed in or
+  // reauthenticated within reauthentication_window. Use it for sensitive operations.
+  pub fn get_recently_authenticated_claims(&self) -> Result<Claims> {
+    let claims = self.get_current_user_claims()?;
+    let reauthentication_window = self
+      .settings
+      .get::<usize>("reauthentication_window")
+      .expect("reauthentication_window must set");
+
+    if !claims.is_authenticated_within(reauthentication_window) {
+      bail!(FormatError::ReauthenticationNeeded(
+        "this operation requires a recent sign in, call reauthenticate first".to_string()
+      ));
+    }
+
+    Ok(claims)
   }
 
   pub fn auth_db_pool_ref(&self) -> &PgPool {
@@ -51,7 +90,15 @@ impl Context {
 
 impl juniper::Context for Context {}
 
-fn extract_bearer_token(bearer_token: &String) -> Option<String> {
+// the peer address has a port, the forwarded address may not
+fn strip_port(addr: &str) -> String {
+  match addr.parse::<SocketAddr>() {
+    Ok(socket_addr) => socket_addr.ip().to_string(),
+    Err(_) => addr.to_string(),
+  }
+}
+
+fn extract_bearer_token(bearer_token: &str) -> Option<String> {
   if !bearer_token.starts_with("bearer ") {
     return None;
   }
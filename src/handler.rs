@@ -1,12 +1,30 @@
 use crate::context::Context;
+use crate::model::oauth::{
+  AuthorizeConsent, AuthorizeParams, AuthorizeResponse, ClientCredentials, OAuthError,
+  OAuthErrorResponse, TokenRequest,
+};
 use crate::presentation::auth::graphql::{create_schema, Schema};
+use crate::repository::sql::authorization_code_repo_sql::AuthorizationCodeRepoSql;
+use crate::repository::sql::oauth_client_repo_sql::OAuthClientRepoSql;
+use crate::repository::sql::refresh_token_repo_sql::RefreshTokenRepoSQL;
+use crate::repository::sql::user_repo_sql::UserRepoSql;
+use crate::usecase::oauth_usecase::{
+  approve_authorization, create_token_by_oauth, json_web_key_set, openid_configuration,
+  start_authorization,
+};
+use actix_web::http::{header, StatusCode};
 use actix_web::{web, Error, HttpRequest, HttpResponse};
 use config::Config;
 use juniper::http::{playground::playground_source, GraphQLRequest};
+use log::error;
 use sqlx::PgPool;
 
-const APP_GRAPHQL_ENDPOINT: &'static str = "/auth/graphql";
-const APP_PLAYGROUND_ENDPOINT: &'static str = "/auth/playground";
+const APP_GRAPHQL_ENDPOINT: &str = "/auth/graphql";
+const APP_PLAYGROUND_ENDPOINT: &str = "/auth/playground";
+const OAUTH_AUTHORIZE_ENDPOINT: &str = "/auth/authorize";
+const OAUTH_TOKEN_ENDPOINT: &str = "/auth/token";
+const OAUTH_JWKS_ENDPOINT: &str = "/auth/jwks";
+const OPENID_CONFIGURATION_ENDPOINT: &str = "/.well-known/openid-configuration";
 
 pub async fn graphql(
   pg_pool: web::Data<PgPool>,
@@ -23,7 +41,7 @@ pub async fn graphql(
 
   let f = || async move {
     let res = data.execute(&schema, &ctx).await;
-    Ok::<_, serde_json::error::Error>(serde_json::to_string(&res)?)
+    serde_json::to_string(&res)
   };
 
   let res = f().await.map_err(Error::from)?;
@@ -41,9 +59,168 @@ pub async fn graphql_playground() -> HttpResponse {
     .body(playground_source(APP_GRAPHQL_ENDPOINT, None))
 }
 
+// OAuth 2.0 authorization endpoint, it sends the user agent to the login page
+pub async fn oauth_authorize(
+  pg_pool: web::Data<PgPool>,
+  settings: web::Data<Config>,
+  req: HttpRequest,
+  params: web::Query<AuthorizeParams>,
+) -> HttpResponse {
+  let ctx = Context::new(
+    pg_pool.get_ref().to_owned(),
+    settings.get_ref().to_owned(),
+    &req,
+  );
+  let client_repo = OAuthClientRepoSql::new(&ctx);
+
+  match start_authorization(&ctx, &client_repo, &params).await {
+    Ok(location) => HttpResponse::Found()
+      .header(header::LOCATION, location)
+      .finish(),
+    Err(err) => oauth_error_response(err),
+  }
+}
+
+// the login page sends the consent of the user with the access token of the user,
+// it answers the redirect URI with the authorization code or the error
+pub async fn oauth_approve(
+  pg_pool: web::Data<PgPool>,
+  settings: web::Data<Config>,
+  req: HttpRequest,
+  params: web::Query<AuthorizeParams>,
+  consent: web::Json<AuthorizeConsent>,
+) -> HttpResponse {
+  let ctx = Context::new(
+    pg_pool.get_ref().to_owned(),
+    settings.get_ref().to_owned(),
+    &req,
+  );
+  let claims = match ctx.get_current_user_claims() {
+    Ok(claims) => claims,
+    Err(err) => return oauth_error_response(OAuthError::AccessDenied(format!("{:#}", err)).into()),
+  };
+  let client_repo = OAuthClientRepoSql::new(&ctx);
+  let code_repo = AuthorizationCodeRepoSql::new(&ctx);
+
+  match approve_authorization(&ctx, &client_repo, &code_repo, &claims, &params, &consent).await {
+    Ok(redirect_to) => HttpResponse::Ok().json(AuthorizeResponse { redirect_to }),
+    Err(err) => oauth_error_response(err),
+  }
+}
+
+// OAuth 2.0 token endpoint, the request is form encoded
+pub async fn oauth_token(
+  pg_pool: web::Data<PgPool>,
+  settings: web::Data<Config>,
+  req: HttpRequest,
+  token_request: web::Form<TokenRequest>,
+) -> HttpResponse {
+  let ctx = Context::new(
+    pg_pool.get_ref().to_owned(),
+    settings.get_ref().to_owned(),
+    &req,
+  );
+  let user_repo = UserRepoSql::new(&ctx);
+  let refresh_token_repo = RefreshTokenRepoSQL::new(&ctx);
+  let client_repo = OAuthClientRepoSql::new(&ctx);
+  let code_repo = AuthorizationCodeRepoSql::new(&ctx);
+  let basic_credentials = req
+    .headers()
+    .get(header::AUTHORIZATION)
+    .and_then(|authorization| authorization.to_str().ok())
+    .and_then(ClientCredentials::from_basic_auth);
+
+  match create_token_by_oauth(
+    &ctx,
+    user_repo,
+    refresh_token_repo,
+    &client_repo,
+    &code_repo,
+    basic_credentials,
+    &token_request,
+  )
+  .await
+  {
+    Ok(token_response) => HttpResponse::Ok()
+      .header(header::CACHE_CONTROL, "no-store")
+      .header(header::PRAGMA, "no-cache")
+      .json(token_response),
+    Err(err) => oauth_error_response(err),
+  }
+}
+
+pub async fn openid_configuration_document(
+  pg_pool: web::Data<PgPool>,
+  settings: web::Data<Config>,
+  req: HttpRequest,
+) -> HttpResponse {
+  let ctx = Context::new(
+    pg_pool.get_ref().to_owned(),
+    settings.get_ref().to_owned(),
+    &req,
+  );
+
+  HttpResponse::Ok().json(openid_configuration(&ctx))
+}
+
+pub async fn oauth_jwks(
+  pg_pool: web::Data<PgPool>,
+  settings: web::Data<Config>,
+  req: HttpRequest,
+) -> HttpResponse {
+  let ctx = Context::new(
+    pg_pool.get_ref().to_owned(),
+    settings.get_ref().to_owned(),
+    &req,
+  );
+
+  match json_web_key_set(&ctx) {
+    Ok(jwks) => HttpResponse::Ok().json(jwks),
+    Err(err) => oauth_error_response(err),
+  }
+}
+
+// OAuth errors are answered as RFC 6749 says, the other errors are server errors
+fn oauth_error_response(err: anyhow::Error) -> HttpResponse {
+  let server_error;
+  let oauth_error = match err.downcast_ref::<OAuthError>() {
+    Some(oauth_error) => oauth_error,
+    None => {
+      error!("oauth: {:#}", err);
+      server_error = OAuthError::ServerError("unexpected error".to_string());
+      &server_error
+    }
+  };
+
+  let status = match oauth_error {
+    OAuthError::InvalidClient(_) => StatusCode::UNAUTHORIZED,
+    OAuthError::AccessDenied(_) => StatusCode::FORBIDDEN,
+    OAuthError::ServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    _ => StatusCode::BAD_REQUEST,
+  };
+
+  let mut response = HttpResponse::build(status);
+  // RFC 6749 5.2: a client which failed to authenticate is told the scheme
+  if let OAuthError::InvalidClient(_) = oauth_error {
+    response.header(header::WWW_AUTHENTICATE, "Basic realm=\"ez-auth\"");
+  }
+
+  response
+    .header(header::CACHE_CONTROL, "no-store")
+    .json(OAuthErrorResponse::from(oauth_error))
+}
+
 pub fn register(config: &mut web::ServiceConfig) {
   config
     .data(create_schema())
     .route(APP_GRAPHQL_ENDPOINT, web::post().to(graphql))
-    .route(APP_PLAYGROUND_ENDPOINT, web::get().to(graphql_playground));
+    .route(APP_PLAYGROUND_ENDPOINT, web::get().to(graphql_playground))
+    .route(OAUTH_AUTHORIZE_ENDPOINT, web::get().to(oauth_authorize))
+    .route(OAUTH_AUTHORIZE_ENDPOINT, web::post().to(oauth_approve))
+    .route(OAUTH_TOKEN_ENDPOINT, web::post().to(oauth_token))
+    .route(OAUTH_JWKS_ENDPOINT, web::get().to(oauth_jwks))
+    .route(
+      OPENID_CONFIGURATION_ENDPOINT,
+      web::get().to(openid_configuration_document),
+    );
 }
@@ -0,0 +1,28 @@
+use crate::context::Context;
+use crate::repository::sql::user_repo_sql::UserRepoSql;
+use crate::usecase::user_usecase::purge_deleted_users;
+use actix_web::rt::time::interval;
+use config::Config;
+use log::{error, info};
+use sqlx::PgPool;
+use std::time::Duration;
+
+// delete for good the users whose account deletion grace period is over
+pub async fn purge_deleted_users_job(auth_db_pool: PgPool, settings: Config) {
+  let purge_interval = settings
+    .get::<u64>("purge_deleted_users_interval")
+    .expect("purge_deleted_users_interval must set");
+  let ctx = Context::without_request(auth_db_pool, settings);
+  let user_repo = UserRepoSql::new(&ctx);
+  let mut ticker = interval(Duration::from_secs(purge_interval));
+
+  loop {
+    ticker.tick().await;
+
+    match purge_deleted_users(&ctx, &user_repo).await {
+      Ok(0) => {}
+      Ok(count) => info!("purged {} deleted users", count),
+      Err(err) => error!("purge deleted users failed: {:#}", err),
+    }
+  }
+}
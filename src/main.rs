@@ -1,4 +1,4 @@
-use actix_web::{get, middleware, App, HttpResponse, HttpServer, Responder};
+use actix_web::{get, middleware, rt, App, HttpResponse, HttpServer, Responder};
 use dotenv::dotenv;
 use sqlx::PgPool;
 use std::io::Result;
@@ -6,6 +6,7 @@ use std::io::Result;
 mod config;
 mod context;
 mod handler;
+mod job;
 mod model;
 mod presentation;
 mod repository;
@@ -30,6 +31,11 @@ async fn main() -> Result<()> {
         .await
         .expect("Connect to authdb must success");
 
+    rt::spawn(job::purge_deleted_users_job(
+        auth_db_pool.clone(),
+        settings.clone(),
+    ));
+
     HttpServer::new(move || {
         App::new()
             .wrap(middleware::Logger::default())
@@ -1,5 +1,10 @@
 pub mod crypto;
 pub mod error;
 pub mod id;
+pub mod identity;
+pub mod mfa;
+pub mod oauth;
+pub mod oidc;
 pub mod token;
 pub mod user;
+pub mod webauthn;
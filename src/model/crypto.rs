@@ -1,4 +1,9 @@
+use super::error::FormatError;
+use anyhow::{bail, Result};
 use nanoid::nanoid;
+use rand::Rng;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::digest::{digest, SHA256};
 
 // const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
 //                         abcdefghijklmnopqrstuvwxyz\
@@ -19,3 +24,104 @@ use nanoid::nanoid;
 pub fn create_unique_token() -> String {
   nanoid!()
 }
+
+// hash a token before saving it, so a leaked database does not leak usable tokens
+pub fn hash_token(token: &str) -> String {
+  hex::encode(digest(&SHA256, token.as_bytes()))
+}
+
+// create a numeric one-time code, e.g. "042917"
+pub fn create_numeric_code(len: usize) -> String {
+  let mut rng = rand::thread_rng();
+
+  (0..len)
+    .map(|_| char::from(b'0' + rng.gen_range(0..10)))
+    .collect()
+}
+
+pub fn create_random_bytes(len: usize) -> Vec<u8> {
+  let mut rng = rand::thread_rng();
+
+  (0..len).map(|_| rng.gen::<u8>()).collect()
+}
+
+// encode bytes in base32 (RFC 4648) without padding, as authenticator apps expect
+pub fn base32_encode(bytes: &[u8]) -> String {
+  const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+  let mut encoded = String::new();
+  let mut buffer = 0u16;
+  let mut bits = 0;
+  for byte in bytes {
+    buffer = (buffer << 8) | *byte as u16;
+    bits += 8;
+    while bits >= 5 {
+      bits -= 5;
+      encoded.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+    }
+  }
+  if bits > 0 {
+    encoded.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+  }
+
+  encoded
+}
+
+// encrypt a secret with AES-256-GCM, the key is 32 bytes in hex.
+// It returns the nonce and the cipher text in hex.
+pub fn encrypt_secret(key: &str, secret: &[u8]) -> Result<String> {
+  let key = aead_key(key)?;
+  let nonce_bytes = create_random_bytes(NONCE_LEN);
+  let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes)
+    .map_err(|_| FormatError::ServerError("invalid nonce".to_string()))?;
+
+  let mut in_out = secret.to_vec();
+  key
+    .seal_in_place_append_tag(nonce, Aad::empty(), &mut in_out)
+    .map_err(|_| FormatError::ServerError("cannot encrypt the secret".to_string()))?;
+
+  Ok(hex::encode([nonce_bytes, in_out].concat()))
+}
+
+pub fn decrypt_secret(key: &str, encrypted_secret: &str) -> Result<Vec<u8>> {
+  let key = aead_key(key)?;
+  let encrypted_secret = hex::decode(encrypted_secret)
+    .map_err(|_| FormatError::ServerError("invalid encrypted secret".to_string()))?;
+  if encrypted_secret.len() < NONCE_LEN {
+    bail!(FormatError::ServerError(
+      "invalid encrypted secret".to_string()
+    ));
+  }
+
+  let (nonce_bytes, cipher_text) = encrypted_secret.split_at(NONCE_LEN);
+  let nonce = Nonce::try_assume_unique_for_key(nonce_bytes)
+    .map_err(|_| FormatError::ServerError("invalid nonce".to_string()))?;
+  let mut in_out = cipher_text.to_vec();
+  let secret = key
+    .open_in_place(nonce, Aad::empty(), &mut in_out)
+    .map_err(|_| FormatError::ServerError("cannot decrypt the secret".to_string()))?;
+
+  Ok(secret.to_vec())
+}
+
+fn aead_key(key: &str) -> Result<LessSafeKey> {
+  let key = hex::decode(key)
+    .map_err(|_| FormatError::ServerError("encryption key must be in hex".to_string()))?;
+  let key = UnboundKey::new(&AES_256_GCM, &key)
+    .map_err(|_| FormatError::ServerError("encryption key must have 32 bytes".to_string()))?;
+
+  Ok(LessSafeKey::new(key))
+}
+
+// create a recovery code which is easy to type, e.g. "k3f9a-2mx7q"
+pub fn create_recovery_code() -> String {
+  const CHARSET: &[u8] = b"abcdefghijkmnpqrstuvwxyz23456789";
+  let mut rng = rand::thread_rng();
+  let mut part = || -> String {
+    (0..5)
+      .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
+      .collect()
+  };
+
+  format!("{}-{}", part(), part())
+}
@@ -1,13 +1,13 @@
 use actix_web::ResponseError;
 use anyhow;
-use juniper::{graphql_value, FieldError};
+use juniper::{FieldError, Object, Value};
+use std::fmt;
 use thiserror::Error;
 
 pub trait SpecificError {
   fn get_code(&self) -> &str;
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Error)]
 pub enum FormatError {
   #[error("server error: {0}")]
@@ -24,6 +24,14 @@ pub enum FormatError {
   Unauthenticated(String),
   #[error("duplicate value found: {0}")]
   DuplicateError(String),
+  #[error("token expired: {0}")]
+  TokenExpired(String),
+  #[error("too many requests: {0}")]
+  TooManyRequests(String),
+  #[error("reauthentication needed: {0}")]
+  ReauthenticationNeeded(String),
+  #[error("account locked: {0}")]
+  AccountLocked(String),
 }
 
 impl SpecificError for FormatError {
@@ -36,12 +44,35 @@ impl SpecificError for FormatError {
       FormatError::Unauthenticated(_) => "UNAUTHENTICATED",
       FormatError::BadRequest(_) => "BAD_REQUEST",
       FormatError::DuplicateError(_) => "DUPLICATE_ERROR",
+      FormatError::TokenExpired(_) => "TOKEN_EXPIRED",
+      FormatError::TooManyRequests(_) => "TOO_MANY_REQUESTS",
+      FormatError::ReauthenticationNeeded(_) => "REAUTHENTICATION_NEEDED",
+      FormatError::AccountLocked(_) => "ACCOUNT_LOCKED",
     }
   }
 }
 
 impl ResponseError for FormatError {}
 
+// ErrorHint gives the client more information about an error.
+// Attach it to an error as a context, it is returned in the error extensions.
+// e.g. Err(anyhow!(FormatError::Unauthenticated(msg)).context(ErrorHint::RemainingAttempts(2)))
+#[derive(Debug)]
+pub enum ErrorHint {
+  RemainingAttempts(i32),
+  // seconds to wait before retrying
+  RetryAfter(i64),
+}
+
+impl fmt::Display for ErrorHint {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ErrorHint::RemainingAttempts(attempts) => write!(f, "{} attempts remaining", attempts),
+      ErrorHint::RetryAfter(seconds) => write!(f, "retry after {} seconds", seconds),
+    }
+  }
+}
+
 impl SpecificError for anyhow::Error {
   fn get_code(&self) -> &str {
     match self.downcast_ref::<FormatError>() {
@@ -52,6 +83,19 @@ impl SpecificError for anyhow::Error {
 }
 
 pub fn to_juniper_field_error(err: anyhow::Error) -> FieldError {
-  let code = err.get_code();
-  FieldError::new(format!("{:#}", err), graphql_value!({ "code": code }))
+  let mut extensions = Object::with_capacity(2);
+  extensions.add_field("code", Value::scalar(err.get_code().to_string()));
+
+  if let Some(hint) = err.downcast_ref::<ErrorHint>() {
+    match hint {
+      ErrorHint::RemainingAttempts(attempts) => {
+        extensions.add_field("remainingAttempts", Value::scalar(*attempts))
+      }
+      ErrorHint::RetryAfter(seconds) => {
+        extensions.add_field("retryAfter", Value::scalar(*seconds as i32))
+      }
+    };
+  }
+
+  FieldError::new(format!("{:#}", err), Value::Object(extensions))
 }
@@ -0,0 +1,57 @@
+use super::crypto::{create_unique_token, hash_token};
+use super::id::Id;
+use chrono::{DateTime, Duration, Utc};
+use juniper::GraphQLObject;
+
+// provider of the identities which are email addresses
+pub const EMAIL_PROVIDER: &str = "email";
+
+// An identity links a user to a way of signing in, e.g. a secondary email address.
+// The primary email address of a user stays in users.email.
+#[derive(Debug, Clone, GraphQLObject)]
+#[graphql(description = "An identity linked to a user, e.g. a secondary email address")]
+pub struct Identity {
+  pub id: Id,
+  #[graphql(skip)]
+  pub user_id: Id,
+  pub provider: String,
+  pub provider_id: String,
+  pub email: Option<String>,
+  pub verified_at: Option<DateTime<Utc>>,
+  pub last_used_at: Option<DateTime<Utc>>,
+  #[graphql(skip)]
+  pub confirmation_token: Option<String>,
+  #[graphql(skip)]
+  pub confirmation_sent_at: Option<DateTime<Utc>>,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+impl Identity {
+  // create an unverified email identity, it returns the raw confirmation token
+  pub fn new_email(user_id: &Id, email: String) -> (Identity, String) {
+    let token = create_unique_token();
+    let identity = Identity {
+      id: Id::create_uuid_v4(),
+      user_id: user_id.clone(),
+      provider: EMAIL_PROVIDER.to_string(),
+      provider_id: email.to_lowercase(),
+      email: Some(email),
+      verified_at: None,
+      last_used_at: None,
+      confirmation_token: Some(hash_token(&token)),
+      confirmation_sent_at: Some(Utc::now()),
+      created_at: Utc::now(),
+      updated_at: Utc::now(),
+    };
+
+    (identity, token)
+  }
+
+  pub fn is_confirmation_token_expired(&self, expires_in: i64) -> bool {
+    match self.confirmation_sent_at {
+      Some(sent_at) => sent_at + Duration::seconds(expires_in) < Utc::now(),
+      None => true,
+    }
+  }
+}
@@ -0,0 +1,326 @@
+use super::crypto::{
+  base32_encode, create_random_bytes, create_recovery_code, create_unique_token, decrypt_secret,
+  encrypt_secret, hash_token,
+};
+use super::id::Id;
+use super::user::OneTimeCode;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use juniper::{GraphQLEnum, GraphQLObject};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::form_urlencoded::byte_serialize;
+
+const TOTP_SECRET_LEN: usize = 20;
+const TOTP_DIGITS: u32 = 6;
+const TOTP_PERIOD: u64 = 30;
+// accept the codes of the previous and the next period, for clock drift
+const TOTP_SKEW: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, GraphQLEnum)]
+pub enum FactorType {
+  Totp,
+  Webauthn,
+  // a one-time code sent to the email address of the user
+  Email,
+}
+
+impl FactorType {
+  pub fn name(&self) -> &'static str {
+    match self {
+      FactorType::Totp => "totp",
+      FactorType::Webauthn => "webauthn",
+      FactorType::Email => "email",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<FactorType> {
+    match name {
+      "totp" => Some(FactorType::Totp),
+      "webauthn" => Some(FactorType::Webauthn),
+      "email" => Some(FactorType::Email),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, GraphQLEnum)]
+pub enum FactorStatus {
+  Unverified,
+  Verified,
+}
+
+impl FactorStatus {
+  pub fn name(&self) -> &'static str {
+    match self {
+      FactorStatus::Unverified => "unverified",
+      FactorStatus::Verified => "verified",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<FactorStatus> {
+    match name {
+      "unverified" => Some(FactorStatus::Unverified),
+      "verified" => Some(FactorStatus::Verified),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, GraphQLObject)]
+#[graphql(description = "A second factor of a user")]
+pub struct MfaFactor {
+  pub id: Id,
+  #[graphql(skip)]
+  pub user_id: Id,
+  pub factor_type: FactorType,
+  pub friendly_name: Option<String>,
+  pub status: FactorStatus,
+  // encrypted by encrypt_secret, empty for a webauthn or email factor
+  #[graphql(skip)]
+  pub secret: String,
+  // the last accepted TOTP time step, a code cannot be used twice
+  #[graphql(skip)]
+  pub last_used_step: i64,
+  // the last code sent to an email factor
+  #[graphql(skip)]
+  pub otp: OneTimeCode,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+impl MfaFactor {
+  // create an unverified TOTP factor, it returns the raw secret in base32
+  pub fn new_totp(
+    user_id: &Id,
+    friendly_name: Option<String>,
+    encryption_key: &str,
+  ) -> Result<(MfaFactor, String)> {
+    let secret = create_random_bytes(TOTP_SECRET_LEN);
+    let factor = MfaFactor {
+      id: Id::create_uuid_v4(),
+      user_id: user_id.clone(),
+      factor_type: FactorType::Totp,
+      friendly_name,
+      status: FactorStatus::Unverified,
+      secret: encrypt_secret(encryption_key, &secret)?,
+      last_used_step: 0,
+      otp: OneTimeCode::default(),
+      created_at: Utc::now(),
+      updated_at: Utc::now(),
+    };
+
+    Ok((factor, base32_encode(&secret)))
+  }
+
+  // a webauthn factor is verified by its registration ceremony,
+  // the public key is kept in its webauthn credential
+  pub fn new_webauthn(user_id: &Id, friendly_name: Option<String>) -> MfaFactor {
+    MfaFactor {
+      id: Id::create_uuid_v4(),
+      user_id: user_id.clone(),
+      factor_type: FactorType::Webauthn,
+      friendly_name,
+      status: FactorStatus::Verified,
+      secret: String::new(),
+      last_used_step: 0,
+      otp: OneTimeCode::default(),
+      created_at: Utc::now(),
+      updated_at: Utc::now(),
+    }
+  }
+
+  // an email factor is verified by the first code sent to the email address
+  pub fn new_email(user_id: &Id, friendly_name: Option<String>) -> MfaFactor {
+    MfaFactor {
+      id: Id::create_uuid_v4(),
+      user_id: user_id.clone(),
+      factor_type: FactorType::Email,
+      friendly_name,
+      status: FactorStatus::Unverified,
+      secret: String::new(),
+      last_used_step: 0,
+      otp: OneTimeCode::default(),
+      created_at: Utc::now(),
+      updated_at: Utc::now(),
+    }
+  }
+
+  pub fn is_verified(&self) -> bool {
+    self.status == FactorStatus::Verified
+  }
+
+  // check a TOTP code, it returns the matched time step
+  pub fn verify_totp(&self, encryption_key: &str, code: &str) -> Result<Option<i64>> {
+    let secret = decrypt_secret(encryption_key, &self.secret)?;
+    let current_step = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap()
+      .as_secs()
+      / TOTP_PERIOD;
+
+    let matched_step = (current_step.saturating_sub(TOTP_SKEW)..=current_step + TOTP_SKEW)
+      .filter(|step| *step as i64 > self.last_used_step)
+      .find(|step| totp_code(&secret, *step) == code);
+
+    Ok(matched_step.map(|step| step as i64))
+  }
+}
+
+// RFC 6238 code of the time step, with HMAC-SHA1 as authenticator apps expect
+fn totp_code(secret: &[u8], step: u64) -> String {
+  let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+  let tag = hmac::sign(&key, &step.to_be_bytes());
+  let hash = tag.as_ref();
+
+  let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+  let binary = u32::from_be_bytes([
+    hash[offset] & 0x7f,
+    hash[offset + 1],
+    hash[offset + 2],
+    hash[offset + 3],
+  ]);
+
+  format!(
+    "{:0width$}",
+    binary % 10u32.pow(TOTP_DIGITS),
+    width = TOTP_DIGITS as usize
+  )
+}
+
+// the URI for authenticator apps, usually shown as a QR code
+pub fn totp_uri(issuer: &str, account_name: &str, secret: &str) -> String {
+  let issuer: String = byte_serialize(issuer.as_bytes()).collect();
+  let account_name: String = byte_serialize(account_name.as_bytes()).collect();
+
+  format!(
+    "otpauth://totp/{issuer}:{account_name}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+    issuer = issuer,
+    account_name = account_name,
+    secret = secret,
+    digits = TOTP_DIGITS,
+    period = TOTP_PERIOD
+  )
+}
+
+#[derive(Debug, GraphQLObject)]
+#[graphql(description = "Enroll TOTP result, verify the factor with a code to finish")]
+pub struct EnrollTotpOutput {
+  pub factor: MfaFactor,
+  #[graphql(description = "Secret in base32, for entering it manually")]
+  pub secret: String,
+  #[graphql(description = "otpauth URI, usually shown as a QR code")]
+  pub uri: String,
+  #[graphql(
+    description = "One-time recovery codes, only returned with the first factor of the user"
+  )]
+  pub recovery_codes: Option<Vec<String>>,
+}
+
+// a sign in which waits for a second factor
+#[derive(Debug, Clone)]
+pub struct MfaChallenge {
+  pub id: Id,
+  pub user_id: Id,
+  // hashed challenge token
+  pub token: String,
+  // the methods of the first factor, the second factor is added to them
+  pub amr: Vec<String>,
+  pub expires_at: DateTime<Utc>,
+}
+
+impl MfaChallenge {
+  // it returns the raw challenge token
+  pub fn new(user_id: &Id, amr: Vec<String>, expires_in: i64) -> (MfaChallenge, String) {
+    let token = create_unique_token();
+    let challenge = MfaChallenge {
+      id: Id::create_uuid_v4(),
+      user_id: user_id.clone(),
+      token: hash_token(&token),
+      amr,
+      expires_at: Utc::now() + Duration::seconds(expires_in),
+    };
+
+    (challenge, token)
+  }
+
+  pub fn is_expired(&self) -> bool {
+    self.expires_at < Utc::now()
+  }
+}
+
+#[derive(Debug, GraphQLObject)]
+#[graphql(
+  description = "The sign in needs a second factor, complete it with the MFA_CHALLENGE grant type"
+)]
+pub struct MfaRequired {
+  pub challenge_token: String,
+  #[graphql(description = "Verified factors of the user, a recovery code can be used as well")]
+  pub available_factors: Vec<MfaFactor>,
+}
+
+// a device which skips the second factor, the client keeps a signed device token of it
+#[derive(Debug, Clone, GraphQLObject)]
+#[graphql(description = "A device which does not need the second factor to sign in")]
+pub struct TrustedDevice {
+  pub id: Id,
+  #[graphql(skip)]
+  pub user_id: Id,
+  pub name: Option<String>,
+  pub expires_at: DateTime<Utc>,
+  pub last_used_at: Option<DateTime<Utc>>,
+  pub created_at: DateTime<Utc>,
+}
+
+impl TrustedDevice {
+  pub fn new(user_id: &Id, name: Option<String>, expires_in: i64) -> TrustedDevice {
+    TrustedDevice {
+      id: Id::create_uuid_v4(),
+      user_id: user_id.clone(),
+      name,
+      expires_at: Utc::now() + Duration::seconds(expires_in),
+      last_used_at: None,
+      created_at: Utc::now(),
+    }
+  }
+
+  pub fn is_expired(&self) -> bool {
+    self.expires_at < Utc::now()
+  }
+}
+
+// claims of the device token, a jwt with its own audience
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceClaims {
+  // the user id
+  pub sub: Id,
+  // the trusted device id
+  pub jti: Id,
+  pub aud: String,
+  pub exp: usize,
+}
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+// create a new set of recovery codes, it returns the raw codes and their hashes
+pub fn new_recovery_codes() -> (Vec<String>, Vec<String>) {
+  let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+    .map(|_| create_recovery_code())
+    .collect();
+  let hashes = codes.iter().map(|code| hash_recovery_code(code)).collect();
+
+  (codes, hashes)
+}
+
+// recovery codes are typed by hand, so they are matched case-insensitively
+pub fn hash_recovery_code(code: &str) -> String {
+  hash_token(&code.trim().to_lowercase())
+}
+
+#[derive(Debug, GraphQLObject)]
+#[graphql(description = "Recovery codes of a user, they replace a lost second factor")]
+pub struct RecoveryCodesStatus {
+  pub remaining: i32,
+  pub last_used_at: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,432 @@
+use super::crypto::{create_random_bytes, create_unique_token, hash_token};
+use super::error::FormatError;
+use super::id::Id;
+use super::webauthn::base64url_encode;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use juniper::{GraphQLEnum, GraphQLInputObject, GraphQLObject};
+use percent_encoding::percent_decode_str;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use url::Url;
+
+pub const CODE_RESPONSE_TYPE: &str = "code";
+pub const S256_CODE_CHALLENGE_METHOD: &str = "S256";
+
+// RFC 7636: a code verifier has 43 to 128 unreserved characters
+const CODE_VERIFIER_MIN_LEN: usize = 43;
+const CODE_VERIFIER_MAX_LEN: usize = 128;
+const CLIENT_SECRET_LEN: usize = 32;
+const DEFAULT_ACCESS_TOKEN_EXP: i32 = 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq, GraphQLEnum)]
+pub enum OAuthGrantType {
+  AuthorizationCode,
+  RefreshToken,
+  // a token of the client itself, for machine-to-machine calls
+  ClientCredentials,
+}
+
+impl OAuthGrantType {
+  pub fn name(&self) -> &'static str {
+    match self {
+      OAuthGrantType::AuthorizationCode => "authorization_code",
+      OAuthGrantType::RefreshToken => "refresh_token",
+      OAuthGrantType::ClientCredentials => "client_credentials",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<OAuthGrantType> {
+    match name {
+      "authorization_code" => Some(OAuthGrantType::AuthorizationCode),
+      "refresh_token" => Some(OAuthGrantType::RefreshToken),
+      "client_credentials" => Some(OAuthGrantType::ClientCredentials),
+      _ => None,
+    }
+  }
+}
+
+// errors of RFC 6749, the code is returned in the error field of the response
+#[derive(Debug, Error)]
+pub enum OAuthError {
+  #[error("{0}")]
+  InvalidRequest(String),
+  #[error("{0}")]
+  InvalidClient(String),
+  #[error("{0}")]
+  InvalidGrant(String),
+  #[error("{0}")]
+  UnsupportedGrantType(String),
+  #[error("{0}")]
+  UnsupportedResponseType(String),
+  #[error("{0}")]
+  AccessDenied(String),
+  #[error("{0}")]
+  UnauthorizedClient(String),
+  #[error("{0}")]
+  InvalidScope(String),
+  #[error("{0}")]
+  ServerError(String),
+}
+
+impl OAuthError {
+  pub fn code(&self) -> &'static str {
+    match self {
+      OAuthError::InvalidRequest(_) => "invalid_request",
+      OAuthError::InvalidClient(_) => "invalid_client",
+      OAuthError::InvalidGrant(_) => "invalid_grant",
+      OAuthError::UnsupportedGrantType(_) => "unsupported_grant_type",
+      OAuthError::UnsupportedResponseType(_) => "unsupported_response_type",
+      OAuthError::AccessDenied(_) => "access_denied",
+      OAuthError::UnauthorizedClient(_) => "unauthorized_client",
+      OAuthError::InvalidScope(_) => "invalid_scope",
+      OAuthError::ServerError(_) => "server_error",
+    }
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct OAuthErrorResponse {
+  pub error: String,
+  pub error_description: String,
+}
+
+impl From<&OAuthError> for OAuthErrorResponse {
+  fn from(err: &OAuthError) -> Self {
+    OAuthErrorResponse {
+      error: err.code().to_string(),
+      error_description: err.to_string(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, GraphQLObject)]
+#[graphql(description = "An application which signs in users with OAuth 2.0")]
+pub struct OAuthClient {
+  pub id: Id,
+  // hashed client secret, None for a public client
+  #[graphql(skip)]
+  pub secret: Option<String>,
+  pub name: String,
+  pub redirect_uris: Vec<String>,
+  pub grant_types: Vec<OAuthGrantType>,
+  #[graphql(description = "Scopes the client can request, any scope when it is empty")]
+  pub scopes: Vec<String>,
+  #[graphql(description = "Lifetime of the access tokens in seconds")]
+  pub access_token_exp: i32,
+  #[graphql(
+    description = "Seconds a session can be refreshed after the sign in, no limit when it is not set"
+  )]
+  pub refresh_token_exp: Option<i32>,
+  #[graphql(description = "Audience of the access tokens, the client id when it is not set")]
+  pub aud: Option<String>,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+impl OAuthClient {
+  // a confidential client gets a secret, it returns the raw secret
+  pub fn new(input: CreateOAuthClientInput) -> Result<(OAuthClient, Option<String>)> {
+    let mut client = OAuthClient {
+      id: Id::create_uuid_v4(),
+      secret: None,
+      name: input.name,
+      redirect_uris: input.redirect_uris,
+      grant_types: input.grant_types.unwrap_or_else(|| {
+        vec![
+          OAuthGrantType::AuthorizationCode,
+          OAuthGrantType::RefreshToken,
+        ]
+      }),
+      scopes: input.scopes.unwrap_or_default(),
+      access_token_exp: input.access_token_exp.unwrap_or(DEFAULT_ACCESS_TOKEN_EXP),
+      refresh_token_exp: input.refresh_token_exp,
+      aud: input.aud,
+      created_at: Utc::now(),
+      updated_at: Utc::now(),
+    };
+    client.validate()?;
+
+    let secret = if input.is_public.unwrap_or(false) {
+      None
+    } else {
+      Some(client.renew_secret())
+    };
+
+    Ok((client, secret))
+  }
+
+  // the aud of the access tokens of the client. It never falls back to the aud of ez-auth,
+  // a token of a client must not be accepted as a token of ez-auth itself.
+  pub fn audience(&self) -> String {
+    self.aud.clone().unwrap_or_else(|| self.id.to_string())
+  }
+
+  // it returns the raw secret, only its hash is kept
+  pub fn renew_secret(&mut self) -> String {
+    let secret = hex::encode(create_random_bytes(CLIENT_SECRET_LEN));
+    self.secret = Some(hash_token(&secret));
+
+    secret
+  }
+
+  pub fn update(&mut self, input: UpdateOAuthClientInput) -> Result<()> {
+    if let Some(name) = input.name {
+      self.name = name;
+    }
+    if let Some(redirect_uris) = input.redirect_uris {
+      self.redirect_uris = redirect_uris;
+    }
+    if let Some(grant_types) = input.grant_types {
+      self.grant_types = grant_types;
+    }
+    if let Some(scopes) = input.scopes {
+      self.scopes = scopes;
+    }
+    if let Some(access_token_exp) = input.access_token_exp {
+      self.access_token_exp = access_token_exp;
+    }
+    if input.refresh_token_exp.is_some() {
+      self.refresh_token_exp = input.refresh_token_exp;
+    }
+    if input.aud.is_some() {
+      self.aud = input.aud;
+    }
+
+    self.validate()
+  }
+
+  fn validate(&self) -> Result<()> {
+    if self.name.trim().is_empty() {
+      bail!(FormatError::ValidationFailed(
+        "client name is required".to_string()
+      ));
+    }
+
+    // RFC 6749: a redirect URI is absolute and has no fragment
+    let is_valid_uri =
+      |uri: &String| matches!(Url::parse(uri), Ok(url) if url.fragment().is_none());
+    if !self.redirect_uris.iter().all(is_valid_uri) {
+      bail!(FormatError::ValidationFailed(
+        "redirect URIs must be absolute URIs without fragment".to_string()
+      ));
+    }
+
+    if self.access_token_exp <= 0 || self.refresh_token_exp.is_some_and(|exp| exp <= 0) {
+      bail!(FormatError::ValidationFailed(
+        "token lifetimes must be positive".to_string()
+      ));
+    }
+
+    Ok(())
+  }
+
+  // redirect URIs are compared exactly, as RFC 6749 recommends
+  pub fn is_redirect_uri_allowed(&self, redirect_uri: &str) -> bool {
+    self.redirect_uris.iter().any(|uri| uri == redirect_uri)
+  }
+
+  pub fn is_public(&self) -> bool {
+    self.secret.is_none()
+  }
+
+  pub fn is_secret_matched(&self, secret: &str) -> bool {
+    self.secret == Some(hash_token(secret))
+  }
+
+  pub fn is_grant_type_allowed(&self, grant_type: OAuthGrantType) -> bool {
+    self.grant_types.contains(&grant_type)
+  }
+
+  // every scope of the space-delimited list must be allowed
+  pub fn is_scope_allowed(&self, scope: &str) -> bool {
+    self.scopes.is_empty()
+      || scope
+        .split_whitespace()
+        .all(|requested| self.scopes.iter().any(|allowed| allowed == requested))
+  }
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(description = "Register an OAuth client")]
+pub struct CreateOAuthClientInput {
+  pub name: String,
+  pub redirect_uris: Vec<String>,
+  #[graphql(description = "AUTHORIZATION_CODE and REFRESH_TOKEN when it is not set")]
+  pub grant_types: Option<Vec<OAuthGrantType>>,
+  pub scopes: Option<Vec<String>>,
+  pub access_token_exp: Option<i32>,
+  pub refresh_token_exp: Option<i32>,
+  pub aud: Option<String>,
+  #[graphql(description = "A public client has no secret, e.g. a mobile or single-page app")]
+  pub is_public: Option<bool>,
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(description = "Update an OAuth client, the fields which are not set are kept")]
+pub struct UpdateOAuthClientInput {
+  pub id: Id,
+  pub name: Option<String>,
+  pub redirect_uris: Option<Vec<String>>,
+  pub grant_types: Option<Vec<OAuthGrantType>>,
+  pub scopes: Option<Vec<String>>,
+  pub access_token_exp: Option<i32>,
+  pub refresh_token_exp: Option<i32>,
+  pub aud: Option<String>,
+}
+
+#[derive(Debug, GraphQLObject)]
+#[graphql(description = "The registered client, the secret is only returned once")]
+pub struct CreateOAuthClientOutput {
+  pub client: OAuthClient,
+  pub client_secret: Option<String>,
+}
+
+// query of the authorization endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorizeParams {
+  pub response_type: String,
+  pub client_id: Id,
+  pub redirect_uri: String,
+  pub scope: Option<String>,
+  pub state: Option<String>,
+  pub code_challenge: Option<String>,
+  pub code_challenge_method: Option<String>,
+  // OpenID Connect, returned in the ID token
+  pub nonce: Option<String>,
+}
+
+// form of the token endpoint
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+  pub grant_type: String,
+  pub client_id: Option<Id>,
+  // client_secret_post authentication, a client can use the basic scheme instead
+  pub client_secret: Option<String>,
+  pub scope: Option<String>,
+  pub code: Option<String>,
+  pub redirect_uri: Option<String>,
+  pub code_verifier: Option<String>,
+  pub refresh_token: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+  pub access_token: String,
+  pub token_type: String,
+  pub expires_in: i32,
+  // no refresh token for the client_credentials grant
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub refresh_token: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub scope: Option<String>,
+  // OpenID Connect ID token, when the openid scope is granted
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub id_token: Option<String>,
+}
+
+// how the client authenticates at the token endpoint
+#[derive(Debug)]
+pub struct ClientCredentials {
+  pub client_id: Id,
+  pub client_secret: Option<String>,
+}
+
+impl ClientCredentials {
+  // RFC 6749 2.3.1: "Basic " + base64(urlencode(client_id) ":" urlencode(client_secret))
+  pub fn from_basic_auth(authorization: &str) -> Option<ClientCredentials> {
+    let encoded = authorization.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(base64::decode(encoded.trim()).ok()?).ok()?;
+    let (client_id, client_secret) = decoded.split_once(':')?;
+    let form_decode = |value: &str| {
+      percent_decode_str(&value.replace('+', " "))
+        .decode_utf8()
+        .ok()
+        .map(|value| value.to_string())
+    };
+
+    Some(ClientCredentials {
+      client_id: Id::new(form_decode(client_id)?),
+      client_secret: Some(form_decode(client_secret)?),
+    })
+  }
+}
+
+// a space-delimited scope parameter
+pub fn parse_scope(scope: &str) -> Vec<String> {
+  scope.split_whitespace().map(str::to_string).collect()
+}
+
+// decision of the user on the consent screen of the login page
+#[derive(Debug, Deserialize)]
+pub struct AuthorizeConsent {
+  pub approved: bool,
+}
+
+// answer of an approved authorization, the user agent goes to redirect_to
+#[derive(Debug, Serialize)]
+pub struct AuthorizeResponse {
+  pub redirect_to: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthorizationCode {
+  pub id: Id,
+  // hashed authorization code
+  pub code: String,
+  pub client_id: Id,
+  pub user_id: Id,
+  pub redirect_uri: String,
+  pub scope: String,
+  pub code_challenge: String,
+  pub nonce: Option<String>,
+  // the session which approved the authorization, the tokens continue it
+  pub amr: Vec<String>,
+  pub authenticated_at: DateTime<Utc>,
+  pub expires_at: DateTime<Utc>,
+}
+
+impl AuthorizationCode {
+  // it returns the raw authorization code
+  pub fn new(
+    params: &AuthorizeParams,
+    user_id: &Id,
+    code_challenge: String,
+    amr: Vec<String>,
+    authenticated_at: DateTime<Utc>,
+    expires_in: i64,
+  ) -> (AuthorizationCode, String) {
+    let code = create_unique_token();
+    let authorization_code = AuthorizationCode {
+      id: Id::create_uuid_v4(),
+      code: hash_token(&code),
+      client_id: params.client_id.clone(),
+      user_id: user_id.clone(),
+      redirect_uri: params.redirect_uri.clone(),
+      scope: params.scope.clone().unwrap_or_default(),
+      code_challenge,
+      nonce: params.nonce.clone(),
+      amr,
+      authenticated_at,
+      expires_at: Utc::now() + Duration::seconds(expires_in),
+    };
+
+    (authorization_code, code)
+  }
+
+  pub fn is_expired(&self) -> bool {
+    self.expires_at < Utc::now()
+  }
+
+  // PKCE S256: BASE64URL(SHA256(code_verifier)) must equal the code challenge
+  pub fn is_code_verifier_matched(&self, code_verifier: &str) -> bool {
+    let is_valid = (CODE_VERIFIER_MIN_LEN..=CODE_VERIFIER_MAX_LEN).contains(&code_verifier.len())
+      && code_verifier
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c));
+
+    is_valid
+      && base64url_encode(digest(&SHA256, code_verifier.as_bytes()).as_ref()) == self.code_challenge
+  }
+}
@@ -0,0 +1,143 @@
+use super::error::FormatError;
+use super::id::Id;
+use super::webauthn::base64url_encode;
+use anyhow::{bail, Result};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+
+pub const OPENID_SCOPE: &str = "openid";
+pub const EMAIL_SCOPE: &str = "email";
+
+const DER_SEQUENCE: u8 = 0x30;
+const DER_INTEGER: u8 = 0x02;
+const DER_BIT_STRING: u8 = 0x03;
+
+// claims of an OpenID Connect ID token, the aud is the client
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+  pub iss: String,
+  pub sub: Id,
+  pub aud: String,
+  pub exp: usize,
+  pub iat: usize,
+  pub auth_time: usize,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub nonce: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub email: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub email_verified: Option<bool>,
+  pub amr: Vec<String>,
+  pub at_hash: String,
+}
+
+// OIDC Core 3.1.3.6: base64url of the left half of the SHA-256 hash of the access token
+pub fn at_hash(access_token: &str) -> String {
+  let hash = digest(&SHA256, access_token.as_bytes());
+  let hash = hash.as_ref();
+
+  base64url_encode(&hash[..hash.len() / 2])
+}
+
+// the discovery document, served at /.well-known/openid-configuration
+#[derive(Debug, Serialize)]
+pub struct OpenIdConfiguration {
+  pub issuer: String,
+  pub authorization_endpoint: String,
+  pub token_endpoint: String,
+  pub jwks_uri: String,
+  pub response_types_supported: Vec<&'static str>,
+  pub grant_types_supported: Vec<&'static str>,
+  pub subject_types_supported: Vec<&'static str>,
+  pub id_token_signing_alg_values_supported: Vec<&'static str>,
+  pub scopes_supported: Vec<&'static str>,
+  pub token_endpoint_auth_methods_supported: Vec<&'static str>,
+  pub code_challenge_methods_supported: Vec<&'static str>,
+  pub claims_supported: Vec<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Jwks {
+  pub keys: Vec<Jwk>,
+}
+
+// the RSA public key which verifies the tokens, as RFC 7517 describes it
+#[derive(Debug, Serialize)]
+pub struct Jwk {
+  pub kty: &'static str,
+  #[serde(rename = "use")]
+  pub key_use: &'static str,
+  pub alg: &'static str,
+  pub kid: String,
+  pub n: String,
+  pub e: String,
+}
+
+impl Jwk {
+  // read the modulus and the exponent of a "BEGIN PUBLIC KEY" PEM (RSA SubjectPublicKeyInfo)
+  pub fn from_rsa_pem(public_key: &str) -> Result<Jwk> {
+    let base64_der: String = public_key
+      .lines()
+      .filter(|line| !line.starts_with("-----"))
+      .collect();
+    let der = base64::decode(base64_der.trim())
+      .map_err(|_| FormatError::ServerError("public_key is not a valid PEM".to_string()))?;
+
+    // SEQUENCE { SEQUENCE { algorithm }, BIT STRING { SEQUENCE { INTEGER n, INTEGER e } } }
+    let (spki, _) = der_read(&der, DER_SEQUENCE)?;
+    let (_, rest) = der_read(spki, DER_SEQUENCE)?;
+    let (bit_string, _) = der_read(rest, DER_BIT_STRING)?;
+    let (rsa_key, _) = der_read(bit_string.get(1..).unwrap_or_default(), DER_SEQUENCE)?;
+    let (modulus, rest) = der_read(rsa_key, DER_INTEGER)?;
+    let (exponent, _) = der_read(rest, DER_INTEGER)?;
+
+    Ok(Jwk {
+      kty: "RSA",
+      key_use: "sig",
+      alg: "RS256",
+      kid: key_id(&der),
+      n: base64url_encode(strip_leading_zeros(modulus)),
+      e: base64url_encode(strip_leading_zeros(exponent)),
+    })
+  }
+}
+
+// the key id is the hash of the public key, it changes with the key
+pub fn key_id(public_key_der: &[u8]) -> String {
+  base64url_encode(&digest(&SHA256, public_key_der).as_ref()[..8])
+}
+
+// read a DER element of the tag, it returns the content and the bytes after the element
+fn der_read(input: &[u8], tag: u8) -> Result<(&[u8], &[u8])> {
+  let invalid = || FormatError::ServerError("public_key is not an RSA public key".to_string());
+
+  if input.first() != Some(&tag) {
+    bail!(invalid());
+  }
+  let first_len_byte = *input.get(1).ok_or_else(invalid)? as usize;
+  let (len, header_len) = if first_len_byte < 0x80 {
+    (first_len_byte, 2)
+  } else {
+    let len_bytes = first_len_byte & 0x7f;
+    let len = input
+      .get(2..2 + len_bytes)
+      .ok_or_else(invalid)?
+      .iter()
+      .fold(0usize, |len, byte| (len << 8) | *byte as usize);
+    (len, 2 + len_bytes)
+  };
+
+  let content = input
+    .get(header_len..header_len + len)
+    .ok_or_else(invalid)?;
+  Ok((content, &input[header_len + len..]))
+}
+
+// a DER integer has a leading zero when its first bit is set, a JWK does not
+fn strip_leading_zeros(bytes: &[u8]) -> &[u8] {
+  let start = bytes
+    .iter()
+    .position(|byte| *byte != 0)
+    .unwrap_or(bytes.len());
+  &bytes[start..]
+}
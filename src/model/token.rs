@@ -1,7 +1,9 @@
 use super::crypto::create_unique_token;
 use super::id::Id;
+use super::mfa::MfaRequired;
+use super::webauthn::PasskeyAssertionInput;
 use chrono::{DateTime, Utc};
-use juniper::{GraphQLEnum, GraphQLInputObject, GraphQLObject};
+use juniper::{GraphQLEnum, GraphQLInputObject, GraphQLObject, GraphQLUnion};
 use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -10,19 +12,34 @@ pub struct RefreshToken {
   pub token: String,
   pub user_id: Id,
   pub revoked: bool,
-  pub created_at: DateTime<Utc>,
-  pub updated_at: DateTime<Utc>,
+  // when and how the user proved the identity in this session, kept by the swapped tokens
+  pub authenticated_at: DateTime<Utc>,
+  pub aal: String,
+  pub amr: Vec<String>,
+  // the OAuth client of the session, None for a session of ez-auth itself
+  pub client_id: Option<Id>,
+  // scopes granted to the client
+  pub scopes: Vec<String>,
 }
 
 impl RefreshToken {
-  pub fn new(user_id: &Id) -> Self {
+  pub fn new(
+    user_id: &Id,
+    authenticated_at: DateTime<Utc>,
+    amr: Vec<String>,
+    client_id: Option<Id>,
+    scopes: Vec<String>,
+  ) -> Self {
     RefreshToken {
       id: Id::create_uuid_v4(),
       token: create_unique_token(),
       user_id: user_id.clone(),
       revoked: false,
-      created_at: Utc::now(),
-      updated_at: Utc::now(),
+      authenticated_at,
+      aal: assurance_level(&amr).to_string(),
+      amr,
+      client_id,
+      scopes,
     }
   }
 
@@ -33,6 +50,110 @@ impl RefreshToken {
 
 pub const ACCESS_TOKEN_EXP: usize = 3600;
 
+// authentication assurance levels, aal2 means the user has proved a second factor
+pub const AAL1: &str = "aal1";
+pub const AAL2: &str = "aal2";
+
+// authentication methods, they are listed in the amr claim
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthMethod {
+  Password,
+  Otp,
+  Sms,
+  MagicLink,
+  // a token sent by email, e.g. sign up confirmation or password recovery
+  EmailLink,
+  Anonymous,
+  Totp,
+  Recovery,
+  Webauthn,
+  // a one-time code of an email factor
+  OtpEmail,
+  // a device trusted after an MFA challenge
+  TrustedDevice,
+}
+
+impl AuthMethod {
+  pub fn name(&self) -> &'static str {
+    match self {
+      AuthMethod::Password => "password",
+      AuthMethod::Otp => "otp",
+      AuthMethod::Sms => "sms",
+      AuthMethod::MagicLink => "magic_link",
+      AuthMethod::EmailLink => "email_link",
+      AuthMethod::Anonymous => "anonymous",
+      AuthMethod::Totp => "totp",
+      AuthMethod::Recovery => "recovery",
+      AuthMethod::Webauthn => "webauthn",
+      AuthMethod::OtpEmail => "otp_email",
+      AuthMethod::TrustedDevice => "trusted_device",
+    }
+  }
+}
+
+// a passkey sign in requires user verification, so it is multi-factor by itself.
+// A trusted device was trusted after a second factor.
+const SECOND_FACTOR_METHODS: [AuthMethod; 5] = [
+  AuthMethod::Totp,
+  AuthMethod::Recovery,
+  AuthMethod::Webauthn,
+  AuthMethod::OtpEmail,
+  AuthMethod::TrustedDevice,
+];
+
+pub fn assurance_level(amr: &[String]) -> &'static str {
+  let has_second_factor = SECOND_FACTOR_METHODS
+    .iter()
+    .any(|method| amr.iter().any(|name| name == method.name()));
+
+  if has_second_factor {
+    AAL2
+  } else {
+    AAL1
+  }
+}
+
+// how the session of new tokens has been authenticated
+pub enum SessionAuth {
+  // the user has just proved the identity with these methods
+  Authenticated(Vec<String>),
+  // continue the session of the refresh token, it is swapped for a new one
+  Refreshed(RefreshToken),
+  // the user has just proved the identity again in the session of the refresh token,
+  // it is swapped for a new one with these methods
+  Reauthenticated {
+    refresh_token: RefreshToken,
+    amr: Vec<String>,
+  },
+  // continue a session authenticated at that time in a new refresh token,
+  // e.g. the session which approved an OAuth authorization with these scopes
+  Continued {
+    authenticated_at: DateTime<Utc>,
+    amr: Vec<String>,
+    scopes: Vec<String>,
+  },
+}
+
+impl SessionAuth {
+  pub fn by(method: AuthMethod) -> Self {
+    SessionAuth::Authenticated(vec![method.name().to_string()])
+  }
+
+  pub fn extend(amr: &[String], method: AuthMethod) -> Self {
+    SessionAuth::Authenticated(extend_amr(amr, method))
+  }
+}
+
+// the methods of a session with one more method, e.g. a second factor
+pub fn extend_amr(amr: &[String], method: AuthMethod) -> Vec<String> {
+  let mut amr = amr.to_vec();
+  if !amr.iter().any(|name| name == method.name()) {
+    amr.push(method.name().to_string());
+  }
+
+  amr
+}
+
 // pub enum PermissionScope {
 //   AdminUserRead,
 //   AdminUserWrite,
@@ -56,16 +177,59 @@ pub const ACCESS_TOKEN_EXP: usize = 3600;
 //   PermissionScope::UserWrite,
 // ];
 
+// the subject of an access token, a client gets tokens by the client_credentials grant
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubjectType {
+  #[default]
+  User,
+  Client,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
+  // the user, or the client when subject_type is client.
+  // Tokens issued before it was named sub are still accepted.
+  #[serde(rename = "sub", alias = "user_id")]
   pub user_id: Id,
+  #[serde(default)]
+  pub subject_type: SubjectType,
   pub aud: String,
   pub exp: usize,
   pub iat: usize,
+  #[serde(default)]
+  pub is_anonymous: bool,
+  // unix time when the user last proved the identity (sign in or reauthenticate)
+  #[serde(default)]
+  pub auth_time: usize,
+  #[serde(default = "default_aal")]
+  pub aal: String,
+  #[serde(default)]
+  pub amr: Vec<String>,
+  // scopes granted to the OAuth client, empty for the tokens of ez-auth itself
+  #[serde(default)]
+  pub scopes: Vec<String>,
+  // the OAuth client the token was issued to, None for the tokens of ez-auth itself
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub client_id: Option<Id>,
+}
+
+fn default_aal() -> String {
+  AAL1.to_string()
 }
 
 impl Claims {
-  pub fn new(user_id: Id, aud: String, exp: usize) -> Self {
+  #[allow(clippy::too_many_arguments)]
+  pub fn new(
+    user_id: Id,
+    aud: String,
+    exp: usize,
+    is_anonymous: bool,
+    auth_time: usize,
+    amr: Vec<String>,
+    scopes: Vec<String>,
+    client_id: Option<Id>,
+  ) -> Self {
     let iat = SystemTime::now()
       .duration_since(UNIX_EPOCH)
       .unwrap()
@@ -73,26 +237,103 @@ impl Claims {
 
     Claims {
       user_id,
+      subject_type: SubjectType::User,
+      aud,
+      exp,
+      iat,
+      is_anonymous,
+      auth_time,
+      aal: assurance_level(&amr).to_string(),
+      amr,
+      scopes,
+      client_id,
+    }
+  }
+
+  // claims of a token of the client itself, no user is involved
+  pub fn new_client(client_id: Id, aud: String, exp: usize, scopes: Vec<String>) -> Self {
+    let iat = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap()
+      .as_secs() as usize;
+
+    Claims {
+      user_id: client_id.clone(),
+      subject_type: SubjectType::Client,
       aud,
       exp,
       iat,
+      is_anonymous: false,
+      auth_time: iat,
+      aal: AAL1.to_string(),
+      amr: vec![],
+      scopes,
+      client_id: Some(client_id),
     }
   }
+
+  // a token of a session of ez-auth itself, not one issued to an OAuth client
+  pub fn is_first_party(&self) -> bool {
+    self.subject_type == SubjectType::User && self.client_id.is_none() && self.scopes.is_empty()
+  }
+
+  pub fn is_authenticated_within(&self, seconds: usize) -> bool {
+    let now = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap()
+      .as_secs() as usize;
+
+    self.auth_time + seconds >= now
+  }
 }
 
 #[derive(Debug, GraphQLEnum)]
 pub enum CreateTokenGrantType {
   Password,
   RefreshToken,
+  MagicLink,
+  EmailOtp,
+  PhoneOtp,
+  // complete a sign in which returned MfaRequired, with challenge_token and a second factor
+  MfaChallenge,
+  // sign in with a passkey, without password
+  Passkey,
 }
 
 #[derive(GraphQLInputObject, Debug)]
 #[graphql(description = "Create token (sign in) input")]
 pub struct CreateTokenInput {
   pub grant_type: CreateTokenGrantType,
+  #[graphql(description = "Email address or username, for the PASSWORD grant type")]
+  pub identifier: Option<String>,
   pub email: Option<String>,
+  pub phone: Option<String>,
   pub password: Option<String>,
   pub refresh_token: Option<String>,
+  pub token: Option<String>,
+  pub code: Option<String>,
+  #[graphql(description = "Challenge token of MfaRequired, for the MFA_CHALLENGE grant type")]
+  pub challenge_token: Option<String>,
+  #[graphql(
+    description = "Factor for the MFA_CHALLENGE grant type, without it the code is a recovery code"
+  )]
+  pub factor_id: Option<Id>,
+  #[graphql(description = "Assertion of passkeyAuthenticationOptions, for a webauthn factor")]
+  pub passkey: Option<PasskeyAssertionInput>,
+  #[graphql(
+    description = "Skip the second factor on this device later, for the MFA_CHALLENGE grant type"
+  )]
+  pub trust_device: Option<bool>,
+  pub device_name: Option<String>,
+  #[graphql(description = "Token of a trusted device, it skips the second factor of a sign in")]
+  pub device_token: Option<String>,
+}
+
+#[derive(Debug, GraphQLUnion)]
+#[graphql(description = "Create token (sign in) result, or the second factor it needs")]
+pub enum CreateTokenResult {
+  Token(CreateTokenOutput),
+  MfaRequired(MfaRequired),
 }
 
 #[derive(Debug, GraphQLObject)]
@@ -102,9 +343,64 @@ pub struct CreateTokenOutput {
   pub token_type: String,
   pub expires_in: i32,
   pub refresh_token: String,
+  #[graphql(description = "Device token, when the MFA challenge was completed with trustDevice")]
+  pub device_token: Option<String>,
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(
+  description = "Reauthenticate input, either password or a verified second factor is required"
+)]
+pub struct ReauthenticateInput {
+  #[graphql(description = "Refresh token of the current session, it is swapped for a new one")]
+  pub refresh_token: String,
+  pub password: Option<String>,
+  #[graphql(description = "Verified factor, without it the code is a recovery code")]
+  pub factor_id: Option<Id>,
+  #[graphql(description = "Code of a TOTP or email factor, or a recovery code")]
+  pub code: Option<String>,
+  #[graphql(description = "Assertion of passkeyAuthenticationOptions, for a webauthn factor")]
+  pub passkey: Option<PasskeyAssertionInput>,
+}
+
+// a second factor proved by the user
+pub struct SecondFactorInput {
+  // None for a recovery code
+  pub factor_id: Option<Id>,
+  // the code of a TOTP or email factor, or a recovery code
+  pub code: Option<String>,
+  // the assertion of a webauthn factor
+  pub passkey: Option<PasskeyAssertionInput>,
+}
+
+pub struct CreateTokenByMfaChallengeInput {
+  pub challenge_token: String,
+  pub second_factor: SecondFactorInput,
+  pub trust_device: bool,
+  pub device_name: Option<String>,
 }
 
 pub struct CreateTokenByPasswordInput {
-  pub email: String,
+  // email address or username
+  pub identifier: String,
   pub password: String,
+  // skips the second factor when the device is trusted
+  pub device_token: Option<String>,
+}
+
+pub struct CreateTokenByMagicLinkInput {
+  pub token: String,
+  pub device_token: Option<String>,
+}
+
+pub struct CreateTokenByEmailOtpInput {
+  pub email: String,
+  pub code: String,
+  pub device_token: Option<String>,
+}
+
+pub struct CreateTokenByPhoneOtpInput {
+  pub phone: String,
+  pub code: String,
+  pub device_token: Option<String>,
 }
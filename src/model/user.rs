@@ -1,13 +1,12 @@
-use super::crypto::create_unique_token;
+use super::crypto::{create_numeric_code, create_unique_token, hash_token};
 use super::id::Id;
 use crate::model::error::FormatError;
 use anyhow::{bail, Result};
 use bcrypt::{hash, verify, DEFAULT_COST};
-use bson::{bson, doc, Bson};
-use chrono::{DateTime, Utc};
+use bson::{bson, Bson};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use juniper::{
-  graphql_object, graphql_scalar, GraphQLEnum, GraphQLInputObject, GraphQLObject,
-  ParseScalarResult, ParseScalarValue, Value,
+  graphql_scalar, GraphQLEnum, GraphQLInputObject, ParseScalarResult, ParseScalarValue, Value,
 };
 use std::fmt;
 
@@ -58,11 +57,83 @@ where
   }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum OtpChannel {
+  Email,
+  Phone,
+}
+
+impl OtpChannel {
+  // used as the prefix of the settings and the columns, e.g. email_otp_length
+  pub fn name(&self) -> &str {
+    match self {
+      OtpChannel::Email => "email_otp",
+      OtpChannel::Phone => "phone_otp",
+    }
+  }
+}
+
+// A numeric one-time code sent to the user. Only the hash of the code is kept.
+#[derive(Debug, Clone, Default)]
+pub struct OneTimeCode {
+  pub code: Option<String>,
+  pub sent_at: Option<DateTime<Utc>>,
+  pub attempts: i32,
+}
+
+impl OneTimeCode {
+  // create a new code, it replaces the previous one and resets the attempts
+  pub fn renew(&mut self, len: usize) -> String {
+    let code = create_numeric_code(len);
+    self.code = Some(hash_token(&code));
+    self.sent_at = Some(Utc::now());
+    self.attempts = 0;
+
+    code
+  }
+
+  pub fn is_expired(&self, expires_in: i64) -> bool {
+    is_expired(self.sent_at, expires_in)
+  }
+
+  pub fn is_matched(&self, code: &str) -> bool {
+    self.code == Some(hash_token(code))
+  }
+
+  // seconds to wait before a new code can be sent, 0 when it can be sent now
+  pub fn resend_wait(&self, resend_interval: i64) -> i64 {
+    match self.sent_at {
+      Some(sent_at) => (sent_at + Duration::seconds(resend_interval) - Utc::now())
+        .num_seconds()
+        .max(0),
+      None => 0,
+    }
+  }
+}
+
+// a phone number which has been sent a sign up code, the user does not exist yet
+#[derive(Debug, Clone)]
+pub struct PhoneSignUp {
+  pub phone: String,
+  pub otp: OneTimeCode,
+}
+
 #[derive(Debug, Clone)]
 pub struct User {
   pub id: Id,
   pub aud: String,
-  pub email: String,
+  pub email: Option<String>,
+  pub username: Option<String>,
+  pub phone: Option<String>,
+  pub phone_confirmed_at: Option<DateTime<Utc>>,
+  pub is_anonymous: bool,
+  pub deleted_at: Option<DateTime<Utc>>,
+  pub failed_sign_in_attempts: i32,
+  pub locked_until: Option<DateTime<Utc>>,
+  pub unlock_token: Option<String>,
+  pub unlock_sent_at: Option<DateTime<Utc>>,
+  pub banned_until: Option<DateTime<Utc>>,
+  pub ban_reason: Option<String>,
   pub role: String,
   pub encrypted_password: String,
   pub user_meta_data: UserMetaData,
@@ -72,7 +143,16 @@ pub struct User {
   pub confirmed_at: Option<DateTime<Utc>>,
   pub confirmation_token: Option<String>,
   pub confirmation_sent_at: Option<DateTime<Utc>>,
-  pub last_sign_in_at: Option<DateTime<Utc>>,
+  pub recover_token: Option<String>,
+  pub recover_sent_at: Option<DateTime<Utc>>,
+  pub magic_link_token: Option<String>,
+  pub magic_link_sent_at: Option<DateTime<Utc>>,
+  pub email_otp: OneTimeCode,
+  pub phone_otp: OneTimeCode,
+  pub email_change: Option<String>,
+  pub email_change_token_new: Option<String>,
+  pub email_change_token_current: Option<String>,
+  pub email_change_sent_at: Option<DateTime<Utc>>,
 }
 
 impl User {
@@ -83,13 +163,79 @@ impl User {
     user_meta_data: UserMetaData,
   ) -> Result<User> {
     let encrypted_password = hash_password(&password)?;
+
+    Ok(User::new_with_encrypted_password(
+      aud,
+      Some(email),
+      encrypted_password,
+      user_meta_data,
+    ))
+  }
+
+  // create a user who signs up with a phone number. The user has no email address
+  // and no password, the user signs in with one-time codes sent to the phone.
+  pub fn new_with_phone(aud: String, phone: String) -> User {
+    let user_meta_data = UserMetaData::new(bson!({}));
+    let mut user = User::new_with_encrypted_password(aud, None, String::new(), user_meta_data);
+    user.phone = Some(phone);
+    user.confirmation_token = None;
+    user.confirmation_sent_at = None;
+
+    user
+  }
+
+  // create a guest user, it can be upgraded to a full account later
+  // by linking an email address and a password
+  pub fn new_anonymous(aud: String) -> User {
+    let user_meta_data = UserMetaData::new(bson!({}));
+    let mut user = User::new_with_encrypted_password(aud, None, String::new(), user_meta_data);
+    user.is_anonymous = true;
+    user.confirmation_token = None;
+    user.confirmation_sent_at = None;
+
+    user
+  }
+
+  // create a user invited by an admin. The user has no usable password until
+  // the user accepts the invitation.
+  pub fn new_invited(
+    aud: String,
+    email: String,
+    role: String,
+    user_meta_data: UserMetaData,
+  ) -> User {
+    let mut user =
+      User::new_with_encrypted_password(aud, Some(email), String::new(), user_meta_data);
+    user.role = role;
+    user.invited_at = Some(Utc::now());
+
+    user
+  }
+
+  fn new_with_encrypted_password(
+    aud: String,
+    email: Option<String>,
+    encrypted_password: String,
+    user_meta_data: UserMetaData,
+  ) -> User {
     let id = Id::create_uuid_v4();
     let confirmation_token = create_unique_token();
 
-    let user = User {
+    User {
       id,
       aud,
       email,
+      username: None,
+      phone: None,
+      phone_confirmed_at: None,
+      is_anonymous: false,
+      deleted_at: None,
+      failed_sign_in_attempts: 0,
+      locked_until: None,
+      unlock_token: None,
+      unlock_sent_at: None,
+      banned_until: None,
+      ban_reason: None,
       encrypted_password,
       role: DefaultUserRole::GeneralUser.to_string(),
       user_meta_data,
@@ -99,10 +245,17 @@ impl User {
       confirmed_at: None,
       confirmation_token: Some(confirmation_token),
       confirmation_sent_at: Some(Utc::now()),
-      last_sign_in_at: None,
-    };
-
-    Ok(user)
+      recover_token: None,
+      recover_sent_at: None,
+      magic_link_token: None,
+      magic_link_sent_at: None,
+      email_otp: OneTimeCode::default(),
+      phone_otp: OneTimeCode::default(),
+      email_change: None,
+      email_change_token_new: None,
+      email_change_token_current: None,
+      email_change_sent_at: None,
+    }
   }
 
   pub fn raw_user_meta_data(&self) -> String {
@@ -115,7 +268,7 @@ impl User {
   }
 
   pub fn check_password(&self, password: &String) -> Result<()> {
-    if verify(password, self.encrypted_password.as_str())? {
+    if self.has_password() && verify(password, self.encrypted_password.as_str())? {
       return Ok(());
     }
 
@@ -127,39 +280,193 @@ impl User {
   pub fn is_confirmed(&self) -> bool {
     self.confirmed_at.is_some()
   }
-}
 
-fn hash_password(password: &String) -> Result<String> {
-  match hash(password, DEFAULT_COST) {
-    Ok(password) => Ok(password),
-    Err(err) => bail!(FormatError::ServerError(err.to_string())),
+  // invited users have no password until they accept the invitation
+  pub fn has_password(&self) -> bool {
+    !self.encrypted_password.is_empty()
   }
-}
 
-#[derive(Debug, GraphQLObject)]
-pub struct Users {
-  pub items: Vec<User>,
-}
+  pub fn is_invited(&self) -> bool {
+    self.invited_at.is_some()
+  }
 
-#[graphql_object]
-impl User {
-  fn id(&self) -> &Id {
-    &self.id
+  pub fn is_admin(&self) -> bool {
+    self.role == DefaultUserRole::SuperAdmin.to_string()
   }
-  fn email(&self) -> &String {
-    &self.email
+
+  // create a new recover token. Only the hash of the token is kept in the user,
+  // the returned raw token is sent to the user's email address.
+  pub fn create_recover_token(&mut self) -> String {
+    let token = create_unique_token();
+    self.recover_token = Some(hash_token(&token));
+    self.recover_sent_at = Some(Utc::now());
+
+    token
   }
-  fn role(&self) -> &String {
-    &self.role
+
+  pub fn renew_confirmation_token(&mut self) -> String {
+    let token = create_unique_token();
+    self.confirmation_token = Some(token.clone());
+    self.confirmation_sent_at = Some(Utc::now());
+
+    token
   }
-  fn user_meta_data(&self) -> &UserMetaData {
-    &self.user_meta_data
+
+  pub fn is_confirmation_token_expired(&self, expires_in: i64) -> bool {
+    is_expired(self.confirmation_sent_at, expires_in)
   }
-  fn created_at(&self) -> &DateTime<Utc> {
-    &self.created_at
+
+  // seconds to wait before a new confirmation can be sent, 0 when it can be sent now
+  pub fn confirmation_resend_wait(&self, resend_interval: i64) -> i64 {
+    match self.confirmation_sent_at {
+      Some(sent_at) => (sent_at + Duration::seconds(resend_interval) - Utc::now())
+        .num_seconds()
+        .max(0),
+      None => 0,
+    }
+  }
+
+  pub fn is_recover_token_expired(&self, expires_in: i64) -> bool {
+    is_expired(self.recover_sent_at, expires_in)
+  }
+
+  // same as the recover token, only the hash of the magic link token is kept
+  pub fn create_magic_link_token(&mut self) -> String {
+    let token = create_unique_token();
+    self.magic_link_token = Some(hash_token(&token));
+    self.magic_link_sent_at = Some(Utc::now());
+
+    token
+  }
+
+  pub fn is_magic_link_token_expired(&self, expires_in: i64) -> bool {
+    is_expired(self.magic_link_sent_at, expires_in)
+  }
+
+  pub fn otp(&self, channel: OtpChannel) -> &OneTimeCode {
+    match channel {
+      OtpChannel::Email => &self.email_otp,
+      OtpChannel::Phone => &self.phone_otp,
+    }
+  }
+
+  pub fn otp_mut(&mut self, channel: OtpChannel) -> &mut OneTimeCode {
+    match channel {
+      OtpChannel::Email => &mut self.email_otp,
+      OtpChannel::Phone => &mut self.phone_otp,
+    }
   }
-  fn updated_at(&self) -> &DateTime<Utc> {
-    &self.updated_at
+
+  pub fn is_deleted(&self) -> bool {
+    self.deleted_at.is_some()
+  }
+
+  // a deleted user can be restored until the grace period is over
+  pub fn is_restorable(&self, grace_period: i64) -> bool {
+    self.is_deleted() && !is_expired(self.deleted_at, grace_period)
+  }
+
+  pub fn is_locked(&self) -> bool {
+    matches!(self.locked_until, Some(locked_until) if locked_until > Utc::now())
+  }
+
+  // seconds until the account is unlocked
+  pub fn locked_retry_after(&self) -> i64 {
+    match self.locked_until {
+      Some(locked_until) => (locked_until - Utc::now()).num_seconds().max(0),
+      None => 0,
+    }
+  }
+
+  // lock the account for lock_duration seconds, it returns the raw unlock token
+  pub fn lock(&mut self, lock_duration: i64) -> String {
+    let token = create_unique_token();
+    self.failed_sign_in_attempts = 0;
+    self.locked_until = Some(Utc::now() + Duration::seconds(lock_duration));
+    self.unlock_token = Some(hash_token(&token));
+    self.unlock_sent_at = Some(Utc::now());
+
+    token
+  }
+
+  pub fn is_unlock_token_expired(&self, expires_in: i64) -> bool {
+    is_expired(self.unlock_sent_at, expires_in)
+  }
+
+  pub fn is_banned(&self) -> bool {
+    matches!(self.banned_until, Some(banned_until) if banned_until > Utc::now())
+  }
+
+  // ban the user for duration seconds, or permanently when duration is None
+  pub fn ban(&mut self, duration: Option<i64>, reason: Option<String>) -> &mut Self {
+    self.banned_until = Some(match duration {
+      Some(duration) => Utc::now() + Duration::seconds(duration),
+      None => Utc.ymd(9999, 12, 31).and_hms(23, 59, 59),
+    });
+    self.ban_reason = reason;
+    self
+  }
+
+  // check whether the user is allowed to get new tokens
+  pub fn check_can_sign_in(&self) -> Result<()> {
+    if self.is_deleted() {
+      bail!(FormatError::Unauthenticated(
+        "this account has been deleted".to_string()
+      ));
+    }
+
+    if self.is_banned() {
+      bail!(FormatError::Forbidden(match &self.ban_reason {
+        Some(reason) => format!("this account has been banned: {}", reason),
+        None => "this account has been banned".to_string(),
+      }));
+    }
+
+    Ok(())
+  }
+
+  pub fn is_phone_confirmed(&self) -> bool {
+    self.phone_confirmed_at.is_some()
+  }
+
+  // start changing the email address to new_email. It returns the raw tokens
+  // for the new address and, when secure is true, for the current address.
+  pub fn create_email_change(
+    &mut self,
+    new_email: String,
+    secure: bool,
+  ) -> (String, Option<String>) {
+    let token_new = create_unique_token();
+    let token_current = if secure {
+      Some(create_unique_token())
+    } else {
+      None
+    };
+
+    self.email_change = Some(new_email);
+    self.email_change_token_new = Some(hash_token(&token_new));
+    self.email_change_token_current = token_current.as_deref().map(hash_token);
+    self.email_change_sent_at = Some(Utc::now());
+
+    (token_new, token_current)
+  }
+
+  pub fn is_email_change_token_expired(&self, expires_in: i64) -> bool {
+    is_expired(self.email_change_sent_at, expires_in)
+  }
+}
+
+fn is_expired(sent_at: Option<DateTime<Utc>>, expires_in: i64) -> bool {
+  match sent_at {
+    Some(sent_at) => sent_at + Duration::seconds(expires_in) < Utc::now(),
+    None => true,
+  }
+}
+
+fn hash_password(password: &String) -> Result<String> {
+  match hash(password, DEFAULT_COST) {
+    Ok(password) => Ok(password),
+    Err(err) => bail!(FormatError::ServerError(err.to_string())),
   }
 }
 
@@ -168,6 +475,52 @@ impl User {
 pub struct CreateUserInput {
   pub email: String,
   pub password: String,
+  pub username: Option<String>,
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(description = "Ban user input")]
+pub struct BanUserInput {
+  pub id: Id,
+  #[graphql(description = "Ban duration in seconds, the ban is permanent when it is not set")]
+  pub duration: Option<i32>,
+  pub reason: Option<String>,
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(description = "Invite user input")]
+pub struct InviteUserInput {
+  pub email: String,
+  pub role: Option<String>,
+  pub data: Option<UserMetaData>,
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(description = "Update email input")]
+pub struct UpdateEmailInput {
+  pub new_email: String,
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(description = "Change the password of the current user")]
+pub struct ChangePasswordInput {
+  pub current_password: String,
+  pub new_password: String,
+  #[graphql(description = "Refresh token of the session to keep signed in")]
+  pub refresh_token: Option<String>,
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(description = "Link an email address and a password to an anonymous user")]
+pub struct LinkEmailPasswordInput {
+  pub email: String,
+  pub password: String,
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(description = "Restore a deleted account input")]
+pub struct RestoreAccountInput {
+  pub email: String,
 }
 
 #[derive(GraphQLInputObject, Debug)]
@@ -181,6 +534,12 @@ pub struct FindAUserInput {
 pub enum VerifyUserType {
   SignUp,
   Recover,
+  Invite,
+  EmailChange,
+  Restore,
+  Unlock,
+  // confirm an email address added by addEmail
+  Identity,
 }
 
 #[derive(Debug, GraphQLInputObject)]
@@ -198,13 +557,126 @@ pub struct UpdateUserInput {
 #[derive(Debug, Clone)]
 pub enum FindOneUserCondition {
   Id(Id),
+  // match the primary email address or a verified email identity
   Email(String),
   ConfirmationToken(String),
+  RecoverToken(String),
+  // match the token sent to either the new or the current email address
+  EmailChangeToken(String),
+  Phone(String),
+  UnlockToken(String),
+  // usernames are matched case-insensitively
+  Username(String),
 }
 
 #[derive(Debug, Clone)]
 pub enum UpdateOneUserField {
   ConfirmationToken(Option<String>),
   ConfirmedAt(Option<DateTime<Utc>>),
+  ConfirmationSentAt(Option<DateTime<Utc>>),
   EncryptedPassword(String),
+  RecoverToken(Option<String>),
+  RecoverSentAt(Option<DateTime<Utc>>),
+  MagicLinkToken(Option<String>),
+  MagicLinkSentAt(Option<DateTime<Utc>>),
+  Otp(OtpChannel, OneTimeCode),
+  PhoneConfirmedAt(Option<DateTime<Utc>>),
+  Email(String),
+  EmailChange(Option<String>),
+  EmailChangeTokenNew(Option<String>),
+  EmailChangeTokenCurrent(Option<String>),
+  EmailChangeSentAt(Option<DateTime<Utc>>),
+  IsAnonymous(bool),
+  DeletedAt(Option<DateTime<Utc>>),
+  FailedSignInAttempts(i32),
+  LockedUntil(Option<DateTime<Utc>>),
+  UnlockToken(Option<String>),
+  UnlockSentAt(Option<DateTime<Utc>>),
+  BannedUntil(Option<DateTime<Utc>>),
+  BanReason(Option<String>),
+  Username(Option<String>),
+}
+
+// normalize a phone number to the E.164 format, e.g. "+84 912-345-678" -> "+84912345678"
+pub fn normalize_phone(phone: &str) -> Result<String> {
+  let phone: String = phone
+    .chars()
+    .filter(|c| !matches!(c, ' ' | '-' | '(' | ')' | '.'))
+    .collect();
+  let digits = phone.strip_prefix('+').unwrap_or_default();
+
+  if !(8..=15).contains(&digits.len()) || !digits.chars().all(|c| c.is_ascii_digit()) {
+    bail!(FormatError::ValidationFailed(
+      "phone number must be in the international format, e.g. +84912345678".to_string()
+    ));
+  }
+
+  Ok(phone)
+}
+
+// usernames which cannot be taken, they are compared case-insensitively
+const RESERVED_USERNAMES: [&str; 16] = [
+  "admin",
+  "administrator",
+  "api",
+  "auth",
+  "help",
+  "info",
+  "mail",
+  "me",
+  "null",
+  "root",
+  "security",
+  "settings",
+  "support",
+  "system",
+  "undefined",
+  "www",
+];
+
+// check an email address: a local part and a domain with a dot, at most 254 characters.
+// The address is proved by the mail sent to it, so this only rejects obvious mistakes.
+pub fn validate_email(email: &str) -> Result<()> {
+  let is_valid = match email.split_once('@') {
+    Some((local, domain)) => {
+      !local.is_empty()
+        && !domain.contains('@')
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+    }
+    None => false,
+  };
+
+  if !is_valid || email.len() > 254 || email.chars().any(|c| c.is_whitespace()) {
+    bail!(FormatError::ValidationFailed(
+      "email address is invalid".to_string()
+    ));
+  }
+
+  Ok(())
+}
+
+// check a username: 3 to 30 letters, digits, '_' or '.', starting with a letter or a digit
+pub fn validate_username(username: &str) -> Result<()> {
+  let is_valid_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+  let starts_with_alphanumeric = username.starts_with(|c: char| c.is_ascii_alphanumeric());
+
+  if !(3..=30).contains(&username.len())
+    || !starts_with_alphanumeric
+    || !username.chars().all(is_valid_char)
+  {
+    bail!(FormatError::ValidationFailed(
+      "username must have from 3 to 30 letters, digits, '_' or '.', and start with a letter or a digit".to_string()
+    ));
+  }
+
+  if RESERVED_USERNAMES.contains(&username.to_ascii_lowercase().as_str()) {
+    bail!(FormatError::ValidationFailed(format!(
+      "username '{}' is reserved",
+      username
+    )));
+  }
+
+  Ok(())
 }
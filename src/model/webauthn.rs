@@ -0,0 +1,671 @@
+use super::crypto::create_random_bytes;
+use super::error::FormatError;
+use super::id::Id;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use juniper::{GraphQLInputObject, GraphQLObject};
+use ring::digest::{digest, SHA256};
+use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1, ED25519};
+use serde::Deserialize;
+
+// COSE algorithm identifiers of the supported public keys
+pub const COSE_ALG_ES256: i32 = -7;
+pub const COSE_ALG_EDDSA: i32 = -8;
+pub const SUPPORTED_ALGORITHMS: [i32; 2] = [COSE_ALG_ES256, COSE_ALG_EDDSA];
+
+const CHALLENGE_LEN: usize = 32;
+
+// authenticator data flags
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+// rp id hash (32), flags (1) and sign count (4)
+const AUTHENTICATOR_DATA_MIN_LEN: usize = 37;
+// aaguid (16) and credential id length (2)
+const ATTESTED_CREDENTIAL_DATA_HEADER_LEN: usize = 18;
+
+// DER prefixes of the SubjectPublicKeyInfo, the raw public key follows them
+const P256_SPKI_PREFIX: [u8; 26] = [
+  0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+  0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+  0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ceremony {
+  Registration,
+  Authentication,
+}
+
+impl Ceremony {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Ceremony::Registration => "registration",
+      Ceremony::Authentication => "authentication",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Ceremony> {
+    match name {
+      "registration" => Some(Ceremony::Registration),
+      "authentication" => Some(Ceremony::Authentication),
+      _ => None,
+    }
+  }
+
+  // the type in the client data
+  fn client_data_type(&self) -> &'static str {
+    match self {
+      Ceremony::Registration => "webauthn.create",
+      Ceremony::Authentication => "webauthn.get",
+    }
+  }
+}
+
+// the relying party, i.e. the site the passkeys belong to
+pub struct RelyingParty {
+  pub id: String,
+  pub name: String,
+  pub origin: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebauthnChallenge {
+  pub id: Id,
+  // None for a passkey sign in, the user is found by the credential
+  pub user_id: Option<Id>,
+  // base64url
+  pub challenge: String,
+  pub ceremony: Ceremony,
+  pub expires_at: DateTime<Utc>,
+}
+
+impl WebauthnChallenge {
+  pub fn new(user_id: Option<Id>, ceremony: Ceremony, expires_in: i64) -> Self {
+    Self {
+      id: Id::create_uuid_v4(),
+      user_id,
+      challenge: base64url_encode(&create_random_bytes(CHALLENGE_LEN)),
+      ceremony,
+      expires_at: Utc::now() + Duration::seconds(expires_in),
+    }
+  }
+
+  pub fn is_expired(&self) -> bool {
+    self.expires_at < Utc::now()
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebauthnCredential {
+  pub id: Id,
+  // the mfa factor which represents the credential
+  pub factor_id: Id,
+  pub user_id: Id,
+  // base64url
+  pub credential_id: String,
+  // DER SubjectPublicKeyInfo
+  pub public_key: Vec<u8>,
+  pub algorithm: i32,
+  pub sign_count: i64,
+  pub transports: Vec<String>,
+}
+
+impl WebauthnCredential {
+  // check the response of a registration ceremony. Attestation is not checked,
+  // the public key is taken from getPublicKey() of the response.
+  pub fn new(
+    rp: &RelyingParty,
+    challenge: &WebauthnChallenge,
+    factor_id: &Id,
+    input: &RegisterPasskeyInput,
+  ) -> Result<Self> {
+    let client_data_json = base64url_decode(&input.client_data_json, "clientDataJson")?;
+    verify_client_data(rp, challenge, &client_data_json)?;
+
+    let authenticator_data = base64url_decode(&input.authenticator_data, "authenticatorData")?;
+    let authenticator_data = AuthenticatorData::parse(&authenticator_data)?;
+    authenticator_data.verify(rp, true)?;
+
+    let credential_id = base64url_decode(&input.credential_id, "credentialId")?;
+    if authenticator_data.credential_id != Some(credential_id.as_slice()) {
+      bail!(FormatError::BadRequest(
+        "credential id does not match the authenticator data".to_string()
+      ));
+    }
+
+    let public_key = base64url_decode(&input.public_key, "publicKey")?;
+    raw_public_key(input.public_key_algorithm, &public_key)?;
+
+    let user_id = challenge.user_id.clone().ok_or_else(|| {
+      FormatError::BadRequest("the challenge does not belong to a user".to_string())
+    })?;
+
+    Ok(Self {
+      id: Id::create_uuid_v4(),
+      factor_id: factor_id.clone(),
+      user_id,
+      credential_id: base64url_encode(&credential_id),
+      public_key,
+      algorithm: input.public_key_algorithm,
+      sign_count: authenticator_data.sign_count as i64,
+      transports: input.transports.clone().unwrap_or_default(),
+    })
+  }
+
+  // check the response of an authentication ceremony, it returns the new sign count.
+  // A passwordless sign in requires user verification, e.g. a PIN or a fingerprint.
+  pub fn verify_assertion(
+    &self,
+    rp: &RelyingParty,
+    challenge: &WebauthnChallenge,
+    assertion: &PasskeyAssertionInput,
+    require_user_verification: bool,
+  ) -> Result<i64> {
+    let client_data_json = base64url_decode(&assertion.client_data_json, "clientDataJson")?;
+    verify_client_data(rp, challenge, &client_data_json)?;
+
+    let raw_authenticator_data =
+      base64url_decode(&assertion.authenticator_data, "authenticatorData")?;
+    let authenticator_data = AuthenticatorData::parse(&raw_authenticator_data)?;
+    authenticator_data.verify(rp, require_user_verification)?;
+
+    let signature = base64url_decode(&assertion.signature, "signature")?;
+    let mut signed_data = raw_authenticator_data.clone();
+    signed_data.extend_from_slice(digest(&SHA256, &client_data_json).as_ref());
+
+    let public_key = raw_public_key(self.algorithm, &self.public_key)?;
+    let verified = match self.algorithm {
+      COSE_ALG_ES256 => UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, public_key)
+        .verify(&signed_data, &signature)
+        .is_ok(),
+      _ => UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(&signed_data, &signature)
+        .is_ok(),
+    };
+    if !verified {
+      bail!(FormatError::Unauthenticated(
+        "passkey signature is invalid".to_string()
+      ));
+    }
+
+    // authenticators without a counter always send 0,
+    // otherwise a counter which does not grow means a cloned authenticator
+    let sign_count = authenticator_data.sign_count as i64;
+    if (sign_count != 0 || self.sign_count != 0) && sign_count <= self.sign_count {
+      bail!(FormatError::Unauthenticated(
+        "passkey sign count is invalid, the authenticator may be cloned".to_string()
+      ));
+    }
+
+    Ok(sign_count)
+  }
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+  #[serde(rename = "type")]
+  ceremony_type: String,
+  challenge: String,
+  origin: String,
+}
+
+fn verify_client_data(
+  rp: &RelyingParty,
+  challenge: &WebauthnChallenge,
+  client_data_json: &[u8],
+) -> Result<()> {
+  let client_data: ClientData = serde_json::from_slice(client_data_json)
+    .map_err(|err| FormatError::BadRequest(format!("invalid clientDataJson: {}", err)))?;
+
+  if client_data.ceremony_type != challenge.ceremony.client_data_type() {
+    bail!(FormatError::BadRequest(format!(
+      "client data type must be {}",
+      challenge.ceremony.client_data_type()
+    )));
+  }
+
+  if client_data.challenge.trim_end_matches('=') != challenge.challenge {
+    bail!(FormatError::Unauthenticated(
+      "passkey challenge does not match".to_string()
+    ));
+  }
+
+  if client_data.origin != rp.origin {
+    bail!(FormatError::Unauthenticated(format!(
+      "origin {} is not allowed",
+      client_data.origin
+    )));
+  }
+
+  Ok(())
+}
+
+struct AuthenticatorData<'a> {
+  rp_id_hash: &'a [u8],
+  flags: u8,
+  sign_count: u32,
+  // only in the registration response
+  credential_id: Option<&'a [u8]>,
+}
+
+impl<'a> AuthenticatorData<'a> {
+  fn parse(data: &'a [u8]) -> Result<Self> {
+    if data.len() < AUTHENTICATOR_DATA_MIN_LEN {
+      bail!(FormatError::BadRequest(
+        "authenticator data is too short".to_string()
+      ));
+    }
+
+    let flags = data[32];
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+    let credential_id = if flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+      let start = AUTHENTICATOR_DATA_MIN_LEN + ATTESTED_CREDENTIAL_DATA_HEADER_LEN;
+      if data.len() < start {
+        bail!(FormatError::BadRequest(
+          "attested credential data is too short".to_string()
+        ));
+      }
+      let len = u16::from_be_bytes([data[start - 2], data[start - 1]]) as usize;
+      data.get(start..start + len)
+    } else {
+      None
+    };
+
+    Ok(Self {
+      rp_id_hash: &data[..32],
+      flags,
+      sign_count,
+      credential_id,
+    })
+  }
+
+  fn verify(&self, rp: &RelyingParty, require_user_verification: bool) -> Result<()> {
+    if self.rp_id_hash != digest(&SHA256, rp.id.as_bytes()).as_ref() {
+      bail!(FormatError::Unauthenticated(
+        "passkey belongs to another relying party".to_string()
+      ));
+    }
+
+    if self.flags & FLAG_USER_PRESENT == 0 {
+      bail!(FormatError::Unauthenticated(
+        "user presence is required".to_string()
+      ));
+    }
+
+    if require_user_verification && self.flags & FLAG_USER_VERIFIED == 0 {
+      bail!(FormatError::Unauthenticated(
+        "user verification is required".to_string()
+      ));
+    }
+
+    Ok(())
+  }
+}
+
+// the raw public key in a SubjectPublicKeyInfo, as ring expects it
+fn raw_public_key(algorithm: i32, spki: &[u8]) -> Result<&[u8]> {
+  let prefix: &[u8] = match algorithm {
+    COSE_ALG_ES256 => &P256_SPKI_PREFIX,
+    COSE_ALG_EDDSA => &ED25519_SPKI_PREFIX,
+    _ => bail!(FormatError::BadRequest(format!(
+      "public key algorithm {} is not supported",
+      algorithm
+    ))),
+  };
+
+  match spki.strip_prefix(prefix) {
+    Some(raw) if !raw.is_empty() => Ok(raw),
+    _ => bail!(FormatError::BadRequest(
+      "public key does not match the algorithm".to_string()
+    )),
+  }
+}
+
+pub fn base64url_encode(bytes: &[u8]) -> String {
+  base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn base64url_decode(value: &str, field: &str) -> Result<Vec<u8>> {
+  base64::decode_config(value.trim_end_matches('='), base64::URL_SAFE_NO_PAD).map_err(|_| {
+    anyhow::anyhow!(FormatError::BadRequest(format!(
+      "{} must be base64url",
+      field
+    )))
+  })
+}
+
+#[derive(Debug, GraphQLObject)]
+#[graphql(
+  description = "Options for navigator.credentials.create(), binary values are in base64url"
+)]
+pub struct PasskeyRegistrationOptions {
+  pub challenge_id: Id,
+  pub challenge: String,
+  pub rp_id: String,
+  pub rp_name: String,
+  pub user_handle: String,
+  pub user_name: String,
+  #[graphql(description = "COSE algorithm identifiers for pubKeyCredParams")]
+  pub algorithms: Vec<i32>,
+  #[graphql(description = "Credential ids which are registered already")]
+  pub exclude_credentials: Vec<String>,
+}
+
+#[derive(Debug, GraphQLObject)]
+#[graphql(description = "Options for navigator.credentials.get(), binary values are in base64url")]
+pub struct PasskeyAuthenticationOptions {
+  pub challenge_id: Id,
+  pub challenge: String,
+  pub rp_id: String,
+  #[graphql(description = "Empty for a discoverable passkey sign in")]
+  pub allow_credentials: Vec<String>,
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(description = "Registration response of the authenticator, binary values in base64url")]
+pub struct RegisterPasskeyInput {
+  pub challenge_id: Id,
+  pub credential_id: String,
+  pub client_data_json: String,
+  #[graphql(description = "response.getAuthenticatorData()")]
+  pub authenticator_data: String,
+  #[graphql(description = "response.getPublicKey(), a DER SubjectPublicKeyInfo")]
+  pub public_key: String,
+  #[graphql(description = "response.getPublicKeyAlgorithm(), ES256 (-7) or EdDSA (-8)")]
+  pub public_key_algorithm: i32,
+  pub transports: Option<Vec<String>>,
+  pub friendly_name: Option<String>,
+}
+
+#[derive(GraphQLInputObject, Debug, Clone)]
+#[graphql(description = "Assertion response of the authenticator, binary values in base64url")]
+pub struct PasskeyAssertionInput {
+  pub challenge_id: Id,
+  pub credential_id: String,
+  pub client_data_json: String,
+  pub authenticator_data: String,
+  pub signature: String,
+}
+
+// an authenticator in software for the tests, it signs the ceremonies like
+// a real authenticator would, without attestation
+#[cfg(test)]
+pub mod software_authenticator {
+  use super::*;
+  use ring::rand::SystemRandom;
+  use ring::signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+
+  pub const FLAGS_USER_VERIFIED: u8 = FLAG_USER_PRESENT | FLAG_USER_VERIFIED;
+  pub const FLAGS_USER_PRESENT: u8 = FLAG_USER_PRESENT;
+
+  enum SoftwareKey {
+    Es256(EcdsaKeyPair),
+    EdDsa(Ed25519KeyPair),
+  }
+
+  pub struct SoftwareAuthenticator {
+    key: SoftwareKey,
+    pub credential_id: Vec<u8>,
+    pub rp_id: String,
+    pub origin: String,
+    pub flags: u8,
+    pub sign_count: u32,
+  }
+
+  impl SoftwareAuthenticator {
+    pub fn new(algorithm: i32, rp: &RelyingParty) -> Self {
+      let rng = SystemRandom::new();
+      let key = match algorithm {
+        COSE_ALG_ES256 => {
+          let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+          SoftwareKey::Es256(
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref()).unwrap(),
+          )
+        }
+        _ => {
+          let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+          SoftwareKey::EdDsa(Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap())
+        }
+      };
+
+      Self {
+        key,
+        credential_id: create_random_bytes(16),
+        rp_id: rp.id.clone(),
+        origin: rp.origin.clone(),
+        flags: FLAGS_USER_VERIFIED,
+        sign_count: 0,
+      }
+    }
+
+    pub fn algorithm(&self) -> i32 {
+      match self.key {
+        SoftwareKey::Es256(_) => COSE_ALG_ES256,
+        SoftwareKey::EdDsa(_) => COSE_ALG_EDDSA,
+      }
+    }
+
+    // what response.getPublicKey() returns
+    pub fn public_key(&self) -> Vec<u8> {
+      let (prefix, raw): (&[u8], &[u8]) = match &self.key {
+        SoftwareKey::Es256(key) => (&P256_SPKI_PREFIX, key.public_key().as_ref()),
+        SoftwareKey::EdDsa(key) => (&ED25519_SPKI_PREFIX, key.public_key().as_ref()),
+      };
+      [prefix, raw].concat()
+    }
+
+    fn authenticator_data(&self, attested: bool) -> Vec<u8> {
+      let mut data = digest(&SHA256, self.rp_id.as_bytes()).as_ref().to_vec();
+      if attested {
+        data.push(self.flags | FLAG_ATTESTED_CREDENTIAL_DATA);
+      } else {
+        data.push(self.flags);
+      }
+      data.extend_from_slice(&self.sign_count.to_be_bytes());
+      if attested {
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+        data.extend_from_slice(&self.credential_id);
+      }
+      data
+    }
+
+    fn client_data_json(&self, ceremony: Ceremony, challenge: &str) -> Vec<u8> {
+      serde_json::to_vec(&serde_json::json!({
+        "type": ceremony.client_data_type(),
+        "challenge": challenge,
+        "origin": self.origin,
+      }))
+      .unwrap()
+    }
+
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+      match &self.key {
+        SoftwareKey::Es256(key) => key
+          .sign(&SystemRandom::new(), data)
+          .unwrap()
+          .as_ref()
+          .to_vec(),
+        SoftwareKey::EdDsa(key) => key.sign(data).as_ref().to_vec(),
+      }
+    }
+
+    // navigator.credentials.create() with the challenge of the registration options
+    pub fn register(&self, challenge_id: &Id, challenge: &str) -> RegisterPasskeyInput {
+      RegisterPasskeyInput {
+        challenge_id: challenge_id.clone(),
+        credential_id: base64url_encode(&self.credential_id),
+        client_data_json: base64url_encode(
+          &self.client_data_json(Ceremony::Registration, challenge),
+        ),
+        authenticator_data: base64url_encode(&self.authenticator_data(true)),
+        public_key: base64url_encode(&self.public_key()),
+        public_key_algorithm: self.algorithm(),
+        transports: None,
+        friendly_name: None,
+      }
+    }
+
+    // navigator.credentials.get() with the challenge of the authentication options
+    pub fn assert(&self, challenge_id: &Id, challenge: &str) -> PasskeyAssertionInput {
+      let client_data_json = self.client_data_json(Ceremony::Authentication, challenge);
+      let authenticator_data = self.authenticator_data(false);
+      let mut signed_data = authenticator_data.clone();
+      signed_data.extend_from_slice(digest(&SHA256, &client_data_json).as_ref());
+
+      PasskeyAssertionInput {
+        challenge_id: challenge_id.clone(),
+        credential_id: base64url_encode(&self.credential_id),
+        client_data_json: base64url_encode(&client_data_json),
+        authenticator_data: base64url_encode(&authenticator_data),
+        signature: base64url_encode(&self.sign(&signed_data)),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::software_authenticator::{
+    SoftwareAuthenticator, FLAGS_USER_PRESENT, FLAGS_USER_VERIFIED,
+  };
+  use super::*;
+  use crate::model::error::SpecificError;
+
+  fn relying_party() -> RelyingParty {
+    RelyingParty {
+      id: "example.com".to_string(),
+      name: "Example".to_string(),
+      origin: "https://example.com".to_string(),
+    }
+  }
+
+  fn registration_challenge() -> WebauthnChallenge {
+    WebauthnChallenge::new(Some(Id::create_uuid_v4()), Ceremony::Registration, 300)
+  }
+
+  fn authentication_challenge() -> WebauthnChallenge {
+    WebauthnChallenge::new(None, Ceremony::Authentication, 300)
+  }
+
+  fn register(authenticator: &SoftwareAuthenticator) -> Result<WebauthnCredential> {
+    let challenge = registration_challenge();
+    WebauthnCredential::new(
+      &relying_party(),
+      &challenge,
+      &Id::create_uuid_v4(),
+      &authenticator.register(&challenge.id, &challenge.challenge),
+    )
+  }
+
+  fn sign_in(
+    credential: &WebauthnCredential,
+    authenticator: &SoftwareAuthenticator,
+    require_user_verification: bool,
+  ) -> Result<i64> {
+    let challenge = authentication_challenge();
+    credential.verify_assertion(
+      &relying_party(),
+      &challenge,
+      &authenticator.assert(&challenge.id, &challenge.challenge),
+      require_user_verification,
+    )
+  }
+
+  fn assert_registration_and_assertion(algorithm: i32) {
+    let mut authenticator = SoftwareAuthenticator::new(algorithm, &relying_party());
+    let credential = register(&authenticator).unwrap();
+    assert_eq!(credential.algorithm, algorithm);
+    assert_eq!(
+      credential.credential_id,
+      base64url_encode(&authenticator.credential_id)
+    );
+    assert_eq!(credential.public_key, authenticator.public_key());
+    assert_eq!(credential.sign_count, 0);
+
+    authenticator.sign_count = 1;
+    assert_eq!(sign_in(&credential, &authenticator, true).unwrap(), 1);
+  }
+
+  #[test]
+  fn es256_registration_and_assertion() {
+    assert_registration_and_assertion(COSE_ALG_ES256);
+  }
+
+  #[test]
+  fn eddsa_registration_and_assertion() {
+    assert_registration_and_assertion(COSE_ALG_EDDSA);
+  }
+
+  #[test]
+  fn rejects_a_signature_of_another_key() {
+    let authenticator = SoftwareAuthenticator::new(COSE_ALG_ES256, &relying_party());
+    let credential = register(&authenticator).unwrap();
+    let mut other = SoftwareAuthenticator::new(COSE_ALG_ES256, &relying_party());
+    other.credential_id = authenticator.credential_id.clone();
+
+    let err = sign_in(&credential, &other, true).unwrap_err();
+    assert_eq!(err.get_code(), "UNAUTHENTICATED");
+  }
+
+  #[test]
+  fn rejects_another_rp_id_hash() {
+    let mut authenticator = SoftwareAuthenticator::new(COSE_ALG_EDDSA, &relying_party());
+    let credential = register(&authenticator).unwrap();
+
+    authenticator.rp_id = "evil.example.com".to_string();
+    let err = register(&authenticator).unwrap_err();
+    assert_eq!(err.get_code(), "UNAUTHENTICATED");
+    let err = sign_in(&credential, &authenticator, false).unwrap_err();
+    assert_eq!(err.get_code(), "UNAUTHENTICATED");
+  }
+
+  #[test]
+  fn checks_user_verification() {
+    let mut authenticator = SoftwareAuthenticator::new(COSE_ALG_ES256, &relying_party());
+    authenticator.flags = FLAGS_USER_PRESENT;
+    // registration always requires user verification
+    assert!(register(&authenticator).is_err());
+
+    authenticator.flags = FLAGS_USER_VERIFIED;
+    let credential = register(&authenticator).unwrap();
+
+    authenticator.flags = FLAGS_USER_PRESENT;
+    let err = sign_in(&credential, &authenticator, true).unwrap_err();
+    assert_eq!(err.get_code(), "UNAUTHENTICATED");
+    // a second factor only needs the user to be present
+    assert!(sign_in(&credential, &authenticator, false).is_ok());
+
+    authenticator.flags = 0;
+    assert!(sign_in(&credential, &authenticator, false).is_err());
+  }
+
+  #[test]
+  fn checks_the_sign_count() {
+    let mut authenticator = SoftwareAuthenticator::new(COSE_ALG_EDDSA, &relying_party());
+    let mut credential = register(&authenticator).unwrap();
+
+    // authenticators without a counter always send 0
+    assert_eq!(sign_in(&credential, &authenticator, true).unwrap(), 0);
+
+    authenticator.sign_count = 5;
+    credential.sign_count = sign_in(&credential, &authenticator, true).unwrap();
+    assert_eq!(credential.sign_count, 5);
+
+    // a counter which does not grow means a cloned authenticator
+    let err = sign_in(&credential, &authenticator, true).unwrap_err();
+    assert_eq!(err.get_code(), "UNAUTHENTICATED");
+    authenticator.sign_count = 3;
+    assert!(sign_in(&credential, &authenticator, true).is_err());
+    authenticator.sign_count = 0;
+    assert!(sign_in(&credential, &authenticator, true).is_err());
+
+    authenticator.sign_count = 6;
+    assert_eq!(sign_in(&credential, &authenticator, true).unwrap(), 6);
+  }
+}
@@ -1,5 +1,6 @@
 mod mutation;
 mod query;
+mod user_node;
 
 use crate::context::Context;
 use juniper::{EmptySubscription, RootNode};
@@ -1,14 +1,58 @@
+use super::user_node::UserNode;
 use crate::context::Context;
-use crate::model::error::to_juniper_field_error;
+use crate::model::error::{to_juniper_field_error, FormatError};
 use crate::model::id::Id;
+use crate::model::identity::Identity;
+use crate::model::mfa::{EnrollTotpOutput, MfaFactor};
+use crate::model::oauth::{
+  CreateOAuthClientInput, CreateOAuthClientOutput, OAuthClient, UpdateOAuthClientInput,
+};
 use crate::model::token::{
-  CreateTokenByPasswordInput, CreateTokenGrantType, CreateTokenInput, CreateTokenOutput,
+  CreateTokenByEmailOtpInput, CreateTokenByMagicLinkInput, CreateTokenByMfaChallengeInput,
+  CreateTokenByPasswordInput, CreateTokenByPhoneOtpInput, CreateTokenGrantType, CreateTokenInput,
+  CreateTokenOutput, CreateTokenResult, ReauthenticateInput, SecondFactorInput,
+};
+use crate::model::user::{
+  BanUserInput, ChangePasswordInput, CreateUserInput, InviteUserInput, LinkEmailPasswordInput,
+  RestoreAccountInput, UpdateEmailInput, UpdateUserInput, VerifyUserInput,
+};
+use crate::model::webauthn::{
+  PasskeyAuthenticationOptions, PasskeyRegistrationOptions, RegisterPasskeyInput,
 };
-use crate::model::user::{CreateUserInput, UpdateUserInput, User, VerifyUserInput};
+use crate::repository::log::mailer_log::MailerLog;
+use crate::repository::log::sms_sender_log::SmsSenderLog;
+use crate::repository::sql::identity_repo_sql::IdentityRepoSql;
+use crate::repository::sql::mfa_challenge_repo_sql::MfaChallengeRepoSql;
+use crate::repository::sql::mfa_factor_repo_sql::MfaFactorRepoSql;
+use crate::repository::sql::oauth_client_repo_sql::OAuthClientRepoSql;
+use crate::repository::sql::phone_sign_up_repo_sql::PhoneSignUpRepoSql;
+use crate::repository::sql::recovery_code_repo_sql::RecoveryCodeRepoSql;
 use crate::repository::sql::refresh_token_repo_sql::RefreshTokenRepoSQL;
+use crate::repository::sql::sms_send_repo_sql::SmsSendRepoSql;
+use crate::repository::sql::trusted_device_repo_sql::TrustedDeviceRepoSql;
 use crate::repository::sql::user_repo_sql::UserRepoSql;
-use crate::usecase::token_usecase::{auth_by_password, auth_by_refresh_token, logout};
-use crate::usecase::user_usecase::{create_user, verify_user};
+use crate::repository::sql::webauthn_repo_sql::WebauthnRepoSql;
+use crate::usecase::identity_usecase::{add_email, remove_identity};
+use crate::usecase::mfa_usecase::{
+  enroll_email_factor, enroll_totp, regenerate_recovery_codes, revoke_trusted_device,
+  send_factor_code, unenroll_factor, verify_factor,
+};
+use crate::usecase::oauth_client_usecase::{
+  create_oauth_client, delete_oauth_client, rotate_oauth_client_secret, update_oauth_client,
+};
+use crate::usecase::token_usecase::{
+  auth_by_email_otp, auth_by_magic_link, auth_by_mfa_challenge, auth_by_passkey, auth_by_password,
+  auth_by_phone_otp, auth_by_refresh_token, logout, reauthenticate, send_email_otp,
+  send_magic_link, send_mfa_challenge_code, send_phone_otp, sign_in_anonymously,
+};
+use crate::usecase::user_usecase::{
+  ban_user, change_password, check_admin, create_user, delete_me, invite_user, link_email_password,
+  recover_password, resend_confirmation, restore_account, unban_user, update_email,
+  update_username, verify_user,
+};
+use crate::usecase::webauthn_usecase::{
+  passkey_authentication_options, passkey_registration_options, register_passkey,
+};
 use juniper;
 use juniper::FieldResult;
 
@@ -16,16 +60,142 @@ pub struct MutationRoot;
 
 #[juniper::graphql_object(Context = Context)]
 impl MutationRoot {
-  async fn crate_user(ctx: &Context, create_user_input: CreateUserInput) -> FieldResult<User> {
+  async fn crate_user(ctx: &Context, create_user_input: CreateUserInput) -> FieldResult<UserNode> {
     let aud = ctx.settings.get::<String>("aud").expect("aud must set");
     let user_repo = UserRepoSql::new(ctx);
-    create_user(aud, &user_repo, create_user_input)
+    let mailer = MailerLog::new();
+    create_user(aud, &user_repo, &mailer, create_user_input)
       .await
+      .map(UserNode)
       .map_err(to_juniper_field_error)
   }
 
-  async fn update_user(
+  async fn invite_user(ctx: &Context, invite_user_input: InviteUserInput) -> FieldResult<UserNode> {
+    let claim = ctx
+      .get_current_user_claims()
+      .map_err(to_juniper_field_error)?;
+    let aud = ctx.settings.get::<String>("aud").expect("aud must set");
+    let user_repo = UserRepoSql::new(ctx);
+    let mailer = MailerLog::new();
+
+    check_admin(&user_repo, &claim.user_id)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    invite_user(aud, &user_repo, &mailer, invite_user_input)
+      .await
+      .map(UserNode)
+      .map_err(to_juniper_field_error)
+  }
+
+  async fn ban_user(ctx: &Context, ban_user_input: BanUserInput) -> FieldResult<UserNode> {
+    let claim = ctx
+      .get_current_user_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+    let refresh_token_repo = RefreshTokenRepoSQL::new(ctx);
+
+    check_admin(&user_repo, &claim.user_id)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    ban_user(&user_repo, &refresh_token_repo, ban_user_input)
+      .await
+      .map(UserNode)
+      .map_err(to_juniper_field_error)
+  }
+
+  async fn create_oauth_client(
+    ctx: &Context,
+    create_oauth_client_input: CreateOAuthClientInput,
+  ) -> FieldResult<CreateOAuthClientOutput> {
+    let claim = ctx
+      .get_current_user_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+    let client_repo = OAuthClientRepoSql::new(ctx);
+
+    check_admin(&user_repo, &claim.user_id)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    create_oauth_client(ctx, &client_repo, create_oauth_client_input)
+      .await
+      .map_err(to_juniper_field_error)
+  }
+
+  async fn update_oauth_client(
     ctx: &Context,
+    update_oauth_client_input: UpdateOAuthClientInput,
+  ) -> FieldResult<OAuthClient> {
+    let claim = ctx
+      .get_current_user_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+    let client_repo = OAuthClientRepoSql::new(ctx);
+
+    check_admin(&user_repo, &claim.user_id)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    update_oauth_client(ctx, &client_repo, update_oauth_client_input)
+      .await
+      .map_err(to_juniper_field_error)
+  }
+
+  // the new secret is only returned here
+  async fn rotate_oauth_client_secret(ctx: &Context, id: Id) -> FieldResult<String> {
+    let claim = ctx
+      .get_recently_authenticated_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+    let client_repo = OAuthClientRepoSql::new(ctx);
+
+    check_admin(&user_repo, &claim.user_id)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    rotate_oauth_client_secret(&client_repo, &id)
+      .await
+      .map_err(to_juniper_field_error)
+  }
+
+  async fn delete_oauth_client(ctx: &Context, id: Id) -> FieldResult<String> {
+    let claim = ctx
+      .get_recently_authenticated_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+    let client_repo = OAuthClientRepoSql::new(ctx);
+
+    check_admin(&user_repo, &claim.user_id)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    delete_oauth_client(&client_repo, &id)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    Ok("The client has been deleted".to_string())
+  }
+
+  async fn unban_user(ctx: &Context, id: Id) -> FieldResult<UserNode> {
+    let claim = ctx
+      .get_current_user_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+
+    check_admin(&user_repo, &claim.user_id)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    unban_user(&user_repo, &id)
+      .await
+      .map(UserNode)
+      .map_err(to_juniper_field_error)
+  }
+
+  async fn update_user(
+    _ctx: &Context,
     id: Id,
     update_user_input: UpdateUserInput,
   ) -> FieldResult<String> {
@@ -37,34 +207,553 @@ impl MutationRoot {
     Ok("ok".to_string())
   }
 
+  async fn change_password(
+    ctx: &Context,
+    change_password_input: ChangePasswordInput,
+  ) -> FieldResult<String> {
+    let claim = ctx
+      .get_recently_authenticated_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+    let refresh_token_repo = RefreshTokenRepoSQL::new(ctx);
+    let device_repo = TrustedDeviceRepoSql::new(ctx);
+    let mailer = MailerLog::new();
+
+    change_password(
+      ctx,
+      &user_repo,
+      &refresh_token_repo,
+      &device_repo,
+      &mailer,
+      &claim.user_id,
+      change_password_input,
+    )
+    .await
+    .map_err(to_juniper_field_error)?;
+
+    Ok("Your password has been changed".to_string())
+  }
+
+  async fn update_username(ctx: &Context, username: Option<String>) -> FieldResult<UserNode> {
+    let claim = ctx
+      .get_current_user_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+
+    update_username(&user_repo, &claim.user_id, username)
+      .await
+      .map(UserNode)
+      .map_err(to_juniper_field_error)
+  }
+
+  async fn add_email(ctx: &Context, email: String) -> FieldResult<Identity> {
+    let claim = ctx
+      .get_recently_authenticated_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+    let identity_repo = IdentityRepoSql::new(ctx);
+    let mailer = MailerLog::new();
+
+    add_email(&user_repo, &identity_repo, &mailer, &claim.user_id, email)
+      .await
+      .map_err(to_juniper_field_error)
+  }
+
+  async fn remove_identity(ctx: &Context, id: Id) -> FieldResult<String> {
+    let claim = ctx
+      .get_recently_authenticated_claims()
+      .map_err(to_juniper_field_error)?;
+    let identity_repo = IdentityRepoSql::new(ctx);
+
+    remove_identity(&identity_repo, &claim.user_id, &id)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    Ok("The identity has been removed".to_string())
+  }
+
+  async fn update_email(
+    ctx: &Context,
+    update_email_input: UpdateEmailInput,
+  ) -> FieldResult<String> {
+    let claim = ctx
+      .get_recently_authenticated_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+    let mailer = MailerLog::new();
+
+    update_email(ctx, &user_repo, &mailer, &claim.user_id, update_email_input)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    Ok("Confirmation tokens have been sent to the email addresses".to_string())
+  }
+
   async fn create_token(
     ctx: &Context,
     create_token_input: CreateTokenInput,
-  ) -> FieldResult<CreateTokenOutput> {
+  ) -> FieldResult<CreateTokenResult> {
     let user_repo = UserRepoSql::new(ctx);
     let token_repo = RefreshTokenRepoSQL::new(ctx);
+    let factor_repo = MfaFactorRepoSql::new(ctx);
+    let challenge_repo = MfaChallengeRepoSql::new(ctx);
+    let device_repo = TrustedDeviceRepoSql::new(ctx);
     match create_token_input.grant_type {
       CreateTokenGrantType::Password => {
         // TODO: validate input
         let create_token_by_password_input = CreateTokenByPasswordInput {
-          email: create_token_input.email.unwrap(),
-          password: create_token_input.password.unwrap(),
+          identifier: required_input(
+            create_token_input.identifier.or(create_token_input.email),
+            "identifier",
+          )?,
+          password: required_input(create_token_input.password, "password")?,
+          device_token: create_token_input.device_token,
         };
 
-        auth_by_password(ctx, user_repo, token_repo, &create_token_by_password_input)
+        let identity_repo = IdentityRepoSql::new(ctx);
+        let mailer = MailerLog::new();
+
+        auth_by_password(
+          ctx,
+          user_repo,
+          token_repo,
+          &identity_repo,
+          &factor_repo,
+          &challenge_repo,
+          &device_repo,
+          &mailer,
+          &create_token_by_password_input,
+        )
+        .await
+        .map_err(to_juniper_field_error)
+      }
+      CreateTokenGrantType::MfaChallenge => {
+        let create_token_by_mfa_challenge_input = CreateTokenByMfaChallengeInput {
+          challenge_token: required_input(create_token_input.challenge_token, "challenge_token")?,
+          second_factor: SecondFactorInput {
+            factor_id: create_token_input.factor_id,
+            code: create_token_input.code,
+            passkey: create_token_input.passkey,
+          },
+          trust_device: create_token_input.trust_device.unwrap_or(false),
+          device_name: create_token_input.device_name,
+        };
+        let recovery_code_repo = RecoveryCodeRepoSql::new(ctx);
+        let webauthn_repo = WebauthnRepoSql::new(ctx);
+        let mailer = MailerLog::new();
+
+        auth_by_mfa_challenge(
+          ctx,
+          user_repo,
+          token_repo,
+          &factor_repo,
+          &challenge_repo,
+          &recovery_code_repo,
+          &webauthn_repo,
+          &device_repo,
+          &mailer,
+          &create_token_by_mfa_challenge_input,
+        )
+        .await
+        .map(CreateTokenResult::Token)
+        .map_err(to_juniper_field_error)
+      }
+      CreateTokenGrantType::Passkey => {
+        let assertion = required_input(create_token_input.passkey, "passkey")?;
+        let webauthn_repo = WebauthnRepoSql::new(ctx);
+
+        auth_by_passkey(ctx, user_repo, token_repo, &webauthn_repo, &assertion)
           .await
+          .map(CreateTokenResult::Token)
           .map_err(to_juniper_field_error)
       }
+      CreateTokenGrantType::EmailOtp => {
+        let create_token_by_email_otp_input = CreateTokenByEmailOtpInput {
+          email: required_input(create_token_input.email, "email")?,
+          code: required_input(create_token_input.code, "code")?,
+          device_token: create_token_input.device_token,
+        };
+
+        auth_by_email_otp(
+          ctx,
+          user_repo,
+          token_repo,
+          &factor_repo,
+          &challenge_repo,
+          &device_repo,
+          &create_token_by_email_otp_input,
+        )
+        .await
+        .map_err(to_juniper_field_error)
+      }
+      CreateTokenGrantType::PhoneOtp => {
+        let create_token_by_phone_otp_input = CreateTokenByPhoneOtpInput {
+          phone: required_input(create_token_input.phone, "phone")?,
+          code: required_input(create_token_input.code, "code")?,
+          device_token: create_token_input.device_token,
+        };
+
+        let sign_up_repo = PhoneSignUpRepoSql::new(ctx);
+
+        auth_by_phone_otp(
+          ctx,
+          user_repo,
+          token_repo,
+          &factor_repo,
+          &challenge_repo,
+          &device_repo,
+          &sign_up_repo,
+          &create_token_by_phone_otp_input,
+        )
+        .await
+        .map_err(to_juniper_field_error)
+      }
       CreateTokenGrantType::RefreshToken => {
         // TODO: validate refresh_token
         let refresh_token_value = create_token_input.refresh_token.unwrap();
-        auth_by_refresh_token(ctx, user_repo, token_repo, refresh_token_value)
-          .await
-          .map_err(to_juniper_field_error)
+        let client_repo = OAuthClientRepoSql::new(ctx);
+        auth_by_refresh_token(
+          ctx,
+          user_repo,
+          token_repo,
+          &client_repo,
+          None,
+          refresh_token_value,
+        )
+        .await
+        .map(CreateTokenResult::Token)
+        .map_err(to_juniper_field_error)
+      }
+      CreateTokenGrantType::MagicLink => {
+        let create_token_by_magic_link_input = CreateTokenByMagicLinkInput {
+          token: required_input(create_token_input.token, "token")?,
+          device_token: create_token_input.device_token,
+        };
+
+        auth_by_magic_link(
+          ctx,
+          user_repo,
+          token_repo,
+          &factor_repo,
+          &challenge_repo,
+          &device_repo,
+          &create_token_by_magic_link_input,
+        )
+        .await
+        .map_err(to_juniper_field_error)
       }
     }
   }
 
+  async fn send_magic_link(ctx: &Context, email: String) -> FieldResult<String> {
+    let user_repo = UserRepoSql::new(ctx);
+    let mailer = MailerLog::new();
+
+    send_magic_link(&user_repo, &mailer, email)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    Ok("If the email address is registered, a magic link has been sent to it".to_string())
+  }
+
+  async fn send_email_otp(ctx: &Context, email: String) -> FieldResult<String> {
+    let user_repo = UserRepoSql::new(ctx);
+    let mailer = MailerLog::new();
+
+    send_email_otp(ctx, &user_repo, &mailer, email)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    Ok("If the email address is registered, a one-time code has been sent to it".to_string())
+  }
+
+  async fn send_phone_otp(ctx: &Context, phone: String) -> FieldResult<String> {
+    let user_repo = UserRepoSql::new(ctx);
+    let sign_up_repo = PhoneSignUpRepoSql::new(ctx);
+    let sms_send_repo = SmsSendRepoSql::new(ctx);
+    let sms_sender = SmsSenderLog::new(ctx.settings.get::<String>("sms_log_file").ok());
+
+    send_phone_otp(
+      ctx,
+      &user_repo,
+      &sign_up_repo,
+      &sms_send_repo,
+      &sms_sender,
+      phone,
+    )
+    .await
+    .map_err(to_juniper_field_error)?;
+
+    Ok("A one-time code has been sent to the phone number".to_string())
+  }
+
+  async fn sign_in_anonymously(ctx: &Context) -> FieldResult<CreateTokenOutput> {
+    let user_repo = UserRepoSql::new(ctx);
+    let token_repo = RefreshTokenRepoSQL::new(ctx);
+
+    sign_in_anonymously(ctx, user_repo, token_repo)
+      .await
+      .map_err(to_juniper_field_error)
+  }
+
+  async fn link_email_password(
+    ctx: &Context,
+    link_email_password_input: LinkEmailPasswordInput,
+  ) -> FieldResult<UserNode> {
+    let claim = ctx
+      .get_current_user_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+    let mailer = MailerLog::new();
+
+    link_email_password(
+      &user_repo,
+      &mailer,
+      &claim.user_id,
+      link_email_password_input,
+    )
+    .await
+    .map(UserNode)
+    .map_err(to_juniper_field_error)
+  }
+
+  async fn reauthenticate(
+    ctx: &Context,
+    reauthenticate_input: ReauthenticateInput,
+  ) -> FieldResult<CreateTokenOutput> {
+    let claim = ctx
+      .get_current_user_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+    let token_repo = RefreshTokenRepoSQL::new(ctx);
+    let factor_repo = MfaFactorRepoSql::new(ctx);
+    let recovery_code_repo = RecoveryCodeRepoSql::new(ctx);
+    let webauthn_repo = WebauthnRepoSql::new(ctx);
+    let mailer = MailerLog::new();
+
+    reauthenticate(
+      ctx,
+      &user_repo,
+      &token_repo,
+      &factor_repo,
+      &recovery_code_repo,
+      &webauthn_repo,
+      &mailer,
+      &claim,
+      &reauthenticate_input,
+    )
+    .await
+    .map_err(to_juniper_field_error)
+  }
+
+  async fn enroll_totp(
+    ctx: &Context,
+    friendly_name: Option<String>,
+  ) -> FieldResult<EnrollTotpOutput> {
+    let claim = ctx
+      .get_recently_authenticated_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+    let factor_repo = MfaFactorRepoSql::new(ctx);
+    let recovery_code_repo = RecoveryCodeRepoSql::new(ctx);
+
+    enroll_totp(
+      ctx,
+      &user_repo,
+      &factor_repo,
+      &recovery_code_repo,
+      &claim,
+      friendly_name,
+    )
+    .await
+    .map_err(to_juniper_field_error)
+  }
+
+  async fn enroll_email_factor(
+    ctx: &Context,
+    friendly_name: Option<String>,
+  ) -> FieldResult<MfaFactor> {
+    let claim = ctx
+      .get_recently_authenticated_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+    let factor_repo = MfaFactorRepoSql::new(ctx);
+    let mailer = MailerLog::new();
+
+    enroll_email_factor(
+      ctx,
+      &user_repo,
+      &factor_repo,
+      &mailer,
+      &claim,
+      friendly_name,
+    )
+    .await
+    .map_err(to_juniper_field_error)
+  }
+
+  async fn send_factor_code(ctx: &Context, factor_id: Id) -> FieldResult<String> {
+    let claim = ctx
+      .get_current_user_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+    let factor_repo = MfaFactorRepoSql::new(ctx);
+    let mailer = MailerLog::new();
+
+    send_factor_code(
+      ctx,
+      &user_repo,
+      &factor_repo,
+      &mailer,
+      &claim.user_id,
+      &factor_id,
+    )
+    .await
+    .map_err(to_juniper_field_error)?;
+
+    Ok("A code has been sent to your email".to_string())
+  }
+
+  // send the code of an email factor chosen to complete an MFA challenge
+  async fn send_mfa_challenge_code(
+    ctx: &Context,
+    challenge_token: String,
+    factor_id: Id,
+  ) -> FieldResult<String> {
+    let user_repo = UserRepoSql::new(ctx);
+    let factor_repo = MfaFactorRepoSql::new(ctx);
+    let challenge_repo = MfaChallengeRepoSql::new(ctx);
+    let mailer = MailerLog::new();
+
+    send_mfa_challenge_code(
+      ctx,
+      &user_repo,
+      &factor_repo,
+      &challenge_repo,
+      &mailer,
+      &challenge_token,
+      &factor_id,
+    )
+    .await
+    .map_err(to_juniper_field_error)?;
+
+    Ok("A code has been sent to your email".to_string())
+  }
+
+  async fn passkey_registration_options(ctx: &Context) -> FieldResult<PasskeyRegistrationOptions> {
+    let claim = ctx
+      .get_recently_authenticated_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+    let factor_repo = MfaFactorRepoSql::new(ctx);
+    let webauthn_repo = WebauthnRepoSql::new(ctx);
+
+    passkey_registration_options(ctx, &user_repo, &factor_repo, &webauthn_repo, &claim)
+      .await
+      .map_err(to_juniper_field_error)
+  }
+
+  async fn register_passkey(
+    ctx: &Context,
+    register_passkey_input: RegisterPasskeyInput,
+  ) -> FieldResult<MfaFactor> {
+    let claim = ctx
+      .get_recently_authenticated_claims()
+      .map_err(to_juniper_field_error)?;
+    let factor_repo = MfaFactorRepoSql::new(ctx);
+    let webauthn_repo = WebauthnRepoSql::new(ctx);
+
+    register_passkey(
+      ctx,
+      &factor_repo,
+      &webauthn_repo,
+      &claim,
+      register_passkey_input,
+    )
+    .await
+    .map_err(to_juniper_field_error)
+  }
+
+  // with challenge_token when the passkey is the second factor
+  async fn passkey_authentication_options(
+    ctx: &Context,
+    challenge_token: Option<String>,
+  ) -> FieldResult<PasskeyAuthenticationOptions> {
+    let webauthn_repo = WebauthnRepoSql::new(ctx);
+    let challenge_repo = MfaChallengeRepoSql::new(ctx);
+
+    passkey_authentication_options(ctx, &webauthn_repo, &challenge_repo, challenge_token)
+      .await
+      .map_err(to_juniper_field_error)
+  }
+
+  async fn revoke_trusted_device(ctx: &Context, id: Id) -> FieldResult<String> {
+    let claim = ctx
+      .get_current_user_claims()
+      .map_err(to_juniper_field_error)?;
+    let device_repo = TrustedDeviceRepoSql::new(ctx);
+
+    revoke_trusted_device(&device_repo, &claim.user_id, &id)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    Ok("The device is not trusted anymore".to_string())
+  }
+
+  async fn regenerate_recovery_codes(ctx: &Context) -> FieldResult<Vec<String>> {
+    let claim = ctx
+      .get_recently_authenticated_claims()
+      .map_err(to_juniper_field_error)?;
+    let factor_repo = MfaFactorRepoSql::new(ctx);
+    let recovery_code_repo = RecoveryCodeRepoSql::new(ctx);
+
+    regenerate_recovery_codes(&factor_repo, &recovery_code_repo, &claim)
+      .await
+      .map_err(to_juniper_field_error)
+  }
+
+  // verifies the code of a TOTP or email factor
+  // it returns null when the factor has just been enrolled, without upgrading the session
+  async fn verify_totp(
+    ctx: &Context,
+    factor_id: Id,
+    code: String,
+  ) -> FieldResult<Option<CreateTokenOutput>> {
+    let claim = ctx
+      .get_current_user_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+    let refresh_token_repo = RefreshTokenRepoSQL::new(ctx);
+    let factor_repo = MfaFactorRepoSql::new(ctx);
+    let mailer = MailerLog::new();
+
+    verify_factor(
+      ctx,
+      &user_repo,
+      &refresh_token_repo,
+      &factor_repo,
+      &mailer,
+      &claim,
+      &factor_id,
+      &code,
+    )
+    .await
+    .map_err(to_juniper_field_error)
+  }
+
+  async fn unenroll_factor(ctx: &Context, factor_id: Id) -> FieldResult<String> {
+    let claim = ctx
+      .get_recently_authenticated_claims()
+      .map_err(to_juniper_field_error)?;
+    let factor_repo = MfaFactorRepoSql::new(ctx);
+
+    unenroll_factor(&factor_repo, &claim, &factor_id)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    Ok("The factor has been removed".to_string())
+  }
+
   async fn logout(ctx: &Context) -> FieldResult<String> {
     let claim = ctx
       .get_current_user_claims()
@@ -77,15 +766,91 @@ impl MutationRoot {
     Ok("dummy response".to_string())
   }
 
+  async fn resend_confirmation(ctx: &Context, email: String) -> FieldResult<String> {
+    let user_repo = UserRepoSql::new(ctx);
+    let mailer = MailerLog::new();
+
+    resend_confirmation(ctx, &user_repo, &mailer, email)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    Ok(
+      "If the email address is waiting for confirmation, a new token has been sent to it"
+        .to_string(),
+    )
+  }
+
+  async fn recover_password(ctx: &Context, email: String) -> FieldResult<String> {
+    let user_repo = UserRepoSql::new(ctx);
+    let mailer = MailerLog::new();
+
+    recover_password(&user_repo, &mailer, email)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    Ok("If the email address is registered, a recover token has been sent to it".to_string())
+  }
+
+  async fn delete_me(ctx: &Context) -> FieldResult<String> {
+    let claim = ctx
+      .get_recently_authenticated_claims()
+      .map_err(to_juniper_field_error)?;
+    let user_repo = UserRepoSql::new(ctx);
+    let refresh_token_repo = RefreshTokenRepoSQL::new(ctx);
+
+    delete_me(&user_repo, &refresh_token_repo, &claim.user_id)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    Ok("Your account has been deleted, it can be restored during the grace period".to_string())
+  }
+
+  async fn restore_account(
+    ctx: &Context,
+    restore_account_input: RestoreAccountInput,
+  ) -> FieldResult<String> {
+    let user_repo = UserRepoSql::new(ctx);
+    let mailer = MailerLog::new();
+
+    restore_account(ctx, &user_repo, &mailer, restore_account_input)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    Ok("If the account can be restored, a restore token has been sent to it".to_string())
+  }
+
+  // it signs in the user, except EMAIL_CHANGE which returns null
   async fn verify(
     ctx: &Context,
     verify_user_input: VerifyUserInput,
-  ) -> FieldResult<CreateTokenOutput> {
+  ) -> FieldResult<Option<CreateTokenResult>> {
     let user_repo = UserRepoSql::new(ctx);
     let refresh_token_repo = RefreshTokenRepoSQL::new(ctx);
+    let identity_repo = IdentityRepoSql::new(ctx);
+    let factor_repo = MfaFactorRepoSql::new(ctx);
+    let challenge_repo = MfaChallengeRepoSql::new(ctx);
+    let device_repo = TrustedDeviceRepoSql::new(ctx);
 
-    verify_user(ctx, &user_repo, &refresh_token_repo, &verify_user_input)
-      .await
-      .map_err(to_juniper_field_error)
+    verify_user(
+      ctx,
+      &user_repo,
+      &refresh_token_repo,
+      &identity_repo,
+      &factor_repo,
+      &challenge_repo,
+      &device_repo,
+      &verify_user_input,
+    )
+    .await
+    .map_err(to_juniper_field_error)
   }
 }
+
+fn required_input<T>(value: Option<T>, name: &str) -> FieldResult<T> {
+  value.ok_or_else(|| {
+    to_juniper_field_error(anyhow::anyhow!(FormatError::ValidationFailed(format!(
+      "{} is required",
+      name
+    ))))
+  })
+}
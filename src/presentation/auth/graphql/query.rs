@@ -1,10 +1,12 @@
+use super::user_node::{UserNode, Users};
 use crate::context::Context;
 use crate::model::{
-  error::to_juniper_field_error,
-  user::{FindAUserInput, User, Users},
+  error::to_juniper_field_error, id::Id, oauth::OAuthClient, user::FindAUserInput,
 };
+use crate::repository::sql::oauth_client_repo_sql::OAuthClientRepoSql;
 use crate::repository::sql::user_repo_sql::UserRepoSql;
-use crate::usecase::user_usecase::UserUsecase;
+use crate::usecase::oauth_client_usecase::{find_oauth_client, find_oauth_clients};
+use crate::usecase::user_usecase::{check_admin, UserUsecase};
 use juniper;
 use juniper::FieldResult;
 
@@ -12,30 +14,41 @@ pub struct QueryRoot;
 
 #[juniper::graphql_object(Context = Context)]
 impl QueryRoot {
-  #[graphql(description = "Find many users")]
+  #[graphql(description = "Find many users, admin only")]
   async fn users(ctx: &Context) -> FieldResult<Users> {
-    ctx.get_current_user_claims()?;
+    let claim = ctx.get_current_user_claims()?;
+    check_admin(&UserRepoSql::new(ctx), &claim.user_id)
+      .await
+      .map_err(to_juniper_field_error)?;
     let mut user_usecase = UserUsecase::new(UserRepoSql::new(ctx));
 
-    user_usecase
+    let users = user_usecase
       .find_many_users()
       .await
-      .map_err(to_juniper_field_error)
+      .map_err(to_juniper_field_error)?;
+
+    Ok(Users {
+      items: users.into_iter().map(UserNode).collect(),
+    })
   }
 
-  #[graphql(description = "Find one user")]
-  async fn user(ctx: &Context, find_a_user_input: FindAUserInput) -> FieldResult<User> {
-    ctx.get_current_user_claims()?;
+  #[graphql(description = "Find one user, admin only")]
+  async fn user(ctx: &Context, find_a_user_input: FindAUserInput) -> FieldResult<UserNode> {
+    let claim = ctx.get_current_user_claims()?;
+    check_admin(&UserRepoSql::new(ctx), &claim.user_id)
+      .await
+      .map_err(to_juniper_field_error)?;
     let mut user_usecase = UserUsecase::new(UserRepoSql::new(ctx));
 
     user_usecase
       .find_user(find_a_user_input)
       .await
+      .map(UserNode)
       .map_err(to_juniper_field_error)
   }
 
   #[graphql(description = "Get user information for logged in user")]
-  async fn me(ctx: &Context) -> FieldResult<User> {
+  async fn me(ctx: &Context) -> FieldResult<UserNode> {
     let claim = ctx.get_current_user_claims()?;
     let mut user_usecase = UserUsecase::new(UserRepoSql::new(ctx));
 
@@ -46,6 +59,37 @@ impl QueryRoot {
 
     user_usecase
       .find_user(find_me)
+      .await
+      .map(UserNode)
+      .map_err(to_juniper_field_error)
+  }
+
+  #[graphql(description = "Find the OAuth clients, admin only")]
+  async fn oauth_clients(ctx: &Context) -> FieldResult<Vec<OAuthClient>> {
+    let claim = ctx.get_current_user_claims()?;
+    let user_repo = UserRepoSql::new(ctx);
+    let client_repo = OAuthClientRepoSql::new(ctx);
+
+    check_admin(&user_repo, &claim.user_id)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    find_oauth_clients(&client_repo)
+      .await
+      .map_err(to_juniper_field_error)
+  }
+
+  #[graphql(description = "Find an OAuth client, admin only")]
+  async fn oauth_client(ctx: &Context, id: Id) -> FieldResult<OAuthClient> {
+    let claim = ctx.get_current_user_claims()?;
+    let user_repo = UserRepoSql::new(ctx);
+    let client_repo = OAuthClientRepoSql::new(ctx);
+
+    check_admin(&user_repo, &claim.user_id)
+      .await
+      .map_err(to_juniper_field_error)?;
+
+    find_oauth_client(&client_repo, &id)
       .await
       .map_err(to_juniper_field_error)
   }
@@ -0,0 +1,80 @@
+use crate::context::Context;
+use crate::model::error::to_juniper_field_error;
+use crate::model::id::Id;
+use crate::model::identity::Identity;
+use crate::model::mfa::{MfaFactor, RecoveryCodesStatus, TrustedDevice};
+use crate::model::user::{User, UserMetaData};
+use crate::repository::sql::identity_repo_sql::IdentityRepoSql;
+use crate::repository::sql::mfa_factor_repo_sql::MfaFactorRepoSql;
+use crate::repository::sql::recovery_code_repo_sql::RecoveryCodeRepoSql;
+use crate::repository::sql::trusted_device_repo_sql::TrustedDeviceRepoSql;
+use crate::usecase::identity_usecase::find_identities;
+use crate::usecase::mfa_usecase::{find_factors, find_recovery_codes_status, find_trusted_devices};
+use chrono::{DateTime, Utc};
+use juniper::{graphql_object, FieldResult, GraphQLObject};
+
+// the User type of the schema, its fields which need repositories are resolved here
+pub struct UserNode(pub User);
+
+#[derive(GraphQLObject)]
+#[graphql(context = Context)]
+pub struct Users {
+  pub items: Vec<UserNode>,
+}
+
+#[graphql_object(name = "User", context = Context)]
+impl UserNode {
+  fn id(&self) -> &Id {
+    &self.0.id
+  }
+  fn email(&self) -> Option<&String> {
+    self.0.email.as_ref()
+  }
+  fn username(&self) -> Option<&String> {
+    self.0.username.as_ref()
+  }
+  fn phone(&self) -> Option<&String> {
+    self.0.phone.as_ref()
+  }
+  fn is_anonymous(&self) -> bool {
+    self.0.is_anonymous
+  }
+  fn role(&self) -> &String {
+    &self.0.role
+  }
+  fn banned_until(&self) -> Option<&DateTime<Utc>> {
+    self.0.banned_until.as_ref()
+  }
+  fn ban_reason(&self) -> Option<&String> {
+    self.0.ban_reason.as_ref()
+  }
+  fn user_meta_data(&self) -> &UserMetaData {
+    &self.0.user_meta_data
+  }
+  fn created_at(&self) -> &DateTime<Utc> {
+    &self.0.created_at
+  }
+  fn updated_at(&self) -> &DateTime<Utc> {
+    &self.0.updated_at
+  }
+  async fn identities(&self, ctx: &Context) -> FieldResult<Vec<Identity>> {
+    find_identities(&IdentityRepoSql::new(ctx), &self.0.id)
+      .await
+      .map_err(to_juniper_field_error)
+  }
+  async fn factors(&self, ctx: &Context) -> FieldResult<Vec<MfaFactor>> {
+    find_factors(&MfaFactorRepoSql::new(ctx), &self.0.id)
+      .await
+      .map_err(to_juniper_field_error)
+  }
+  async fn recovery_codes(&self, ctx: &Context) -> FieldResult<RecoveryCodesStatus> {
+    find_recovery_codes_status(&RecoveryCodeRepoSql::new(ctx), &self.0.id)
+      .await
+      .map_err(to_juniper_field_error)
+  }
+  async fn trusted_devices(&self, ctx: &Context) -> FieldResult<Vec<TrustedDevice>> {
+    find_trusted_devices(&TrustedDeviceRepoSql::new(ctx), &self.0.id)
+      .await
+      .map_err(to_juniper_field_error)
+  }
+}
@@ -1,3 +1,16 @@
+pub mod authorization_code_repo;
+pub mod identity_repo;
+pub mod log;
+pub mod mailer;
+pub mod mfa_challenge_repo;
+pub mod mfa_factor_repo;
+pub mod oauth_client_repo;
+pub mod phone_sign_up_repo;
+pub mod recovery_code_repo;
 pub mod refresh_token_repo;
+pub mod sms_send_repo;
+pub mod sms_sender;
 pub mod sql;
+pub mod trusted_device_repo;
 pub mod user_repo;
+pub mod webauthn_repo;
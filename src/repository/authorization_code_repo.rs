@@ -0,0 +1,10 @@
+use crate::model::oauth::AuthorizationCode;
+use anyhow::Result;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait AuthorizationCodeRepo {
+  async fn insert(&self, authorization_code: &AuthorizationCode) -> Result<()>;
+  // find the code by its hash and delete it, so it cannot be used again
+  async fn take(&self, hashed_code: &str) -> Result<AuthorizationCode>;
+}
@@ -0,0 +1,16 @@
+use crate::model::id::Id;
+use crate::model::identity::Identity;
+use anyhow::Result;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait IdentityRepo {
+  async fn insert(&self, identity: &Identity) -> Result<()>;
+  async fn find_by_user_id(&self, user_id: &Id) -> Result<Vec<Identity>>;
+  async fn find_one_by_confirmation_token(&self, token: &str) -> Result<Identity>;
+  async fn mark_verified(&self, id: &Id) -> Result<()>;
+  async fn touch_last_used(&self, provider: &str, provider_id: &str) -> Result<()>;
+  async fn delete_one(&self, user_id: &Id, id: &Id) -> Result<()>;
+  // delete the unverified identity, so the provider id can be claimed again
+  async fn delete_unverified(&self, provider: &str, provider_id: &str) -> Result<()>;
+}
@@ -0,0 +1,2 @@
+pub mod mailer_log;
+pub mod sms_sender_log;
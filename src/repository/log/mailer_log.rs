@@ -0,0 +1,24 @@
+use super::super::mailer::Mailer;
+use anyhow::Result;
+use async_trait::async_trait;
+use log::info;
+
+// Mailer that writes emails to the log instead of sending them.
+// Use it for development until a real mail provider is set up.
+#[derive(Default)]
+pub struct MailerLog;
+
+impl MailerLog {
+  pub fn new() -> Self {
+    MailerLog
+  }
+}
+
+#[async_trait]
+impl Mailer for MailerLog {
+  async fn send(&self, to: &str, subject: &str, body: &str) -> Result<()> {
+    info!("send mail to: {}, subject: {}, body: {}", to, subject, body);
+
+    Ok(())
+  }
+}
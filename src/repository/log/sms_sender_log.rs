@@ -0,0 +1,41 @@
+use super::super::sms_sender::SmsSender;
+use crate::model::error::FormatError;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use log::info;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+// SmsSender that writes SMS to the log instead of sending them.
+// When a file path is given, SMS are also appended to the file, one per line,
+// so development tools and tests can read the one-time codes from it.
+pub struct SmsSenderLog {
+  file_path: Option<String>,
+}
+
+impl SmsSenderLog {
+  pub fn new(file_path: Option<String>) -> Self {
+    SmsSenderLog { file_path }
+  }
+}
+
+#[async_trait]
+impl SmsSender for SmsSenderLog {
+  async fn send(&self, to: &str, message: &str) -> Result<()> {
+    info!("send sms to: {}, message: {}", to, message);
+
+    if let Some(file_path) = &self.file_path {
+      let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file_path)
+        .map_err(|err| FormatError::ServerError(err.to_string()))?;
+
+      writeln!(file, "{}\t{}\t{}", Utc::now().to_rfc3339(), to, message)
+        .map_err(|err| FormatError::ServerError(err.to_string()))?;
+    }
+
+    Ok(())
+  }
+}
@@ -0,0 +1,12 @@
+use crate::model::id::Id;
+use crate::model::mfa::MfaChallenge;
+use anyhow::Result;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait MfaChallengeRepo {
+  async fn insert(&self, challenge: &MfaChallenge) -> Result<()>;
+  async fn find_by_token(&self, hashed_token: &str) -> Result<MfaChallenge>;
+  // it returns false when the challenge has already been deleted, e.g. by a concurrent request
+  async fn delete_one(&self, id: &Id) -> Result<bool>;
+}
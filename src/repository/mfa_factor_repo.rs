@@ -0,0 +1,27 @@
+use crate::model::id::Id;
+use crate::model::mfa::{FactorType, MfaFactor};
+use crate::model::user::OneTimeCode;
+use anyhow::Result;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait MfaFactorRepo {
+  async fn insert(&self, factor: &MfaFactor) -> Result<()>;
+  async fn find_by_user_id(&self, user_id: &Id) -> Result<Vec<MfaFactor>>;
+  async fn find_one(&self, user_id: &Id, id: &Id) -> Result<MfaFactor>;
+  async fn has_verified(&self, user_id: &Id) -> Result<bool>;
+  // mark the factor verified and remember the accepted time step. Both return false
+  // when the factor is verified or the step is used already, e.g. by a concurrent request
+  async fn mark_verified(&self, id: &Id, last_used_step: i64) -> Result<bool>;
+  async fn update_last_used_step(&self, id: &Id, last_used_step: i64) -> Result<bool>;
+  async fn update_otp(&self, id: &Id, otp: &OneTimeCode) -> Result<()>;
+  // count an attempt of the code of an email factor, before the code is checked. It returns
+  // the code with the counted attempt, and fails with NotFoundError when there is no code
+  // or no attempt left.
+  async fn count_otp_attempt(&self, id: &Id, max_attempts: i32) -> Result<OneTimeCode>;
+  // clear the code when it is still the given hashed code,
+  // it returns false when a concurrent request has used it already
+  async fn take_otp(&self, id: &Id, hashed_code: &str) -> Result<bool>;
+  async fn delete_one(&self, user_id: &Id, id: &Id) -> Result<()>;
+  async fn delete_unverified(&self, user_id: &Id, factor_type: FactorType) -> Result<()>;
+}
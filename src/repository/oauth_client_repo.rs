@@ -0,0 +1,14 @@
+use crate::model::id::Id;
+use crate::model::oauth::OAuthClient;
+use anyhow::Result;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait OAuthClientRepo {
+  async fn insert(&self, client: &OAuthClient) -> Result<()>;
+  async fn find_many(&self) -> Result<Vec<OAuthClient>>;
+  async fn find_one(&self, id: &Id) -> Result<OAuthClient>;
+  // save every field of the client, including the secret
+  async fn update(&self, client: &OAuthClient) -> Result<()>;
+  async fn delete_one(&self, id: &Id) -> Result<()>;
+}
@@ -0,0 +1,14 @@
+use crate::model::user::{OneTimeCode, PhoneSignUp};
+use anyhow::Result;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait PhoneSignUpRepo {
+  // save the sign up, it replaces the code of a previous one of the phone number
+  async fn upsert(&self, sign_up: &PhoneSignUp) -> Result<()>;
+  async fn find_one(&self, phone: &str) -> Result<PhoneSignUp>;
+  // same as UserRepo::count_otp_attempt, for the code of a sign up
+  async fn count_otp_attempt(&self, phone: &str, max_attempts: i32) -> Result<OneTimeCode>;
+  // it returns false when the sign up has already been deleted, e.g. by a concurrent request
+  async fn delete_one(&self, phone: &str) -> Result<bool>;
+}
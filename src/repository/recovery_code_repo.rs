@@ -0,0 +1,13 @@
+use crate::model::id::Id;
+use crate::model::mfa::RecoveryCodesStatus;
+use anyhow::Result;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait RecoveryCodeRepo {
+  // replace all recovery codes of the user with the hashed codes
+  async fn replace_all(&self, user_id: &Id, hashed_codes: &[String]) -> Result<()>;
+  // mark the unused code as used, it returns false when no such code exists
+  async fn use_code(&self, user_id: &Id, hashed_code: &str) -> Result<bool>;
+  async fn find_status(&self, user_id: &Id) -> Result<RecoveryCodesStatus>;
+}
@@ -7,6 +7,7 @@ use async_trait::async_trait;
 pub trait RefreshTokenRepo {
   async fn insert(&self, refresh_token: &RefreshToken) -> Result<()>;
   async fn delete_by_user_id(&self, user_id: &Id) -> Result<()>;
+  async fn delete_by_user_id_except(&self, user_id: &Id, kept_token_value: &str) -> Result<()>;
   async fn swap_token(
     &self,
     user_id: &Id,
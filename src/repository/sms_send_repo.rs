@@ -0,0 +1,12 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+#[async_trait]
+pub trait SmsSendRepo {
+  async fn insert(&self, phone: &str, ip: Option<&str>) -> Result<()>;
+  async fn count_by_phone(&self, phone: &str, since: DateTime<Utc>) -> Result<i64>;
+  async fn count_by_ip(&self, ip: &str, since: DateTime<Utc>) -> Result<i64>;
+  // forget the messages sent before the time, they do not count any more
+  async fn delete_before(&self, sent_before: DateTime<Utc>) -> Result<()>;
+}
@@ -0,0 +1,7 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait SmsSender {
+  async fn send(&self, to: &str, message: &str) -> Result<()>;
+}
@@ -1,2 +1,12 @@
+pub mod authorization_code_repo_sql;
+pub mod identity_repo_sql;
+pub mod mfa_challenge_repo_sql;
+pub mod mfa_factor_repo_sql;
+pub mod oauth_client_repo_sql;
+pub mod phone_sign_up_repo_sql;
+pub mod recovery_code_repo_sql;
 pub mod refresh_token_repo_sql;
+pub mod sms_send_repo_sql;
+pub mod trusted_device_repo_sql;
 pub mod user_repo_sql;
+pub mod webauthn_repo_sql;
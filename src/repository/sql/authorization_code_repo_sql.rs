@@ -0,0 +1,86 @@
+use super::super::authorization_code_repo::AuthorizationCodeRepo;
+use crate::context::Context;
+use crate::model::error::FormatError;
+use crate::model::id::Id;
+use crate::model::oauth::AuthorizationCode;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use sqlx::{postgres::PgRow, Row};
+
+pub struct AuthorizationCodeRepoSql<'a> {
+  ctx: &'a Context,
+}
+
+impl AuthorizationCodeRepoSql<'_> {
+  pub fn new(ctx: &Context) -> AuthorizationCodeRepoSql<'_> {
+    AuthorizationCodeRepoSql { ctx }
+  }
+}
+
+#[async_trait]
+impl AuthorizationCodeRepo for AuthorizationCodeRepoSql<'_> {
+  async fn insert(&self, authorization_code: &AuthorizationCode) -> Result<()> {
+    sqlx::query(
+      r#"
+        INSERT INTO oauth_authorization_codes (
+          id, code, client_id, user_id, redirect_uri, scope, code_challenge, nonce, amr,
+          authenticated_at, expires_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+      "#,
+    )
+    .bind(authorization_code.id.to_string())
+    .bind(authorization_code.code.clone())
+    .bind(authorization_code.client_id.to_string())
+    .bind(authorization_code.user_id.to_string())
+    .bind(authorization_code.redirect_uri.clone())
+    .bind(authorization_code.scope.clone())
+    .bind(authorization_code.code_challenge.clone())
+    .bind(authorization_code.nonce.clone())
+    .bind(authorization_code.amr.clone())
+    .bind(authorization_code.authenticated_at)
+    .bind(authorization_code.expires_at)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
+  async fn take(&self, hashed_code: &str) -> Result<AuthorizationCode> {
+    sqlx::query(
+      r#"
+        DELETE FROM oauth_authorization_codes
+        WHERE code = $1
+        RETURNING id, code, client_id, user_id, redirect_uri, scope, code_challenge, nonce,
+          amr, authenticated_at, expires_at
+      "#,
+    )
+    .bind(hashed_code)
+    .map(|row: PgRow| AuthorizationCode {
+      id: Id::new(row.get("id")),
+      code: row.get("code"),
+      client_id: Id::new(row.get("client_id")),
+      user_id: Id::new(row.get("user_id")),
+      redirect_uri: row.get("redirect_uri"),
+      scope: row.get("scope"),
+      code_challenge: row.get("code_challenge"),
+      nonce: row.get("nonce"),
+      amr: row.get("amr"),
+      authenticated_at: row.get("authenticated_at"),
+      expires_at: row.get("expires_at"),
+    })
+    .fetch_one(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)
+  }
+}
+
+fn sqlx_err_to_anyhow(e: sqlx::Error) -> anyhow::Error {
+  let message = e.to_string();
+  if message.starts_with("no rows returned") {
+    return anyhow!(FormatError::NotFoundError(message));
+  }
+
+  anyhow!(FormatError::ServerError(message))
+}
@@ -0,0 +1,176 @@
+use super::super::identity_repo::IdentityRepo;
+use crate::context::Context;
+use crate::model::error::FormatError;
+use crate::model::id::Id;
+use crate::model::identity::Identity;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use sqlx::{postgres::PgRow, Done, Row};
+
+const IDENTITY_COLUMNS: &str = "
+  id, user_id, provider, provider_id, email, verified_at, last_used_at,
+  confirmation_token, confirmation_sent_at, created_at, updated_at
+";
+
+pub struct IdentityRepoSql<'a> {
+  ctx: &'a Context,
+}
+
+impl IdentityRepoSql<'_> {
+  pub fn new(ctx: &Context) -> IdentityRepoSql<'_> {
+    IdentityRepoSql { ctx }
+  }
+}
+
+#[async_trait]
+impl IdentityRepo for IdentityRepoSql<'_> {
+  async fn insert(&self, identity: &Identity) -> Result<()> {
+    sqlx::query(
+      r#"
+        INSERT INTO identities (
+          id, user_id, provider, provider_id, email, verified_at,
+          confirmation_token, confirmation_sent_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+      "#,
+    )
+    .bind(identity.id.to_string())
+    .bind(identity.user_id.to_string())
+    .bind(identity.provider.clone())
+    .bind(identity.provider_id.clone())
+    .bind(identity.email.clone())
+    .bind(identity.verified_at)
+    .bind(identity.confirmation_token.clone())
+    .bind(identity.confirmation_sent_at)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
+  async fn find_by_user_id(&self, user_id: &Id) -> Result<Vec<Identity>> {
+    let query = format!(
+      "SELECT {} FROM identities WHERE user_id = $1 ORDER BY created_at",
+      IDENTITY_COLUMNS
+    );
+    sqlx::query(query.as_str())
+      .bind(user_id.to_string())
+      .map(pg_row_to_identity)
+      .fetch_all(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn find_one_by_confirmation_token(&self, token: &str) -> Result<Identity> {
+    let query = format!(
+      "SELECT {} FROM identities WHERE confirmation_token = $1",
+      IDENTITY_COLUMNS
+    );
+    sqlx::query(query.as_str())
+      .bind(token)
+      .map(pg_row_to_identity)
+      .fetch_one(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn mark_verified(&self, id: &Id) -> Result<()> {
+    sqlx::query(
+      r#"
+        UPDATE identities
+        SET verified_at = NOW(), confirmation_token = NULL, confirmation_sent_at = NULL,
+          updated_at = NOW()
+        WHERE id = $1
+      "#,
+    )
+    .bind(id.to_string())
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
+  async fn touch_last_used(&self, provider: &str, provider_id: &str) -> Result<()> {
+    sqlx::query(
+      r#"
+        UPDATE identities
+        SET last_used_at = NOW()
+        WHERE provider = $1 AND provider_id = $2
+      "#,
+    )
+    .bind(provider)
+    .bind(provider_id)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
+  async fn delete_one(&self, user_id: &Id, id: &Id) -> Result<()> {
+    let deleted = sqlx::query("DELETE FROM identities WHERE user_id = $1 AND id = $2")
+      .bind(user_id.to_string())
+      .bind(id.to_string())
+      .execute(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)?;
+
+    if deleted.rows_affected() == 0 {
+      return Err(anyhow!(FormatError::NotFoundError(
+        "identity not found".to_string()
+      )));
+    }
+
+    Ok(())
+  }
+
+  async fn delete_unverified(&self, provider: &str, provider_id: &str) -> Result<()> {
+    sqlx::query(
+      r#"
+        DELETE FROM identities
+        WHERE provider = $1 AND provider_id = $2 AND verified_at IS NULL
+      "#,
+    )
+    .bind(provider)
+    .bind(provider_id)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+}
+
+fn pg_row_to_identity(row: PgRow) -> Identity {
+  Identity {
+    id: Id::new(row.get("id")),
+    user_id: Id::new(row.get("user_id")),
+    provider: row.get("provider"),
+    provider_id: row.get("provider_id"),
+    email: row.get("email"),
+    verified_at: row.get("verified_at"),
+    last_used_at: row.get("last_used_at"),
+    confirmation_token: row.get("confirmation_token"),
+    confirmation_sent_at: row.get("confirmation_sent_at"),
+    created_at: row.get("created_at"),
+    updated_at: row.get("updated_at"),
+  }
+}
+
+const UNIQUE_VIOLATION_CODE: &str = "23505";
+
+fn sqlx_err_to_anyhow(e: sqlx::Error) -> anyhow::Error {
+  let message = e.to_string();
+  if message.starts_with("no rows returned") {
+    return anyhow!(FormatError::NotFoundError(message));
+  }
+
+  let db_error_code = e.as_database_error().and_then(|db_error| db_error.code());
+  if db_error_code.as_deref() == Some(UNIQUE_VIOLATION_CODE) {
+    return anyhow!(FormatError::DuplicateError(message));
+  }
+
+  anyhow!(FormatError::ServerError(message))
+}
@@ -0,0 +1,80 @@
+use super::super::mfa_challenge_repo::MfaChallengeRepo;
+use crate::context::Context;
+use crate::model::error::FormatError;
+use crate::model::id::Id;
+use crate::model::mfa::MfaChallenge;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use sqlx::{postgres::PgRow, Done, Row};
+
+pub struct MfaChallengeRepoSql<'a> {
+  ctx: &'a Context,
+}
+
+impl MfaChallengeRepoSql<'_> {
+  pub fn new(ctx: &Context) -> MfaChallengeRepoSql<'_> {
+    MfaChallengeRepoSql { ctx }
+  }
+}
+
+#[async_trait]
+impl MfaChallengeRepo for MfaChallengeRepoSql<'_> {
+  async fn insert(&self, challenge: &MfaChallenge) -> Result<()> {
+    sqlx::query(
+      r#"
+        INSERT INTO mfa_challenges (id, user_id, token, amr, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+      "#,
+    )
+    .bind(challenge.id.to_string())
+    .bind(challenge.user_id.to_string())
+    .bind(challenge.token.clone())
+    .bind(challenge.amr.clone())
+    .bind(challenge.expires_at)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
+  async fn find_by_token(&self, hashed_token: &str) -> Result<MfaChallenge> {
+    sqlx::query(
+      r#"
+        SELECT id, user_id, token, amr, expires_at
+        FROM mfa_challenges
+        WHERE token = $1
+      "#,
+    )
+    .bind(hashed_token)
+    .map(|row: PgRow| MfaChallenge {
+      id: Id::new(row.get("id")),
+      user_id: Id::new(row.get("user_id")),
+      token: row.get("token"),
+      amr: row.get("amr"),
+      expires_at: row.get("expires_at"),
+    })
+    .fetch_one(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn delete_one(&self, id: &Id) -> Result<bool> {
+    let deleted = sqlx::query("DELETE FROM mfa_challenges WHERE id = $1")
+      .bind(id.to_string())
+      .execute(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(deleted.rows_affected() > 0)
+  }
+}
+
+fn sqlx_err_to_anyhow(e: sqlx::Error) -> anyhow::Error {
+  let message = e.to_string();
+  if message.starts_with("no rows returned") {
+    return anyhow!(FormatError::NotFoundError(message));
+  }
+
+  anyhow!(FormatError::ServerError(message))
+}
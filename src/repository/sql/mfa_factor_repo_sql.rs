@@ -0,0 +1,249 @@
+use super::super::mfa_factor_repo::MfaFactorRepo;
+use crate::context::Context;
+use crate::model::error::FormatError;
+use crate::model::id::Id;
+use crate::model::mfa::{FactorStatus, FactorType, MfaFactor};
+use crate::model::user::OneTimeCode;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use sqlx::{postgres::PgRow, Done, Row};
+
+const MFA_FACTOR_COLUMNS: &str = "
+  id, user_id, factor_type, friendly_name, status, secret, last_used_step,
+  otp, otp_sent_at, otp_attempts, created_at, updated_at
+";
+
+pub struct MfaFactorRepoSql<'a> {
+  ctx: &'a Context,
+}
+
+impl MfaFactorRepoSql<'_> {
+  pub fn new(ctx: &Context) -> MfaFactorRepoSql<'_> {
+    MfaFactorRepoSql { ctx }
+  }
+}
+
+#[async_trait]
+impl MfaFactorRepo for MfaFactorRepoSql<'_> {
+  async fn insert(&self, factor: &MfaFactor) -> Result<()> {
+    sqlx::query(
+      r#"
+        INSERT INTO mfa_factors (
+          id, user_id, factor_type, friendly_name, status, secret, last_used_step
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+      "#,
+    )
+    .bind(factor.id.to_string())
+    .bind(factor.user_id.to_string())
+    .bind(factor.factor_type.name())
+    .bind(factor.friendly_name.clone())
+    .bind(factor.status.name())
+    .bind(factor.secret.clone())
+    .bind(factor.last_used_step)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
+  async fn find_by_user_id(&self, user_id: &Id) -> Result<Vec<MfaFactor>> {
+    let query = format!(
+      "SELECT {} FROM mfa_factors WHERE user_id = $1 ORDER BY created_at",
+      MFA_FACTOR_COLUMNS
+    );
+    sqlx::query(query.as_str())
+      .bind(user_id.to_string())
+      .map(pg_row_to_mfa_factor)
+      .fetch_all(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn find_one(&self, user_id: &Id, id: &Id) -> Result<MfaFactor> {
+    let query = format!(
+      "SELECT {} FROM mfa_factors WHERE user_id = $1 AND id = $2",
+      MFA_FACTOR_COLUMNS
+    );
+    sqlx::query(query.as_str())
+      .bind(user_id.to_string())
+      .bind(id.to_string())
+      .map(pg_row_to_mfa_factor)
+      .fetch_one(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn has_verified(&self, user_id: &Id) -> Result<bool> {
+    sqlx::query(
+      r#"
+        SELECT EXISTS (
+          SELECT 1 FROM mfa_factors WHERE user_id = $1 AND status = $2
+        ) AS has_verified
+      "#,
+    )
+    .bind(user_id.to_string())
+    .bind(FactorStatus::Verified.name())
+    .map(|row: PgRow| row.get("has_verified"))
+    .fetch_one(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn mark_verified(&self, id: &Id, last_used_step: i64) -> Result<bool> {
+    let updated = sqlx::query(
+      r#"
+        UPDATE mfa_factors
+        SET status = $2, last_used_step = $3, updated_at = NOW()
+        WHERE id = $1 AND status <> $2
+          -- an email factor has no time step, it is verified with step 0
+          AND (last_used_step IS NULL OR last_used_step < $3 OR $3 = 0)
+      "#,
+    )
+    .bind(id.to_string())
+    .bind(FactorStatus::Verified.name())
+    .bind(last_used_step)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(updated.rows_affected() > 0)
+  }
+
+  async fn update_last_used_step(&self, id: &Id, last_used_step: i64) -> Result<bool> {
+    let updated = sqlx::query(
+      r#"
+        UPDATE mfa_factors
+        SET last_used_step = $2, updated_at = NOW()
+        WHERE id = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+      "#,
+    )
+    .bind(id.to_string())
+    .bind(last_used_step)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(updated.rows_affected() > 0)
+  }
+
+  async fn update_otp(&self, id: &Id, otp: &OneTimeCode) -> Result<()> {
+    sqlx::query(
+      r#"
+        UPDATE mfa_factors
+        SET otp = $2, otp_sent_at = $3, otp_attempts = $4, updated_at = NOW()
+        WHERE id = $1
+      "#,
+    )
+    .bind(id.to_string())
+    .bind(otp.code.clone())
+    .bind(otp.sent_at)
+    .bind(otp.attempts)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
+  async fn count_otp_attempt(&self, id: &Id, max_attempts: i32) -> Result<OneTimeCode> {
+    sqlx::query(
+      r#"
+        UPDATE mfa_factors
+        SET otp_attempts = otp_attempts + 1, updated_at = NOW()
+        WHERE id = $1 AND otp IS NOT NULL AND otp_attempts < $2
+        RETURNING otp, otp_sent_at, otp_attempts
+      "#,
+    )
+    .bind(id.to_string())
+    .bind(max_attempts)
+    .map(|row: PgRow| OneTimeCode {
+      code: row.get("otp"),
+      sent_at: row.get("otp_sent_at"),
+      attempts: row.get("otp_attempts"),
+    })
+    .fetch_one(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn take_otp(&self, id: &Id, hashed_code: &str) -> Result<bool> {
+    let updated = sqlx::query(
+      r#"
+        UPDATE mfa_factors
+        SET otp = NULL, otp_sent_at = NULL, otp_attempts = 0, updated_at = NOW()
+        WHERE id = $1 AND otp = $2
+      "#,
+    )
+    .bind(id.to_string())
+    .bind(hashed_code)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(updated.rows_affected() > 0)
+  }
+
+  async fn delete_one(&self, user_id: &Id, id: &Id) -> Result<()> {
+    let deleted = sqlx::query("DELETE FROM mfa_factors WHERE user_id = $1 AND id = $2")
+      .bind(user_id.to_string())
+      .bind(id.to_string())
+      .execute(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)?;
+
+    if deleted.rows_affected() == 0 {
+      return Err(anyhow!(FormatError::NotFoundError(
+        "factor not found".to_string()
+      )));
+    }
+
+    Ok(())
+  }
+
+  async fn delete_unverified(&self, user_id: &Id, factor_type: FactorType) -> Result<()> {
+    sqlx::query(
+      r#"
+        DELETE FROM mfa_factors
+        WHERE user_id = $1 AND factor_type = $2 AND status = $3
+      "#,
+    )
+    .bind(user_id.to_string())
+    .bind(factor_type.name())
+    .bind(FactorStatus::Unverified.name())
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+}
+
+fn pg_row_to_mfa_factor(row: PgRow) -> MfaFactor {
+  MfaFactor {
+    id: Id::new(row.get("id")),
+    user_id: Id::new(row.get("user_id")),
+    factor_type: FactorType::from_name(row.get("factor_type")).unwrap_or(FactorType::Totp),
+    friendly_name: row.get("friendly_name"),
+    status: FactorStatus::from_name(row.get("status")).unwrap_or(FactorStatus::Unverified),
+    secret: row.get("secret"),
+    last_used_step: row.get("last_used_step"),
+    otp: OneTimeCode {
+      code: row.get("otp"),
+      sent_at: row.get("otp_sent_at"),
+      attempts: row.get("otp_attempts"),
+    },
+    created_at: row.get("created_at"),
+    updated_at: row.get("updated_at"),
+  }
+}
+
+fn sqlx_err_to_anyhow(e: sqlx::Error) -> anyhow::Error {
+  let message = e.to_string();
+  if message.starts_with("no rows returned") {
+    return anyhow!(FormatError::NotFoundError(message));
+  }
+
+  anyhow!(FormatError::ServerError(message))
+}
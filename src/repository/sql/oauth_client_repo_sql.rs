@@ -0,0 +1,155 @@
+use super::super::oauth_client_repo::OAuthClientRepo;
+use crate::context::Context;
+use crate::model::error::FormatError;
+use crate::model::id::Id;
+use crate::model::oauth::{OAuthClient, OAuthGrantType};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use sqlx::{postgres::PgRow, Done, Row};
+
+const OAUTH_CLIENT_COLUMNS: &str = "
+  id, secret, name, redirect_uris, grant_types, scopes, access_token_exp, refresh_token_exp,
+  aud, created_at, updated_at
+";
+
+pub struct OAuthClientRepoSql<'a> {
+  ctx: &'a Context,
+}
+
+impl OAuthClientRepoSql<'_> {
+  pub fn new(ctx: &Context) -> OAuthClientRepoSql<'_> {
+    OAuthClientRepoSql { ctx }
+  }
+}
+
+#[async_trait]
+impl OAuthClientRepo for OAuthClientRepoSql<'_> {
+  async fn insert(&self, client: &OAuthClient) -> Result<()> {
+    sqlx::query(
+      r#"
+        INSERT INTO oauth_clients (
+          id, secret, name, redirect_uris, grant_types, scopes, access_token_exp,
+          refresh_token_exp, aud
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+      "#,
+    )
+    .bind(client.id.to_string())
+    .bind(client.secret.clone())
+    .bind(client.name.clone())
+    .bind(client.redirect_uris.clone())
+    .bind(grant_type_names(&client.grant_types))
+    .bind(client.scopes.clone())
+    .bind(client.access_token_exp)
+    .bind(client.refresh_token_exp)
+    .bind(client.aud.clone())
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
+  async fn find_many(&self) -> Result<Vec<OAuthClient>> {
+    let query = format!(
+      "SELECT {} FROM oauth_clients ORDER BY created_at",
+      OAUTH_CLIENT_COLUMNS
+    );
+    sqlx::query(query.as_str())
+      .map(pg_row_to_oauth_client)
+      .fetch_all(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn find_one(&self, id: &Id) -> Result<OAuthClient> {
+    let query = format!(
+      "SELECT {} FROM oauth_clients WHERE id = $1",
+      OAUTH_CLIENT_COLUMNS
+    );
+    sqlx::query(query.as_str())
+      .bind(id.to_string())
+      .map(pg_row_to_oauth_client)
+      .fetch_one(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn update(&self, client: &OAuthClient) -> Result<()> {
+    sqlx::query(
+      r#"
+        UPDATE oauth_clients
+        SET secret = $2, name = $3, redirect_uris = $4, grant_types = $5, scopes = $6,
+          access_token_exp = $7, refresh_token_exp = $8, aud = $9, updated_at = NOW()
+        WHERE id = $1
+      "#,
+    )
+    .bind(client.id.to_string())
+    .bind(client.secret.clone())
+    .bind(client.name.clone())
+    .bind(client.redirect_uris.clone())
+    .bind(grant_type_names(&client.grant_types))
+    .bind(client.scopes.clone())
+    .bind(client.access_token_exp)
+    .bind(client.refresh_token_exp)
+    .bind(client.aud.clone())
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
+  async fn delete_one(&self, id: &Id) -> Result<()> {
+    let deleted = sqlx::query("DELETE FROM oauth_clients WHERE id = $1")
+      .bind(id.to_string())
+      .execute(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)?;
+
+    if deleted.rows_affected() == 0 {
+      return Err(anyhow!(FormatError::NotFoundError(
+        "client not found".to_string()
+      )));
+    }
+
+    Ok(())
+  }
+}
+
+fn grant_type_names(grant_types: &[OAuthGrantType]) -> Vec<String> {
+  grant_types
+    .iter()
+    .map(|grant_type| grant_type.name().to_string())
+    .collect()
+}
+
+fn pg_row_to_oauth_client(row: PgRow) -> OAuthClient {
+  let grant_types: Vec<String> = row.get("grant_types");
+
+  OAuthClient {
+    id: Id::new(row.get("id")),
+    secret: row.get("secret"),
+    name: row.get("name"),
+    redirect_uris: row.get("redirect_uris"),
+    grant_types: grant_types
+      .iter()
+      .filter_map(|name| OAuthGrantType::from_name(name))
+      .collect(),
+    scopes: row.get("scopes"),
+    access_token_exp: row.get("access_token_exp"),
+    refresh_token_exp: row.get("refresh_token_exp"),
+    aud: row.get("aud"),
+    created_at: row.get("created_at"),
+    updated_at: row.get("updated_at"),
+  }
+}
+
+fn sqlx_err_to_anyhow(e: sqlx::Error) -> anyhow::Error {
+  let message = e.to_string();
+  if message.starts_with("no rows returned") {
+    return anyhow!(FormatError::NotFoundError(message));
+  }
+
+  anyhow!(FormatError::ServerError(message))
+}
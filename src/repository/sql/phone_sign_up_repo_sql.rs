@@ -0,0 +1,96 @@
+use super::super::phone_sign_up_repo::PhoneSignUpRepo;
+use crate::context::Context;
+use crate::model::error::FormatError;
+use crate::model::user::{OneTimeCode, PhoneSignUp};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use sqlx::{postgres::PgRow, Done, Row};
+
+pub struct PhoneSignUpRepoSql<'a> {
+  ctx: &'a Context,
+}
+
+impl PhoneSignUpRepoSql<'_> {
+  pub fn new(ctx: &Context) -> PhoneSignUpRepoSql<'_> {
+    PhoneSignUpRepoSql { ctx }
+  }
+}
+
+#[async_trait]
+impl PhoneSignUpRepo for PhoneSignUpRepoSql<'_> {
+  async fn upsert(&self, sign_up: &PhoneSignUp) -> Result<()> {
+    sqlx::query(
+      r#"
+        INSERT INTO phone_sign_ups (phone, otp, otp_sent_at, otp_attempts)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (phone)
+        DO UPDATE SET otp = $2, otp_sent_at = $3, otp_attempts = $4
+      "#,
+    )
+    .bind(sign_up.phone.clone())
+    .bind(sign_up.otp.code.clone())
+    .bind(sign_up.otp.sent_at)
+    .bind(sign_up.otp.attempts)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
+  async fn find_one(&self, phone: &str) -> Result<PhoneSignUp> {
+    sqlx::query("SELECT phone, otp, otp_sent_at, otp_attempts FROM phone_sign_ups WHERE phone = $1")
+      .bind(phone)
+      .map(|row: PgRow| PhoneSignUp {
+        phone: row.get("phone"),
+        otp: pg_row_to_otp(&row),
+      })
+      .fetch_one(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn count_otp_attempt(&self, phone: &str, max_attempts: i32) -> Result<OneTimeCode> {
+    sqlx::query(
+      r#"
+        UPDATE phone_sign_ups
+        SET otp_attempts = otp_attempts + 1
+        WHERE phone = $1 AND otp IS NOT NULL AND otp_attempts < $2
+        RETURNING otp, otp_sent_at, otp_attempts
+      "#,
+    )
+    .bind(phone)
+    .bind(max_attempts)
+    .map(|row: PgRow| pg_row_to_otp(&row))
+    .fetch_one(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn delete_one(&self, phone: &str) -> Result<bool> {
+    let deleted = sqlx::query("DELETE FROM phone_sign_ups WHERE phone = $1")
+      .bind(phone)
+      .execute(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(deleted.rows_affected() > 0)
+  }
+}
+
+fn pg_row_to_otp(row: &PgRow) -> OneTimeCode {
+  OneTimeCode {
+    code: row.get("otp"),
+    sent_at: row.get("otp_sent_at"),
+    attempts: row.get("otp_attempts"),
+  }
+}
+
+fn sqlx_err_to_anyhow(e: sqlx::Error) -> anyhow::Error {
+  let message = e.to_string();
+  if message.starts_with("no rows returned") {
+    return anyhow!(FormatError::NotFoundError(message));
+  }
+
+  anyhow!(FormatError::ServerError(message))
+}
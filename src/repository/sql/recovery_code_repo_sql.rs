@@ -0,0 +1,91 @@
+use super::super::recovery_code_repo::RecoveryCodeRepo;
+use crate::context::Context;
+use crate::model::error::FormatError;
+use crate::model::id::Id;
+use crate::model::mfa::RecoveryCodesStatus;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use sqlx::{postgres::PgRow, Done, Row};
+
+pub struct RecoveryCodeRepoSql<'a> {
+  ctx: &'a Context,
+}
+
+impl RecoveryCodeRepoSql<'_> {
+  pub fn new(ctx: &Context) -> RecoveryCodeRepoSql<'_> {
+    RecoveryCodeRepoSql { ctx }
+  }
+}
+
+#[async_trait]
+impl RecoveryCodeRepo for RecoveryCodeRepoSql<'_> {
+  async fn replace_all(&self, user_id: &Id, hashed_codes: &[String]) -> Result<()> {
+    let mut tx = self
+      .ctx
+      .auth_db_pool_ref()
+      .begin()
+      .await
+      .map_err(sqlx_err_to_anyhow)?;
+
+    sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+      .bind(user_id.to_string())
+      .execute(&mut tx)
+      .await
+      .map_err(sqlx_err_to_anyhow)?;
+
+    for hashed_code in hashed_codes {
+      sqlx::query("INSERT INTO mfa_recovery_codes (id, user_id, code) VALUES ($1, $2, $3)")
+        .bind(Id::create_uuid_v4().to_string())
+        .bind(user_id.to_string())
+        .bind(hashed_code)
+        .execute(&mut tx)
+        .await
+        .map_err(sqlx_err_to_anyhow)?;
+    }
+
+    tx.commit().await.map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
+  async fn use_code(&self, user_id: &Id, hashed_code: &str) -> Result<bool> {
+    let used = sqlx::query(
+      r#"
+        UPDATE mfa_recovery_codes
+        SET used_at = NOW()
+        WHERE user_id = $1 AND code = $2 AND used_at IS NULL
+      "#,
+    )
+    .bind(user_id.to_string())
+    .bind(hashed_code)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(used.rows_affected() == 1)
+  }
+
+  async fn find_status(&self, user_id: &Id) -> Result<RecoveryCodesStatus> {
+    sqlx::query(
+      r#"
+        SELECT
+          COUNT(*) FILTER (WHERE used_at IS NULL) AS remaining,
+          MAX(used_at) AS last_used_at
+        FROM mfa_recovery_codes
+        WHERE user_id = $1
+      "#,
+    )
+    .bind(user_id.to_string())
+    .map(|row: PgRow| RecoveryCodesStatus {
+      remaining: row.get::<i64, &str>("remaining") as i32,
+      last_used_at: row.get("last_used_at"),
+    })
+    .fetch_one(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)
+  }
+}
+
+fn sqlx_err_to_anyhow(e: sqlx::Error) -> anyhow::Error {
+  anyhow!(FormatError::ServerError(e.to_string()))
+}
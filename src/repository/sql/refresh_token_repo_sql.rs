@@ -20,20 +20,25 @@ impl<'a> RefreshTokenRepoSQL<'a> {
 #[async_trait]
 impl RefreshTokenRepo for RefreshTokenRepoSQL<'_> {
   async fn insert(&self, refresh_token: &RefreshToken) -> Result<()> {
-    let query = format!(
+    sqlx::query(
       "
-      INSERT INTO refresh_tokens (id, token, user_id, revoked)
-      VALUES ('{id}', '{token}', '{user_id}', false)
+      INSERT INTO refresh_tokens (
+        id, token, user_id, revoked, authenticated_at, aal, amr, client_id, scopes
+      )
+      VALUES ($1, $2, $3, false, $4, $5, $6, $7, $8)
     ",
-      id = refresh_token.id,
-      token = refresh_token.token,
-      user_id = refresh_token.user_id
-    );
-
-    sqlx::query(query.as_str())
-      .execute(self.ctx.auth_db_pool_ref())
-      .await
-      .map_err(sqlx_err_to_anyhow)?;
+    )
+    .bind(refresh_token.id.to_string())
+    .bind(refresh_token.token.clone())
+    .bind(refresh_token.user_id.to_string())
+    .bind(refresh_token.authenticated_at)
+    .bind(refresh_token.aal.clone())
+    .bind(refresh_token.amr.clone())
+    .bind(refresh_token.client_id.as_ref().map(|id| id.to_string()))
+    .bind(refresh_token.scopes.clone())
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
 
     Ok(())
   }
@@ -53,6 +58,22 @@ impl RefreshTokenRepo for RefreshTokenRepoSQL<'_> {
     Ok(())
   }
 
+  async fn delete_by_user_id_except(&self, user_id: &Id, kept_token_value: &str) -> Result<()> {
+    sqlx::query(
+      "
+      DELETE FROM refresh_tokens
+      WHERE user_id = $1::text and token <> $2::text
+    ",
+    )
+    .bind(user_id.to_string())
+    .bind(kept_token_value)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
   async fn swap_token(
     &self,
     user_id: &Id,
@@ -61,7 +82,7 @@ impl RefreshTokenRepo for RefreshTokenRepoSQL<'_> {
   ) -> Result<()> {
     let old_refresh_token = sqlx::query(
       r#"
-        SELECT revoked, id, created_at, updated_at
+        SELECT revoked, id, authenticated_at, aal, amr, client_id, scopes
         FROM refresh_tokens
         WHERE user_id = $1::text and token = $2::text
       "#,
@@ -71,10 +92,13 @@ impl RefreshTokenRepo for RefreshTokenRepoSQL<'_> {
     .map(|row: PgRow| RefreshToken {
       id: Id::new(row.get("id")),
       revoked: row.get("revoked"),
+      authenticated_at: row.get("authenticated_at"),
+      aal: row.get("aal"),
+      amr: row.get("amr"),
+      client_id: row.get::<Option<String>, &str>("client_id").map(Id::new),
+      scopes: row.get("scopes"),
       token: old_refresh_token_value.clone(),
       user_id: user_id.clone(),
-      created_at: row.get("created_at"),
-      updated_at: row.get("updated_at"),
     })
     .fetch_one(self.ctx.auth_db_pool_ref())
     .await
@@ -106,7 +130,7 @@ impl RefreshTokenRepo for RefreshTokenRepoSQL<'_> {
   async fn find_one_by_token(&self, token_value: String) -> Result<RefreshToken> {
     sqlx::query(
       r#"
-        SELECT id, user_id, revoked, created_at, updated_at
+        SELECT id, user_id, revoked, authenticated_at, aal, amr, client_id, scopes
         FROM refresh_tokens
         WHERE token = $1::text
       "#,
@@ -117,8 +141,11 @@ impl RefreshTokenRepo for RefreshTokenRepoSQL<'_> {
       user_id: Id::new(row.get("user_id")),
       token: token_value.clone(),
       revoked: row.get("revoked"),
-      created_at: row.get("created_at"),
-      updated_at: row.get("updated_at"),
+      authenticated_at: row.get("authenticated_at"),
+      aal: row.get("aal"),
+      amr: row.get("amr"),
+      client_id: row.get::<Option<String>, &str>("client_id").map(Id::new),
+      scopes: row.get("scopes"),
     })
     .fetch_one(self.ctx.auth_db_pool_ref())
     .await
@@ -132,5 +159,5 @@ fn sqlx_err_to_anyhow(e: sqlx::Error) -> anyhow::Error {
     return anyhow!(FormatError::NotFoundError(message));
   }
 
-  return anyhow!(FormatError::ServerError(message));
+  anyhow!(FormatError::ServerError(message))
 }
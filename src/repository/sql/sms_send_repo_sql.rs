@@ -0,0 +1,65 @@
+use super::super::sms_send_repo::SmsSendRepo;
+use crate::context::Context;
+use crate::model::error::FormatError;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{postgres::PgRow, Row};
+
+pub struct SmsSendRepoSql<'a> {
+  ctx: &'a Context,
+}
+
+impl SmsSendRepoSql<'_> {
+  pub fn new(ctx: &Context) -> SmsSendRepoSql<'_> {
+    SmsSendRepoSql { ctx }
+  }
+}
+
+#[async_trait]
+impl SmsSendRepo for SmsSendRepoSql<'_> {
+  async fn insert(&self, phone: &str, ip: Option<&str>) -> Result<()> {
+    sqlx::query("INSERT INTO sms_sends (phone, ip) VALUES ($1, $2)")
+      .bind(phone)
+      .bind(ip)
+      .execute(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
+  async fn count_by_phone(&self, phone: &str, since: DateTime<Utc>) -> Result<i64> {
+    sqlx::query("SELECT COUNT(*) AS count FROM sms_sends WHERE phone = $1 AND sent_at > $2")
+      .bind(phone)
+      .bind(since)
+      .map(|row: PgRow| row.get("count"))
+      .fetch_one(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn count_by_ip(&self, ip: &str, since: DateTime<Utc>) -> Result<i64> {
+    sqlx::query("SELECT COUNT(*) AS count FROM sms_sends WHERE ip = $1 AND sent_at > $2")
+      .bind(ip)
+      .bind(since)
+      .map(|row: PgRow| row.get("count"))
+      .fetch_one(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn delete_before(&self, sent_before: DateTime<Utc>) -> Result<()> {
+    sqlx::query("DELETE FROM sms_sends WHERE sent_at <= $1")
+      .bind(sent_before)
+      .execute(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+}
+
+fn sqlx_err_to_anyhow(e: sqlx::Error) -> anyhow::Error {
+  anyhow!(FormatError::ServerError(e.to_string()))
+}
@@ -0,0 +1,125 @@
+use super::super::trusted_device_repo::TrustedDeviceRepo;
+use crate::context::Context;
+use crate::model::error::FormatError;
+use crate::model::id::Id;
+use crate::model::mfa::TrustedDevice;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use sqlx::{postgres::PgRow, Done, Row};
+
+const TRUSTED_DEVICE_COLUMNS: &str = "id, user_id, name, expires_at, last_used_at, created_at";
+
+pub struct TrustedDeviceRepoSql<'a> {
+  ctx: &'a Context,
+}
+
+impl TrustedDeviceRepoSql<'_> {
+  pub fn new(ctx: &Context) -> TrustedDeviceRepoSql<'_> {
+    TrustedDeviceRepoSql { ctx }
+  }
+}
+
+#[async_trait]
+impl TrustedDeviceRepo for TrustedDeviceRepoSql<'_> {
+  async fn insert(&self, device: &TrustedDevice) -> Result<()> {
+    sqlx::query(
+      r#"
+        INSERT INTO trusted_devices (id, user_id, name, expires_at)
+        VALUES ($1, $2, $3, $4)
+      "#,
+    )
+    .bind(device.id.to_string())
+    .bind(device.user_id.to_string())
+    .bind(device.name.clone())
+    .bind(device.expires_at)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
+  async fn find_by_user_id(&self, user_id: &Id) -> Result<Vec<TrustedDevice>> {
+    let query = format!(
+      "SELECT {} FROM trusted_devices WHERE user_id = $1 ORDER BY created_at",
+      TRUSTED_DEVICE_COLUMNS
+    );
+    sqlx::query(query.as_str())
+      .bind(user_id.to_string())
+      .map(pg_row_to_trusted_device)
+      .fetch_all(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn find_one(&self, user_id: &Id, id: &Id) -> Result<TrustedDevice> {
+    let query = format!(
+      "SELECT {} FROM trusted_devices WHERE user_id = $1 AND id = $2",
+      TRUSTED_DEVICE_COLUMNS
+    );
+    sqlx::query(query.as_str())
+      .bind(user_id.to_string())
+      .bind(id.to_string())
+      .map(pg_row_to_trusted_device)
+      .fetch_one(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn touch(&self, id: &Id) -> Result<()> {
+    sqlx::query("UPDATE trusted_devices SET last_used_at = NOW() WHERE id = $1")
+      .bind(id.to_string())
+      .execute(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
+  async fn delete_one(&self, user_id: &Id, id: &Id) -> Result<()> {
+    let deleted = sqlx::query("DELETE FROM trusted_devices WHERE user_id = $1 AND id = $2")
+      .bind(user_id.to_string())
+      .bind(id.to_string())
+      .execute(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)?;
+
+    if deleted.rows_affected() == 0 {
+      return Err(anyhow!(FormatError::NotFoundError(
+        "trusted device not found".to_string()
+      )));
+    }
+
+    Ok(())
+  }
+
+  async fn delete_by_user_id(&self, user_id: &Id) -> Result<()> {
+    sqlx::query("DELETE FROM trusted_devices WHERE user_id = $1")
+      .bind(user_id.to_string())
+      .execute(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+}
+
+fn pg_row_to_trusted_device(row: PgRow) -> TrustedDevice {
+  TrustedDevice {
+    id: Id::new(row.get("id")),
+    user_id: Id::new(row.get("user_id")),
+    name: row.get("name"),
+    expires_at: row.get("expires_at"),
+    last_used_at: row.get("last_used_at"),
+    created_at: row.get("created_at"),
+  }
+}
+
+fn sqlx_err_to_anyhow(e: sqlx::Error) -> anyhow::Error {
+  let message = e.to_string();
+  if message.starts_with("no rows returned") {
+    return anyhow!(FormatError::NotFoundError(message));
+  }
+
+  anyhow!(FormatError::ServerError(message))
+}
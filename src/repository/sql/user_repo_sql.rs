@@ -2,20 +2,34 @@ use crate::context::Context;
 use crate::model::error::FormatError;
 use crate::model::{
   id::Id,
-  user::{FindOneUserCondition, UpdateOneUserField, User, UserMetaData},
+  identity::EMAIL_PROVIDER,
+  user::{FindOneUserCondition, OneTimeCode, OtpChannel, UpdateOneUserField, User, UserMetaData},
 };
 use crate::repository::user_repo::UserRepo;
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use bson::Bson;
-use sqlx::{postgres::PgRow, types::Json, Row};
+use chrono::{DateTime, Utc};
+use sqlx::{postgres::PgRow, types::Json, Done, Row};
+
+const USER_COLUMNS: &str = "
+  id, aud, email, username, phone, phone_confirmed_at, is_anonymous, deleted_at,
+  failed_sign_in_attempts, locked_until, unlock_token, unlock_sent_at,
+  banned_until, ban_reason, role, encrypted_password, raw_user_meta_data,
+  created_at, updated_at, confirmation_token, confirmed_at,
+  invited_at, confirmation_sent_at,
+  recover_token, recover_sent_at, magic_link_token, magic_link_sent_at,
+  email_otp, email_otp_sent_at, email_otp_attempts,
+  phone_otp, phone_otp_sent_at, phone_otp_attempts,
+  email_change, email_change_token_new, email_change_token_current, email_change_sent_at
+";
 
 pub struct UserRepoSql<'a> {
   ctx: &'a Context,
 }
 
 impl UserRepoSql<'_> {
-  pub fn new(ctx: &Context) -> UserRepoSql {
+  pub fn new(ctx: &Context) -> UserRepoSql<'_> {
     UserRepoSql { ctx }
   }
 }
@@ -25,8 +39,11 @@ impl UserRepo for UserRepoSql<'_> {
   async fn insert(&self, user: &User) -> Result<()> {
     sqlx::query(
       r#"
-        INSERT INTO users (id, aud, email, role, encrypted_password, raw_user_meta_data, confirmation_token)
-        VALUES ($1, $2, $3, $4, $5, $6::jsonb, $7)
+        INSERT INTO users (
+          id, aud, email, role, encrypted_password, raw_user_meta_data, confirmation_token,
+          confirmation_sent_at, invited_at, phone, is_anonymous, username
+        )
+        VALUES ($1, $2, $3, $4, $5, $6::jsonb, $7, $8, $9, $10, $11, $12)
       "#,
     )
     .bind(user.id.to_string())
@@ -36,6 +53,11 @@ impl UserRepo for UserRepoSql<'_> {
     .bind(user.encrypted_password.clone())
     .bind(user.raw_user_meta_data().clone())
     .bind(user.confirmation_token.clone())
+    .bind(user.confirmation_sent_at)
+    .bind(user.invited_at)
+    .bind(user.phone.clone())
+    .bind(user.is_anonymous)
+    .bind(user.username.clone())
     .fetch_all(self.ctx.auth_db_pool_ref())
     .await
     .map_err(sqlx_err_to_anyhow)?;
@@ -44,19 +66,12 @@ impl UserRepo for UserRepoSql<'_> {
   }
 
   async fn find(&self) -> Result<Vec<User>> {
-    let users = sqlx::query(
-      r#"
-        SELECT
-          id, aud, email, role, encrypted_password, raw_user_meta_data,
-          created_at, updated_at, confirmation_token, confirmed_at,
-          invited_at, last_sign_in_at, confirmation_sent_at
-        FROM users;
-      "#,
-    )
-    .map(pg_row_to_user)
-    .fetch_all(self.ctx.auth_db_pool_ref())
-    .await
-    .map_err(sqlx_err_to_anyhow)?;
+    let query = format!("SELECT {} FROM users", USER_COLUMNS);
+    let users = sqlx::query(query.as_str())
+      .map(pg_row_to_user)
+      .fetch_all(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)?;
 
     Ok(users)
   }
@@ -66,14 +81,11 @@ impl UserRepo for UserRepoSql<'_> {
 
     let query = format!(
       "
-        SELECT
-          id, aud, email, role, encrypted_password, raw_user_meta_data,
-          created_at, updated_at, confirmation_token, confirmed_at,
-          invited_at, last_sign_in_at, confirmation_sent_at
+        SELECT {}
         FROM users
         WHERE {}
       ",
-      where_clause
+      USER_COLUMNS, where_clause
     );
     let user = sqlx::query(query.as_str())
       .map(pg_row_to_user)
@@ -87,10 +99,10 @@ impl UserRepo for UserRepoSql<'_> {
   async fn update_one(
     &self,
     condition: &FindOneUserCondition,
-    update_data: &Vec<UpdateOneUserField>,
+    update_data: &[UpdateOneUserField],
   ) -> Result<()> {
     let update_content = update_data
-      .into_iter()
+      .iter()
       .map(update_user_field_to_string)
       .collect::<Vec<String>>()
       .join(", ");
@@ -113,6 +125,76 @@ impl UserRepo for UserRepoSql<'_> {
     Ok(())
   }
 
+  async fn take_magic_link(&self, magic_link_token: &str) -> Result<User> {
+    // magic_link_sent_at is kept, the caller checks the expiry with it
+    let query = format!(
+      "
+        UPDATE users
+        SET magic_link_token = NULL, updated_at = NOW()
+        WHERE magic_link_token = $1
+        RETURNING {}
+      ",
+      USER_COLUMNS
+    );
+    let user = sqlx::query(query.as_str())
+      .bind(magic_link_token)
+      .map(pg_row_to_user)
+      .fetch_one(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(user)
+  }
+
+  async fn count_failed_sign_in_attempt(&self, id: &Id) -> Result<i32> {
+    let attempts = sqlx::query(
+      r#"
+        UPDATE users
+        SET failed_sign_in_attempts = failed_sign_in_attempts + 1
+        WHERE id = $1
+        RETURNING failed_sign_in_attempts
+      "#,
+    )
+    .bind(id.to_string())
+    .map(|row: PgRow| row.get("failed_sign_in_attempts"))
+    .fetch_one(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(attempts)
+  }
+
+  async fn count_otp_attempt(
+    &self,
+    id: &Id,
+    channel: OtpChannel,
+    max_attempts: i32,
+  ) -> Result<OneTimeCode> {
+    let otp_column = channel.name();
+    let query = format!(
+      "
+        UPDATE users
+        SET {0}_attempts = {0}_attempts + 1
+        WHERE id = $1 AND {0} IS NOT NULL AND {0}_attempts < $2
+        RETURNING {0}, {0}_sent_at, {0}_attempts
+      ",
+      otp_column
+    );
+    let otp = sqlx::query(query.as_str())
+      .bind(id.to_string())
+      .bind(max_attempts)
+      .map(|row: PgRow| OneTimeCode {
+        code: row.get(otp_column),
+        sent_at: row.get(format!("{}_sent_at", otp_column).as_str()),
+        attempts: row.get(format!("{}_attempts", otp_column).as_str()),
+      })
+      .fetch_one(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(otp)
+  }
+
   async fn delete_one(&self, condition: &FindOneUserCondition) -> Result<()> {
     let where_clause = find_one_user_condition_to_string(condition);
     let query = format!(" DELETE from users WHERE {}", where_clause);
@@ -122,27 +204,53 @@ impl UserRepo for UserRepoSql<'_> {
 
     Ok(())
   }
+
+  async fn purge_deleted(&self, deleted_before: DateTime<Utc>) -> Result<u64> {
+    let deleted_rows = sqlx::query(
+      r#"
+        DELETE FROM users
+        WHERE deleted_at IS NOT NULL AND deleted_at < $1
+      "#,
+    )
+    .bind(deleted_before)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(deleted_rows.rows_affected())
+  }
 }
 
 fn find_one_user_condition_to_string(find_one_condition: &FindOneUserCondition) -> String {
   match find_one_condition {
-    FindOneUserCondition::Id(id) => format!("id = '{}'", id.to_string()),
-    FindOneUserCondition::Email(email) => format!("email = '{}'", email),
+    FindOneUserCondition::Id(id) => format!("id = {}", quote(&id.to_string())),
+    FindOneUserCondition::Email(email) => format!(
+      "(email = {email} OR id IN (
+        SELECT user_id FROM identities
+        WHERE provider = '{provider}' AND provider_id = LOWER({email}) AND verified_at IS NOT NULL
+      ))",
+      email = quote(email),
+      provider = EMAIL_PROVIDER
+    ),
     FindOneUserCondition::ConfirmationToken(token) => {
-      format!("confirmation_token = '{}'", token)
+      format!("confirmation_token = {}", quote(token))
+    }
+    FindOneUserCondition::RecoverToken(token) => format!("recover_token = {}", quote(token)),
+    FindOneUserCondition::Phone(phone) => format!("phone = {}", quote(phone)),
+    FindOneUserCondition::EmailChangeToken(token) => format!(
+      "(email_change_token_new = {token} OR email_change_token_current = {token})",
+      token = quote(token)
+    ),
+    FindOneUserCondition::UnlockToken(token) => format!("unlock_token = {}", quote(token)),
+    FindOneUserCondition::Username(username) => {
+      format!("LOWER(username) = LOWER({})", quote(username))
     }
   }
 }
 
 fn update_user_field_to_string(user_field: &UpdateOneUserField) -> String {
   match user_field {
-    UpdateOneUserField::ConfirmationToken(token) => {
-      if token.is_some() {
-        format!("confirmation_token = '{}'", token.clone().unwrap())
-      } else {
-        "confirmation_token = NULL".to_string()
-      }
-    }
+    UpdateOneUserField::ConfirmationToken(token) => nullable_text("confirmation_token", token),
     UpdateOneUserField::ConfirmedAt(time) => {
       if time.is_some() {
         "confirmed_at = NOW()".to_string()
@@ -150,9 +258,61 @@ fn update_user_field_to_string(user_field: &UpdateOneUserField) -> String {
         "confirmed_at = NULL".to_string()
       }
     }
+    UpdateOneUserField::ConfirmationSentAt(time) => nullable_time("confirmation_sent_at", time),
     UpdateOneUserField::EncryptedPassword(password) => {
       format!("encrypted_password = '{}'", password)
     }
+    UpdateOneUserField::RecoverToken(token) => nullable_text("recover_token", token),
+    UpdateOneUserField::RecoverSentAt(time) => nullable_time("recover_sent_at", time),
+    UpdateOneUserField::MagicLinkToken(token) => nullable_text("magic_link_token", token),
+    UpdateOneUserField::MagicLinkSentAt(time) => nullable_time("magic_link_sent_at", time),
+    UpdateOneUserField::Otp(channel, otp) => format!(
+      "{}, {}, {}_attempts = {}",
+      nullable_text(channel.name(), &otp.code),
+      nullable_time(&format!("{}_sent_at", channel.name()), &otp.sent_at),
+      channel.name(),
+      otp.attempts
+    ),
+    UpdateOneUserField::PhoneConfirmedAt(time) => nullable_time("phone_confirmed_at", time),
+    UpdateOneUserField::Email(email) => format!("email = {}", quote(email)),
+    UpdateOneUserField::EmailChange(email) => nullable_text("email_change", email),
+    UpdateOneUserField::EmailChangeTokenNew(token) => {
+      nullable_text("email_change_token_new", token)
+    }
+    UpdateOneUserField::EmailChangeTokenCurrent(token) => {
+      nullable_text("email_change_token_current", token)
+    }
+    UpdateOneUserField::EmailChangeSentAt(time) => nullable_time("email_change_sent_at", time),
+    UpdateOneUserField::IsAnonymous(is_anonymous) => format!("is_anonymous = {}", is_anonymous),
+    UpdateOneUserField::DeletedAt(time) => nullable_time("deleted_at", time),
+    UpdateOneUserField::FailedSignInAttempts(attempts) => {
+      format!("failed_sign_in_attempts = {}", attempts)
+    }
+    UpdateOneUserField::LockedUntil(time) => nullable_time("locked_until", time),
+    UpdateOneUserField::UnlockToken(token) => nullable_text("unlock_token", token),
+    UpdateOneUserField::UnlockSentAt(time) => nullable_time("unlock_sent_at", time),
+    UpdateOneUserField::BannedUntil(time) => nullable_time("banned_until", time),
+    UpdateOneUserField::BanReason(reason) => nullable_text("ban_reason", reason),
+    UpdateOneUserField::Username(username) => nullable_text("username", username),
+  }
+}
+
+fn nullable_text(column: &str, value: &Option<String>) -> String {
+  match value {
+    Some(value) => format!("{} = {}", column, quote(value)),
+    None => format!("{} = NULL", column),
+  }
+}
+
+// quote a text value which comes from the user, e.g. an email address
+fn quote(value: &str) -> String {
+  format!("'{}'", value.replace('\'', "''"))
+}
+
+fn nullable_time(column: &str, value: &Option<DateTime<Utc>>) -> String {
+  match value {
+    Some(value) => format!("{} = '{}'", column, value.to_rfc3339()),
+    None => format!("{} = NULL", column),
   }
 }
 
@@ -161,6 +321,17 @@ fn pg_row_to_user(row: PgRow) -> User {
     id: Id::new(row.get("id")),
     aud: row.get("aud"),
     email: row.get("email"),
+    username: row.get("username"),
+    phone: row.get("phone"),
+    phone_confirmed_at: row.get("phone_confirmed_at"),
+    is_anonymous: row.get("is_anonymous"),
+    deleted_at: row.get("deleted_at"),
+    failed_sign_in_attempts: row.get("failed_sign_in_attempts"),
+    locked_until: row.get("locked_until"),
+    unlock_token: row.get("unlock_token"),
+    unlock_sent_at: row.get("unlock_sent_at"),
+    banned_until: row.get("banned_until"),
+    ban_reason: row.get("ban_reason"),
     encrypted_password: row.get("encrypted_password"),
     role: row.get("role"),
     user_meta_data: UserMetaData::new(
@@ -174,16 +345,40 @@ fn pg_row_to_user(row: PgRow) -> User {
     confirmation_token: row.get("confirmation_token"),
     confirmed_at: row.get("confirmed_at"),
     invited_at: row.get("invited_at"),
-    last_sign_in_at: row.get("last_sign_in_at"),
     confirmation_sent_at: row.get("confirmation_sent_at"),
+    recover_token: row.get("recover_token"),
+    recover_sent_at: row.get("recover_sent_at"),
+    magic_link_token: row.get("magic_link_token"),
+    magic_link_sent_at: row.get("magic_link_sent_at"),
+    email_otp: OneTimeCode {
+      code: row.get("email_otp"),
+      sent_at: row.get("email_otp_sent_at"),
+      attempts: row.get("email_otp_attempts"),
+    },
+    phone_otp: OneTimeCode {
+      code: row.get("phone_otp"),
+      sent_at: row.get("phone_otp_sent_at"),
+      attempts: row.get("phone_otp_attempts"),
+    },
+    email_change: row.get("email_change"),
+    email_change_token_new: row.get("email_change_token_new"),
+    email_change_token_current: row.get("email_change_token_current"),
+    email_change_sent_at: row.get("email_change_sent_at"),
   }
 }
 
+const UNIQUE_VIOLATION_CODE: &str = "23505";
+
 fn sqlx_err_to_anyhow(e: sqlx::Error) -> anyhow::Error {
   let message = e.to_string();
   if message.starts_with("no rows returned") {
     return anyhow!(FormatError::NotFoundError(message));
   }
 
-  return anyhow!(FormatError::ServerError(message));
+  let db_error_code = e.as_database_error().and_then(|db_error| db_error.code());
+  if db_error_code.as_deref() == Some(UNIQUE_VIOLATION_CODE) {
+    return anyhow!(FormatError::DuplicateError(message));
+  }
+
+  anyhow!(FormatError::ServerError(message))
 }
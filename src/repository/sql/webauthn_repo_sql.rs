@@ -0,0 +1,167 @@
+use super::super::webauthn_repo::WebauthnRepo;
+use crate::context::Context;
+use crate::model::error::FormatError;
+use crate::model::id::Id;
+use crate::model::webauthn::{Ceremony, WebauthnChallenge, WebauthnCredential};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use sqlx::{postgres::PgRow, Row};
+
+const CREDENTIAL_COLUMNS: &str = "
+  id, factor_id, user_id, credential_id, public_key, algorithm, sign_count, transports
+";
+
+pub struct WebauthnRepoSql<'a> {
+  ctx: &'a Context,
+}
+
+impl WebauthnRepoSql<'_> {
+  pub fn new(ctx: &Context) -> WebauthnRepoSql<'_> {
+    WebauthnRepoSql { ctx }
+  }
+}
+
+#[async_trait]
+impl WebauthnRepo for WebauthnRepoSql<'_> {
+  async fn insert_challenge(&self, challenge: &WebauthnChallenge) -> Result<()> {
+    sqlx::query(
+      r#"
+        INSERT INTO webauthn_challenges (id, user_id, challenge, ceremony, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+      "#,
+    )
+    .bind(challenge.id.to_string())
+    .bind(
+      challenge
+        .user_id
+        .as_ref()
+        .map(|user_id| user_id.to_string()),
+    )
+    .bind(challenge.challenge.clone())
+    .bind(challenge.ceremony.name())
+    .bind(challenge.expires_at)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
+  async fn take_challenge(&self, id: &Id) -> Result<WebauthnChallenge> {
+    sqlx::query(
+      r#"
+        DELETE FROM webauthn_challenges
+        WHERE id = $1
+        RETURNING id, user_id, challenge, ceremony, expires_at
+      "#,
+    )
+    .bind(id.to_string())
+    .map(|row: PgRow| WebauthnChallenge {
+      id: Id::new(row.get("id")),
+      user_id: row.get::<Option<String>, &str>("user_id").map(Id::new),
+      challenge: row.get("challenge"),
+      ceremony: Ceremony::from_name(row.get("ceremony")).unwrap_or(Ceremony::Authentication),
+      expires_at: row.get("expires_at"),
+    })
+    .fetch_one(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn insert_credential(&self, credential: &WebauthnCredential) -> Result<()> {
+    sqlx::query(
+      r#"
+        INSERT INTO webauthn_credentials (
+          id, factor_id, user_id, credential_id, public_key, algorithm, sign_count, transports
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+      "#,
+    )
+    .bind(credential.id.to_string())
+    .bind(credential.factor_id.to_string())
+    .bind(credential.user_id.to_string())
+    .bind(credential.credential_id.clone())
+    .bind(credential.public_key.clone())
+    .bind(credential.algorithm)
+    .bind(credential.sign_count)
+    .bind(credential.transports.clone())
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+
+  async fn find_credentials_by_user_id(&self, user_id: &Id) -> Result<Vec<WebauthnCredential>> {
+    let query = format!(
+      "SELECT {} FROM webauthn_credentials WHERE user_id = $1 ORDER BY created_at",
+      CREDENTIAL_COLUMNS
+    );
+    sqlx::query(query.as_str())
+      .bind(user_id.to_string())
+      .map(pg_row_to_credential)
+      .fetch_all(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn find_credential(&self, credential_id: &str) -> Result<WebauthnCredential> {
+    let query = format!(
+      "SELECT {} FROM webauthn_credentials WHERE credential_id = $1",
+      CREDENTIAL_COLUMNS
+    );
+    sqlx::query(query.as_str())
+      .bind(credential_id)
+      .map(pg_row_to_credential)
+      .fetch_one(self.ctx.auth_db_pool_ref())
+      .await
+      .map_err(sqlx_err_to_anyhow)
+  }
+
+  async fn update_sign_count(&self, id: &Id, sign_count: i64) -> Result<()> {
+    sqlx::query(
+      r#"
+        UPDATE webauthn_credentials
+        SET sign_count = $2, last_used_at = NOW()
+        WHERE id = $1
+      "#,
+    )
+    .bind(id.to_string())
+    .bind(sign_count)
+    .execute(self.ctx.auth_db_pool_ref())
+    .await
+    .map_err(sqlx_err_to_anyhow)?;
+
+    Ok(())
+  }
+}
+
+fn pg_row_to_credential(row: PgRow) -> WebauthnCredential {
+  WebauthnCredential {
+    id: Id::new(row.get("id")),
+    factor_id: Id::new(row.get("factor_id")),
+    user_id: Id::new(row.get("user_id")),
+    credential_id: row.get("credential_id"),
+    public_key: row.get("public_key"),
+    algorithm: row.get("algorithm"),
+    sign_count: row.get("sign_count"),
+    transports: row.get("transports"),
+  }
+}
+
+const UNIQUE_VIOLATION_CODE: &str = "23505";
+
+fn sqlx_err_to_anyhow(e: sqlx::Error) -> anyhow::Error {
+  let message = e.to_string();
+  if message.starts_with("no rows returned") {
+    return anyhow!(FormatError::NotFoundError(message));
+  }
+
+  // the credential id is registered already
+  let db_error_code = e.as_database_error().and_then(|db_error| db_error.code());
+  if db_error_code.as_deref() == Some(UNIQUE_VIOLATION_CODE) {
+    return anyhow!(FormatError::DuplicateError(message));
+  }
+
+  anyhow!(FormatError::ServerError(message))
+}
@@ -0,0 +1,14 @@
+use crate::model::id::Id;
+use crate::model::mfa::TrustedDevice;
+use anyhow::Result;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait TrustedDeviceRepo {
+  async fn insert(&self, device: &TrustedDevice) -> Result<()>;
+  async fn find_by_user_id(&self, user_id: &Id) -> Result<Vec<TrustedDevice>>;
+  async fn find_one(&self, user_id: &Id, id: &Id) -> Result<TrustedDevice>;
+  async fn touch(&self, id: &Id) -> Result<()>;
+  async fn delete_one(&self, user_id: &Id, id: &Id) -> Result<()>;
+  async fn delete_by_user_id(&self, user_id: &Id) -> Result<()>;
+}
@@ -1,6 +1,8 @@
-use crate::model::user::{FindOneUserCondition, UpdateOneUserField, User};
+use crate::model::id::Id;
+use crate::model::user::{FindOneUserCondition, OneTimeCode, OtpChannel, UpdateOneUserField, User};
 use anyhow::Result;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 
 #[async_trait]
 pub trait UserRepo {
@@ -10,7 +12,24 @@ pub trait UserRepo {
   async fn update_one(
     &self,
     condition: &FindOneUserCondition,
-    update_data: &Vec<UpdateOneUserField>,
+    update_data: &[UpdateOneUserField],
   ) -> Result<()>;
+  // consume the magic link in one statement, so that it can be used only once.
+  // It returns the user as found, and fails with NotFoundError when the link was used.
+  async fn take_magic_link(&self, magic_link_token: &str) -> Result<User>;
+  // add a failed sign in attempt in one statement, it returns the number of failed attempts
+  async fn count_failed_sign_in_attempt(&self, id: &Id) -> Result<i32>;
+  // count an attempt of the one-time code of the channel in one statement, before
+  // the code is checked. It returns the code with the counted attempt, and fails with
+  // NotFoundError when there is no code or no attempt left.
+  async fn count_otp_attempt(
+    &self,
+    id: &Id,
+    channel: OtpChannel,
+    max_attempts: i32,
+  ) -> Result<OneTimeCode>;
   async fn delete_one(&self, condition: &FindOneUserCondition) -> Result<()>;
+  // delete for good the users who were (soft) deleted before the given time,
+  // it returns the number of deleted users
+  async fn purge_deleted(&self, deleted_before: DateTime<Utc>) -> Result<u64>;
 }
@@ -0,0 +1,16 @@
+use crate::model::id::Id;
+use crate::model::webauthn::{WebauthnChallenge, WebauthnCredential};
+use anyhow::Result;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait WebauthnRepo {
+  async fn insert_challenge(&self, challenge: &WebauthnChallenge) -> Result<()>;
+  // find and delete the challenge, so it cannot be answered twice
+  async fn take_challenge(&self, id: &Id) -> Result<WebauthnChallenge>;
+  async fn insert_credential(&self, credential: &WebauthnCredential) -> Result<()>;
+  async fn find_credentials_by_user_id(&self, user_id: &Id) -> Result<Vec<WebauthnCredential>>;
+  async fn find_credential(&self, credential_id: &str) -> Result<WebauthnCredential>;
+  // remember the sign count of a successful assertion
+  async fn update_sign_count(&self, id: &Id, sign_count: i64) -> Result<()>;
+}
@@ -1,2 +1,7 @@
+pub mod identity_usecase;
+pub mod mfa_usecase;
+pub mod oauth_client_usecase;
+pub mod oauth_usecase;
 pub mod token_usecase;
 pub mod user_usecase;
+pub mod webauthn_usecase;
@@ -0,0 +1,127 @@
+use crate::context::Context;
+use crate::model::crypto::hash_token;
+use crate::model::error::{FormatError, SpecificError};
+use crate::model::id::Id;
+use crate::model::identity::{Identity, EMAIL_PROVIDER};
+use crate::model::user::{validate_email, FindOneUserCondition, User};
+use crate::repository::identity_repo::IdentityRepo;
+use crate::repository::mailer::Mailer;
+use crate::repository::user_repo::UserRepo;
+use anyhow::{bail, Result};
+
+pub async fn find_identities<I: IdentityRepo>(
+  identity_repo: &I,
+  user_id: &Id,
+) -> Result<Vec<Identity>> {
+  identity_repo.find_by_user_id(user_id).await
+}
+
+// link a secondary email address to the user, it is usable after
+// the confirmation token sent to the address is verified with the IDENTITY type
+pub async fn add_email<R: UserRepo, I: IdentityRepo, M: Mailer>(
+  user_repo: &R,
+  identity_repo: &I,
+  mailer: &M,
+  user_id: &Id,
+  email: String,
+) -> Result<Identity> {
+  validate_email(&email)?;
+  let user = user_repo
+    .find_one(&FindOneUserCondition::Id(user_id.clone()))
+    .await?;
+  if user.is_anonymous {
+    bail!(FormatError::BadRequest(
+      "an anonymous user must link an email address and a password first".to_string()
+    ));
+  }
+
+  match user_repo
+    .find_one(&FindOneUserCondition::Email(email.clone()))
+    .await
+  {
+    Ok(_) => bail!(FormatError::DuplicateError(
+      "A user with this email address has already been registered".to_string()
+    )),
+    Err(err) if err.get_code() == "NOT_FOUND" => {}
+    Err(err) => return Err(err),
+  }
+
+  let (identity, confirmation_token) = Identity::new_email(user_id, email.clone());
+  // nobody has verified the address yet, so a pending identity must not block it
+  identity_repo
+    .delete_unverified(&identity.provider, &identity.provider_id)
+    .await?;
+  identity_repo.insert(&identity).await?;
+
+  mailer
+    .send(
+      &email,
+      "Confirm your email address",
+      &format!(
+        "Use this token to add this email address to your account: {}",
+        confirmation_token
+      ),
+    )
+    .await?;
+
+  Ok(identity)
+}
+
+pub async fn remove_identity<I: IdentityRepo>(
+  identity_repo: &I,
+  user_id: &Id,
+  identity_id: &Id,
+) -> Result<()> {
+  identity_repo.delete_one(user_id, identity_id).await
+}
+
+// verify an email identity, and return its user
+pub async fn confirm_identity<R: UserRepo, I: IdentityRepo>(
+  ctx: &Context,
+  user_repo: &R,
+  identity_repo: &I,
+  token: &str,
+) -> Result<User> {
+  let confirmation_token_exp = ctx
+    .settings
+    .get::<i64>("confirmation_token_exp")
+    .expect("confirmation_token_exp must set");
+
+  let identity = identity_repo
+    .find_one_by_confirmation_token(&hash_token(token))
+    .await?;
+
+  if identity.is_confirmation_token_expired(confirmation_token_exp) {
+    bail!(FormatError::TokenExpired(
+      "confirmation token has expired".to_string()
+    ));
+  }
+
+  // the address may have been registered since the token was sent
+  if let Some(email) = &identity.email {
+    match user_repo
+      .find_one(&FindOneUserCondition::Email(email.clone()))
+      .await
+    {
+      Ok(_) => bail!(FormatError::DuplicateError(
+        "A user with this email address has already been registered".to_string()
+      )),
+      Err(err) if err.get_code() == "NOT_FOUND" => {}
+      Err(err) => return Err(err),
+    }
+  }
+
+  identity_repo.mark_verified(&identity.id).await?;
+
+  user_repo
+    .find_one(&FindOneUserCondition::Id(identity.user_id.clone()))
+    .await
+}
+
+// remember when an email identity was used to sign in,
+// nothing is updated when the email address is the primary one
+pub async fn touch_email_identity<I: IdentityRepo>(identity_repo: &I, email: &str) -> Result<()> {
+  identity_repo
+    .touch_last_used(EMAIL_PROVIDER, &email.to_lowercase())
+    .await
+}
@@ -0,0 +1,281 @@
+use super::token_usecase::{
+  account_locked_error, count_failed_sign_in, create_token_output, send_email_factor_code,
+  verify_email_factor_code, verify_totp_code,
+};
+use crate::context::Context;
+use crate::model::error::{FormatError, SpecificError};
+use crate::model::id::Id;
+use crate::model::mfa::{
+  new_recovery_codes, totp_uri, EnrollTotpOutput, FactorType, MfaFactor, RecoveryCodesStatus,
+  TrustedDevice,
+};
+use crate::model::token::{AuthMethod, Claims, CreateTokenOutput, SessionAuth, AAL2};
+use crate::model::user::FindOneUserCondition;
+use crate::repository::mailer::Mailer;
+use crate::repository::mfa_factor_repo::MfaFactorRepo;
+use crate::repository::recovery_code_repo::RecoveryCodeRepo;
+use crate::repository::refresh_token_repo::RefreshTokenRepo;
+use crate::repository::trusted_device_repo::TrustedDeviceRepo;
+use crate::repository::user_repo::UserRepo;
+use anyhow::{bail, Result};
+
+pub async fn find_factors<F: MfaFactorRepo>(
+  factor_repo: &F,
+  user_id: &Id,
+) -> Result<Vec<MfaFactor>> {
+  factor_repo.find_by_user_id(user_id).await
+}
+
+// once the user has a verified factor, managing the factors requires an aal2 session,
+// otherwise a stolen password would be enough to add or remove a second factor.
+// It returns whether the user has a verified factor.
+pub async fn check_factor_management<F: MfaFactorRepo>(
+  factor_repo: &F,
+  claims: &Claims,
+) -> Result<bool> {
+  let has_verified_factor = factor_repo.has_verified(&claims.user_id).await?;
+  if has_verified_factor && claims.aal != AAL2 {
+    bail!(FormatError::Forbidden(
+      "verify a second factor before managing the factors".to_string()
+    ));
+  }
+
+  Ok(has_verified_factor)
+}
+
+// start enrolling a TOTP factor, it is usable after verify_factor succeeds.
+// A previous unverified TOTP factor is replaced.
+pub async fn enroll_totp<R: UserRepo, F: MfaFactorRepo, C: RecoveryCodeRepo>(
+  ctx: &Context,
+  user_repo: &R,
+  factor_repo: &F,
+  recovery_code_repo: &C,
+  claims: &Claims,
+  friendly_name: Option<String>,
+) -> Result<EnrollTotpOutput> {
+  let user_id = &claims.user_id;
+  let encryption_key = ctx
+    .settings
+    .get::<String>("mfa_encryption_key")
+    .expect("mfa_encryption_key must set");
+  let issuer = ctx
+    .settings
+    .get::<String>("mfa_totp_issuer")
+    .expect("mfa_totp_issuer must set");
+
+  let has_verified_factor = check_factor_management(factor_repo, claims).await?;
+  let user = user_repo
+    .find_one(&FindOneUserCondition::Id(user_id.clone()))
+    .await?;
+  if user.is_anonymous {
+    bail!(FormatError::BadRequest(
+      "an anonymous user cannot enroll a second factor".to_string()
+    ));
+  }
+
+  factor_repo
+    .delete_unverified(user_id, FactorType::Totp)
+    .await?;
+  let (factor, secret) = MfaFactor::new_totp(user_id, friendly_name, &encryption_key)?;
+  factor_repo.insert(&factor).await?;
+
+  let fallback_name = user.id.to_string();
+  let account_name = user
+    .email
+    .or(user.username)
+    .or(user.phone)
+    .unwrap_or(fallback_name);
+  let uri = totp_uri(&issuer, &account_name, &secret);
+
+  // only the first factor comes with recovery codes, so a lost device does not lock the user out.
+  // Later codes are given by regenerate_recovery_codes only.
+  let recovery_codes_status = recovery_code_repo.find_status(user_id).await?;
+  let recovery_codes = if !has_verified_factor && recovery_codes_status.remaining == 0 {
+    let (codes, hashed_codes) = new_recovery_codes();
+    recovery_code_repo
+      .replace_all(user_id, &hashed_codes)
+      .await?;
+    Some(codes)
+  } else {
+    None
+  };
+
+  Ok(EnrollTotpOutput {
+    factor,
+    secret,
+    uri,
+    recovery_codes,
+  })
+}
+
+// start enrolling an email factor, a code is sent to the email address of the user
+// and the factor is usable after verify_factor succeeds with it.
+// A previous unverified email factor is replaced.
+pub async fn enroll_email_factor<R: UserRepo, F: MfaFactorRepo, M: Mailer>(
+  ctx: &Context,
+  user_repo: &R,
+  factor_repo: &F,
+  mailer: &M,
+  claims: &Claims,
+  friendly_name: Option<String>,
+) -> Result<MfaFactor> {
+  let user_id = &claims.user_id;
+  check_factor_management(factor_repo, claims).await?;
+  let user = user_repo
+    .find_one(&FindOneUserCondition::Id(user_id.clone()))
+    .await?;
+  if user.is_anonymous {
+    bail!(FormatError::BadRequest(
+      "an anonymous user cannot enroll a second factor".to_string()
+    ));
+  }
+  if user.email.is_none() {
+    bail!(FormatError::BadRequest(
+      "add an email address before enrolling an email factor".to_string()
+    ));
+  }
+
+  factor_repo
+    .delete_unverified(user_id, FactorType::Email)
+    .await?;
+  let factor = MfaFactor::new_email(user_id, friendly_name);
+  factor_repo.insert(&factor).await?;
+
+  send_email_factor_code(ctx, factor_repo, mailer, &user, &factor).await?;
+
+  Ok(factor)
+}
+
+// send a new code of an email factor of the signed in user, e.g. to finish the enrolment
+pub async fn send_factor_code<R: UserRepo, F: MfaFactorRepo, M: Mailer>(
+  ctx: &Context,
+  user_repo: &R,
+  factor_repo: &F,
+  mailer: &M,
+  user_id: &Id,
+  factor_id: &Id,
+) -> Result<()> {
+  let factor = factor_repo.find_one(user_id, factor_id).await?;
+  let user = user_repo
+    .find_one(&FindOneUserCondition::Id(user_id.clone()))
+    .await?;
+
+  send_email_factor_code(ctx, factor_repo, mailer, &user, &factor).await
+}
+
+pub async fn find_recovery_codes_status<C: RecoveryCodeRepo>(
+  recovery_code_repo: &C,
+  user_id: &Id,
+) -> Result<RecoveryCodesStatus> {
+  recovery_code_repo.find_status(user_id).await
+}
+
+// replace the recovery codes with a new set, the old codes cannot be used anymore
+pub async fn regenerate_recovery_codes<F: MfaFactorRepo, C: RecoveryCodeRepo>(
+  factor_repo: &F,
+  recovery_code_repo: &C,
+  claims: &Claims,
+) -> Result<Vec<String>> {
+  if !factor_repo.has_verified(&claims.user_id).await? {
+    bail!(FormatError::BadRequest(
+      "enroll a second factor before creating recovery codes".to_string()
+    ));
+  }
+
+  if claims.aal != AAL2 {
+    bail!(FormatError::Forbidden(
+      "verify a second factor before creating recovery codes".to_string()
+    ));
+  }
+
+  let (codes, hashed_codes) = new_recovery_codes();
+  recovery_code_repo
+    .replace_all(&claims.user_id, &hashed_codes)
+    .await?;
+
+  Ok(codes)
+}
+
+// verify a TOTP or email code of the factor. A verified factor upgrades the session
+// to aal2. An unverified factor finishes its enrolment and returns None, a factor
+// enrolled in this session proves nothing more than the session itself.
+#[allow(clippy::too_many_arguments)]
+pub async fn verify_factor<R: UserRepo, K: RefreshTokenRepo, F: MfaFactorRepo, M: Mailer>(
+  ctx: &Context,
+  user_repo: &R,
+  refresh_token_repo: &K,
+  factor_repo: &F,
+  mailer: &M,
+  claims: &Claims,
+  factor_id: &Id,
+  code: &str,
+) -> Result<Option<CreateTokenOutput>> {
+  let user = user_repo
+    .find_one(&FindOneUserCondition::Id(claims.user_id.clone()))
+    .await?;
+  if user.is_locked() {
+    return Err(account_locked_error(&user));
+  }
+
+  let factor = factor_repo.find_one(&claims.user_id, factor_id).await?;
+  let verified = match factor.factor_type {
+    FactorType::Totp => verify_totp_code(ctx, factor_repo, &claims.user_id, Some(factor_id), code)
+      .await
+      .map(|_| AuthMethod::Totp),
+    FactorType::Email => verify_email_factor_code(ctx, factor_repo, &factor, code)
+      .await
+      .map(|_| AuthMethod::OtpEmail),
+    FactorType::Webauthn => bail!(FormatError::BadRequest(
+      "a passkey is verified by signing in with it".to_string()
+    )),
+  };
+  let method = match verified {
+    Ok(method) => method,
+    // wrong codes count as failed sign in attempts, as in an MFA challenge
+    Err(err) if err.get_code() == "UNAUTHENTICATED" => {
+      return Err(count_failed_sign_in(ctx, user_repo, mailer, user, err).await?)
+    }
+    Err(err) => return Err(err),
+  };
+
+  if !factor.is_verified() {
+    return Ok(None);
+  }
+
+  let create_token_output = create_token_output(
+    ctx,
+    refresh_token_repo,
+    &user,
+    SessionAuth::extend(&claims.amr, method),
+  )
+  .await?;
+
+  Ok(Some(create_token_output))
+}
+
+pub async fn unenroll_factor<F: MfaFactorRepo>(
+  factor_repo: &F,
+  claims: &Claims,
+  factor_id: &Id,
+) -> Result<()> {
+  factor_repo.find_one(&claims.user_id, factor_id).await?;
+  check_factor_management(factor_repo, claims).await?;
+
+  factor_repo.delete_one(&claims.user_id, factor_id).await
+}
+
+pub async fn find_trusted_devices<D: TrustedDeviceRepo>(
+  device_repo: &D,
+  user_id: &Id,
+) -> Result<Vec<TrustedDevice>> {
+  device_repo.find_by_user_id(user_id).await
+}
+
+// the device token of a revoked device does not skip the second factor anymore
+pub async fn revoke_trusted_device<D: TrustedDeviceRepo>(
+  device_repo: &D,
+  user_id: &Id,
+  device_id: &Id,
+) -> Result<()> {
+  device_repo.delete_one(user_id, device_id).await
+}
@@ -0,0 +1,83 @@
+use crate::context::Context;
+use crate::model::error::FormatError;
+use crate::model::id::Id;
+use crate::model::oauth::{
+  CreateOAuthClientInput, CreateOAuthClientOutput, OAuthClient, UpdateOAuthClientInput,
+};
+use crate::repository::oauth_client_repo::OAuthClientRepo;
+use anyhow::{bail, Result};
+
+pub async fn find_oauth_clients<O: OAuthClientRepo>(client_repo: &O) -> Result<Vec<OAuthClient>> {
+  client_repo.find_many().await
+}
+
+pub async fn find_oauth_client<O: OAuthClientRepo>(
+  client_repo: &O,
+  id: &Id,
+) -> Result<OAuthClient> {
+  client_repo.find_one(id).await
+}
+
+// the access tokens of ez-auth are only accepted with its own aud
+fn check_client_audience(ctx: &Context, client: &OAuthClient) -> Result<()> {
+  let aud = ctx.settings.get::<String>("aud").expect("aud must set");
+  if client.audience() == aud {
+    bail!(FormatError::ValidationFailed(
+      "aud of the client must differ from the aud of ez-auth".to_string()
+    ));
+  }
+
+  Ok(())
+}
+
+// register a client, the secret of a confidential client is only returned here
+pub async fn create_oauth_client<O: OAuthClientRepo>(
+  ctx: &Context,
+  client_repo: &O,
+  input: CreateOAuthClientInput,
+) -> Result<CreateOAuthClientOutput> {
+  let (client, client_secret) = OAuthClient::new(input)?;
+  check_client_audience(ctx, &client)?;
+  client_repo.insert(&client).await?;
+
+  Ok(CreateOAuthClientOutput {
+    client,
+    client_secret,
+  })
+}
+
+pub async fn update_oauth_client<O: OAuthClientRepo>(
+  ctx: &Context,
+  client_repo: &O,
+  input: UpdateOAuthClientInput,
+) -> Result<OAuthClient> {
+  let mut client = client_repo.find_one(&input.id).await?;
+  client.update(input)?;
+  check_client_audience(ctx, &client)?;
+  client_repo.update(&client).await?;
+
+  Ok(client)
+}
+
+// replace the secret of a confidential client, the old secret stops working at once
+pub async fn rotate_oauth_client_secret<O: OAuthClientRepo>(
+  client_repo: &O,
+  id: &Id,
+) -> Result<String> {
+  let mut client = client_repo.find_one(id).await?;
+  if client.secret.is_none() {
+    bail!(FormatError::BadRequest(
+      "a public client does not have a secret".to_string()
+    ));
+  }
+
+  let secret = client.renew_secret();
+  client_repo.update(&client).await?;
+
+  Ok(secret)
+}
+
+// the sessions and the authorization codes of the client are deleted with it
+pub async fn delete_oauth_client<O: OAuthClientRepo>(client_repo: &O, id: &Id) -> Result<()> {
+  client_repo.delete_one(id).await
+}
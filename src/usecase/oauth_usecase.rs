@@ -0,0 +1,542 @@
+use super::token_usecase::{
+  auth_by_refresh_token, create_access_token, create_client_token_output, create_id_token,
+};
+use crate::context::Context;
+use crate::model::crypto::hash_token;
+use crate::model::error::{FormatError, SpecificError};
+use crate::model::oauth::{
+  parse_scope, AuthorizationCode, AuthorizeConsent, AuthorizeParams, ClientCredentials,
+  OAuthClient, OAuthError, OAuthGrantType, TokenRequest, TokenResponse, CODE_RESPONSE_TYPE,
+  S256_CODE_CHALLENGE_METHOD,
+};
+use crate::model::oidc::{
+  at_hash, IdTokenClaims, Jwk, Jwks, OpenIdConfiguration, EMAIL_SCOPE, OPENID_SCOPE,
+};
+use crate::model::token::{Claims, CreateTokenOutput, SessionAuth};
+use crate::model::user::{FindOneUserCondition, User};
+use crate::repository::authorization_code_repo::AuthorizationCodeRepo;
+use crate::repository::oauth_client_repo::OAuthClientRepo;
+use crate::repository::refresh_token_repo::RefreshTokenRepo;
+use crate::repository::user_repo::UserRepo;
+use anyhow::{anyhow, bail, Result};
+use chrono::{TimeZone, Utc};
+use url::Url;
+
+// the client and the redirect URI are checked first. Their errors are shown to the user,
+// an unverified redirect URI must not receive anything.
+async fn find_authorize_client<O: OAuthClientRepo>(
+  client_repo: &O,
+  params: &AuthorizeParams,
+) -> Result<OAuthClient> {
+  let client = match client_repo.find_one(&params.client_id).await {
+    Ok(client) => client,
+    Err(err) if err.get_code() == "NOT_FOUND" => bail!(OAuthError::InvalidClient(
+      "client is not registered".to_string()
+    )),
+    Err(err) => return Err(err),
+  };
+
+  if !client.is_redirect_uri_allowed(&params.redirect_uri) {
+    bail!(OAuthError::InvalidRequest(
+      "redirect_uri is not registered for the client".to_string()
+    ));
+  }
+
+  Ok(client)
+}
+
+// the other errors of the request are sent to the redirect URI, it returns the code challenge
+fn check_authorize_params(
+  client: &OAuthClient,
+  params: &AuthorizeParams,
+) -> Result<String, OAuthError> {
+  if params.response_type != CODE_RESPONSE_TYPE {
+    return Err(OAuthError::UnsupportedResponseType(
+      "only the code response_type is supported".to_string(),
+    ));
+  }
+  if !client.is_grant_type_allowed(OAuthGrantType::AuthorizationCode) {
+    return Err(OAuthError::UnauthorizedClient(
+      "the client cannot use the authorization_code grant".to_string(),
+    ));
+  }
+  if let Some(scope) = &params.scope {
+    if !client.is_scope_allowed(scope) {
+      return Err(OAuthError::InvalidScope(
+        "the scope is not allowed for the client".to_string(),
+      ));
+    }
+  }
+
+  let code_challenge = params
+    .code_challenge
+    .clone()
+    .ok_or_else(|| OAuthError::InvalidRequest("code_challenge is required".to_string()))?;
+  if params.code_challenge_method.as_deref() != Some(S256_CODE_CHALLENGE_METHOD) {
+    return Err(OAuthError::InvalidRequest(
+      "code_challenge_method must be S256".to_string(),
+    ));
+  }
+
+  Ok(code_challenge)
+}
+
+fn redirect_uri_with(redirect_uri: &str, pairs: &[(&str, &str)]) -> Result<String> {
+  let mut url = Url::parse(redirect_uri)
+    .map_err(|_| OAuthError::InvalidRequest("redirect_uri is not a valid URI".to_string()))?;
+  url.query_pairs_mut().extend_pairs(pairs);
+
+  Ok(url.to_string())
+}
+
+// the error response of the authorization endpoint, the state is sent back
+fn error_redirect_uri(params: &AuthorizeParams, err: &OAuthError) -> Result<String> {
+  let description = err.to_string();
+  let mut pairs = vec![("error", err.code()), ("error_description", &description)];
+  if let Some(state) = &params.state {
+    pairs.push(("state", state));
+  }
+
+  redirect_uri_with(&params.redirect_uri, &pairs)
+}
+
+// the authorization endpoint sends the user to the login page with the request.
+// The page signs in the user, asks for the consent and sends it to approve_authorization.
+pub async fn start_authorization<O: OAuthClientRepo>(
+  ctx: &Context,
+  client_repo: &O,
+  params: &AuthorizeParams,
+) -> Result<String> {
+  let login_url = ctx
+    .settings
+    .get::<String>("oauth_login_url")
+    .expect("oauth_login_url must set");
+
+  let client = find_authorize_client(client_repo, params).await?;
+  if let Err(err) = check_authorize_params(&client, params) {
+    return error_redirect_uri(params, &err);
+  }
+
+  let mut pairs = vec![
+    ("response_type", params.response_type.clone()),
+    ("client_id", params.client_id.to_string()),
+    ("redirect_uri", params.redirect_uri.clone()),
+  ];
+  let optional_pairs = [
+    ("scope", &params.scope),
+    ("state", &params.state),
+    ("code_challenge", &params.code_challenge),
+    ("code_challenge_method", &params.code_challenge_method),
+    ("nonce", &params.nonce),
+  ];
+  for (key, value) in optional_pairs.iter() {
+    if let Some(value) = value {
+      pairs.push((key, value.clone()));
+    }
+  }
+
+  let mut url = Url::parse(&login_url)
+    .map_err(|_| FormatError::ServerError("oauth_login_url is not a valid URL".to_string()))?;
+  url.query_pairs_mut().extend_pairs(pairs);
+
+  Ok(url.to_string())
+}
+
+// the signed in user approves or denies the authorization request, it returns
+// the redirect URI with the authorization code or the access_denied error.
+// Only a session of ez-auth itself can decide, not a token issued to a client.
+pub async fn approve_authorization<O: OAuthClientRepo, A: AuthorizationCodeRepo>(
+  ctx: &Context,
+  client_repo: &O,
+  code_repo: &A,
+  claims: &Claims,
+  params: &AuthorizeParams,
+  consent: &AuthorizeConsent,
+) -> Result<String> {
+  let code_exp = ctx
+    .settings
+    .get::<i64>("oauth_authorization_code_exp")
+    .expect("oauth_authorization_code_exp must set");
+
+  if !claims.is_first_party() {
+    bail!(OAuthError::AccessDenied(
+      "only a session of ez-auth can approve an authorization".to_string()
+    ));
+  }
+
+  let client = find_authorize_client(client_repo, params).await?;
+  let code_challenge = match check_authorize_params(&client, params) {
+    Ok(code_challenge) => code_challenge,
+    Err(err) => return error_redirect_uri(params, &err),
+  };
+  if claims.is_anonymous {
+    let err = OAuthError::AccessDenied("an anonymous user cannot authorize a client".to_string());
+    return error_redirect_uri(params, &err);
+  }
+  if !consent.approved {
+    let err = OAuthError::AccessDenied("the user denied the authorization".to_string());
+    return error_redirect_uri(params, &err);
+  }
+
+  let (authorization_code, code) = AuthorizationCode::new(
+    params,
+    &claims.user_id,
+    code_challenge,
+    claims.amr.clone(),
+    Utc.timestamp(claims.auth_time as i64, 0),
+    code_exp,
+  );
+  code_repo.insert(&authorization_code).await?;
+
+  let mut pairs = vec![("code", code.as_str())];
+  if let Some(state) = &params.state {
+    pairs.push(("state", state));
+  }
+
+  redirect_uri_with(&params.redirect_uri, &pairs)
+}
+
+// authenticate the client at the token endpoint, by the basic scheme or client_secret_post.
+// A confidential client must send its secret, a public client only its id.
+async fn authenticate_client<O: OAuthClientRepo>(
+  client_repo: &O,
+  basic_credentials: Option<ClientCredentials>,
+  token_request: &TokenRequest,
+) -> Result<OAuthClient> {
+  let credentials = match (basic_credentials, &token_request.client_id) {
+    (Some(_), Some(_)) if token_request.client_secret.is_some() => {
+      bail!(OAuthError::InvalidRequest(
+        "use only one client authentication method".to_string()
+      ))
+    }
+    (Some(credentials), Some(client_id)) if &credentials.client_id != client_id => {
+      bail!(OAuthError::InvalidRequest(
+        "client_id does not match the authorization header".to_string()
+      ))
+    }
+    (Some(credentials), _) => credentials,
+    (None, Some(client_id)) => ClientCredentials {
+      client_id: client_id.clone(),
+      client_secret: token_request.client_secret.clone(),
+    },
+    (None, None) => bail!(OAuthError::InvalidRequest(
+      "client_id is required".to_string()
+    )),
+  };
+
+  let client = match client_repo.find_one(&credentials.client_id).await {
+    Ok(client) => client,
+    Err(err) if err.get_code() == "NOT_FOUND" => bail!(OAuthError::InvalidClient(
+      "client authentication failed".to_string()
+    )),
+    Err(err) => return Err(err),
+  };
+
+  let is_authenticated = match &credentials.client_secret {
+    Some(client_secret) => client.is_secret_matched(client_secret),
+    None => client.is_public(),
+  };
+  if !is_authenticated {
+    bail!(OAuthError::InvalidClient(
+      "client authentication failed".to_string()
+    ));
+  }
+
+  Ok(client)
+}
+
+// the token endpoint, for the authorization_code, refresh_token and client_credentials grants
+pub async fn create_token_by_oauth<
+  T: UserRepo,
+  K: RefreshTokenRepo,
+  O: OAuthClientRepo,
+  A: AuthorizationCodeRepo,
+>(
+  ctx: &Context,
+  user_repo: T,
+  token_repo: K,
+  client_repo: &O,
+  code_repo: &A,
+  basic_credentials: Option<ClientCredentials>,
+  token_request: &TokenRequest,
+) -> Result<TokenResponse> {
+  let grant_type = OAuthGrantType::from_name(&token_request.grant_type).ok_or_else(|| {
+    OAuthError::UnsupportedGrantType(format!(
+      "grant_type {} is not supported",
+      token_request.grant_type
+    ))
+  })?;
+
+  let client = authenticate_client(client_repo, basic_credentials, token_request).await?;
+  if !client.is_grant_type_allowed(grant_type) {
+    bail!(OAuthError::UnauthorizedClient(format!(
+      "the client cannot use the {} grant",
+      grant_type.name()
+    )));
+  }
+
+  match grant_type {
+    OAuthGrantType::AuthorizationCode => {
+      exchange_authorization_code(
+        ctx,
+        user_repo,
+        token_repo,
+        code_repo,
+        &client,
+        token_request,
+      )
+      .await
+    }
+    OAuthGrantType::RefreshToken => {
+      let refresh_token = required_param(&token_request.refresh_token, "refresh_token")?;
+      let create_token_output = auth_by_refresh_token(
+        ctx,
+        user_repo,
+        token_repo,
+        client_repo,
+        Some(&client.id),
+        refresh_token.to_string(),
+      )
+      .await
+      .map_err(to_invalid_grant)?;
+
+      Ok(to_token_response(create_token_output, None))
+    }
+    OAuthGrantType::ClientCredentials => {
+      issue_client_token(ctx, &client, token_request.scope.as_deref())
+    }
+  }
+}
+
+// the token of a confidential client itself, its sub is the client.
+// Without a scope parameter the client gets all of its scopes.
+fn issue_client_token(
+  ctx: &Context,
+  client: &OAuthClient,
+  scope: Option<&str>,
+) -> Result<TokenResponse> {
+  if client.is_public() {
+    bail!(OAuthError::UnauthorizedClient(
+      "a public client cannot use the client_credentials grant".to_string()
+    ));
+  }
+
+  let scopes = match scope {
+    Some(scope) if !client.is_scope_allowed(scope) => bail!(OAuthError::InvalidScope(
+      "the scope is not allowed for the client".to_string()
+    )),
+    Some(scope) => parse_scope(scope),
+    None => client.scopes.clone(),
+  };
+
+  let exp = Utc::now().timestamp() as usize + client.access_token_exp as usize;
+  let claims = Claims::new_client(client.id.clone(), client.audience(), exp, scopes.clone());
+
+  Ok(TokenResponse {
+    access_token: create_access_token(ctx, &claims),
+    token_type: "bearer".to_string(),
+    expires_in: client.access_token_exp,
+    refresh_token: None,
+    scope: Some(scopes.join(" ")).filter(|scope| !scope.is_empty()),
+    id_token: None,
+  })
+}
+
+async fn exchange_authorization_code<T: UserRepo, K: RefreshTokenRepo, A: AuthorizationCodeRepo>(
+  ctx: &Context,
+  user_repo: T,
+  token_repo: K,
+  code_repo: &A,
+  client: &OAuthClient,
+  token_request: &TokenRequest,
+) -> Result<TokenResponse> {
+  let code = required_param(&token_request.code, "code")?;
+  let redirect_uri = required_param(&token_request.redirect_uri, "redirect_uri")?;
+  let code_verifier = required_param(&token_request.code_verifier, "code_verifier")?;
+
+  let authorization_code = match code_repo.take(&hash_token(code)).await {
+    Ok(authorization_code) => authorization_code,
+    Err(err) if err.get_code() == "NOT_FOUND" => bail!(OAuthError::InvalidGrant(
+      "authorization code is invalid or used".to_string()
+    )),
+    Err(err) => return Err(err),
+  };
+
+  if authorization_code.is_expired() {
+    bail!(OAuthError::InvalidGrant(
+      "authorization code has expired".to_string()
+    ));
+  }
+  if authorization_code.client_id != client.id || authorization_code.redirect_uri != redirect_uri {
+    bail!(OAuthError::InvalidGrant(
+      "authorization code was issued to another client or redirect_uri".to_string()
+    ));
+  }
+  if !authorization_code.is_code_verifier_matched(code_verifier) {
+    bail!(OAuthError::InvalidGrant(
+      "code_verifier does not match the code_challenge".to_string()
+    ));
+  }
+
+  let user = user_repo
+    .find_one(&FindOneUserCondition::Id(
+      authorization_code.user_id.clone(),
+    ))
+    .await
+    .map_err(to_invalid_grant)?;
+  let scopes = parse_scope(&authorization_code.scope);
+  let create_token_output = create_client_token_output(
+    ctx,
+    &token_repo,
+    &user,
+    SessionAuth::Continued {
+      authenticated_at: authorization_code.authenticated_at,
+      amr: authorization_code.amr.clone(),
+      scopes: scopes.clone(),
+    },
+    Some(client),
+  )
+  .await
+  .map_err(to_invalid_grant)?;
+
+  let id_token = if scopes.iter().any(|scope| scope == OPENID_SCOPE) {
+    Some(create_user_id_token(
+      ctx,
+      client,
+      &user,
+      &authorization_code,
+      &create_token_output.access_token,
+    )?)
+  } else {
+    None
+  };
+
+  let scope = Some(authorization_code.scope).filter(|scope| !scope.is_empty());
+  Ok(TokenResponse {
+    id_token,
+    ..to_token_response(create_token_output, scope)
+  })
+}
+
+// the ID token lives as long as the access token it comes with
+fn create_user_id_token(
+  ctx: &Context,
+  client: &OAuthClient,
+  user: &User,
+  authorization_code: &AuthorizationCode,
+  access_token: &str,
+) -> Result<String> {
+  let issuer = ctx
+    .settings
+    .get::<String>("oidc_issuer")
+    .expect("oidc_issuer must set");
+  let iat = Utc::now().timestamp() as usize;
+  // the email claims are only released with the email scope
+  let (email, email_verified) = if parse_scope(&authorization_code.scope)
+    .iter()
+    .any(|scope| scope == EMAIL_SCOPE)
+  {
+    (
+      user.email.clone(),
+      Some(user.email.is_some() && user.is_confirmed()),
+    )
+  } else {
+    (None, None)
+  };
+
+  let claims = IdTokenClaims {
+    iss: issuer,
+    sub: user.id.clone(),
+    aud: client.id.to_string(),
+    exp: iat + client.access_token_exp as usize,
+    iat,
+    auth_time: authorization_code.authenticated_at.timestamp() as usize,
+    nonce: authorization_code.nonce.clone(),
+    email,
+    email_verified,
+    amr: authorization_code.amr.clone(),
+    at_hash: at_hash(access_token),
+  };
+
+  create_id_token(ctx, &claims)
+}
+
+// the discovery document of ez-auth as an OpenID provider
+pub fn openid_configuration(ctx: &Context) -> OpenIdConfiguration {
+  let issuer = ctx
+    .settings
+    .get::<String>("oidc_issuer")
+    .expect("oidc_issuer must set");
+
+  OpenIdConfiguration {
+    authorization_endpoint: format!("{}/auth/authorize", issuer),
+    token_endpoint: format!("{}/auth/token", issuer),
+    jwks_uri: format!("{}/auth/jwks", issuer),
+    issuer,
+    response_types_supported: vec![CODE_RESPONSE_TYPE],
+    grant_types_supported: vec![
+      OAuthGrantType::AuthorizationCode.name(),
+      OAuthGrantType::RefreshToken.name(),
+      OAuthGrantType::ClientCredentials.name(),
+    ],
+    subject_types_supported: vec!["public"],
+    id_token_signing_alg_values_supported: vec!["RS256"],
+    scopes_supported: vec![OPENID_SCOPE, EMAIL_SCOPE],
+    token_endpoint_auth_methods_supported: vec![
+      "client_secret_basic",
+      "client_secret_post",
+      "none",
+    ],
+    code_challenge_methods_supported: vec![S256_CODE_CHALLENGE_METHOD],
+    claims_supported: vec![
+      "iss",
+      "sub",
+      "aud",
+      "exp",
+      "iat",
+      "auth_time",
+      "nonce",
+      "email",
+      "email_verified",
+      "amr",
+      "at_hash",
+    ],
+  }
+}
+
+// the key set which verifies the access tokens and the ID tokens
+pub fn json_web_key_set(ctx: &Context) -> Result<Jwks> {
+  let public_key = ctx
+    .settings
+    .get::<String>("public_key")
+    .expect("public_key must set");
+
+  Ok(Jwks {
+    keys: vec![Jwk::from_rsa_pem(&public_key)?],
+  })
+}
+
+fn required_param<'a>(value: &'a Option<String>, name: &str) -> Result<&'a str> {
+  value
+    .as_deref()
+    .ok_or_else(|| anyhow!(OAuthError::InvalidRequest(format!("{} is required", name))))
+}
+
+// the user or the refresh token cannot be used, e.g. the user is banned or the token is revoked
+fn to_invalid_grant(err: anyhow::Error) -> anyhow::Error {
+  match err.get_code() {
+    "SERVER_ERROR" | "UNEXPECTED_ERROR" => err,
+    _ => anyhow!(OAuthError::InvalidGrant(format!("{:#}", err))),
+  }
+}
+
+fn to_token_response(output: CreateTokenOutput, scope: Option<String>) -> TokenResponse {
+  TokenResponse {
+    access_token: output.access_token,
+    token_type: output.token_type,
+    expires_in: output.expires_in,
+    refresh_token: Some(output.refresh_token),
+    scope,
+    id_token: None,
+  }
+}
@@ -1,20 +1,54 @@
+use super::identity_usecase::touch_email_identity;
+use super::webauthn_usecase::verify_passkey_assertion;
 use crate::context::Context;
-use crate::model::error::FormatError;
+use crate::model::crypto::hash_token;
+use crate::model::error::{ErrorHint, FormatError, SpecificError};
 use crate::model::id::Id;
+use crate::model::mfa::{
+  hash_recovery_code, DeviceClaims, FactorType, MfaChallenge, MfaFactor, MfaRequired, TrustedDevice,
+};
+use crate::model::oauth::OAuthClient;
+use crate::model::oidc::{IdTokenClaims, Jwk};
 use crate::model::token::Claims;
 use crate::model::token::{
-  CreateTokenByPasswordInput, CreateTokenOutput, RefreshToken, ACCESS_TOKEN_EXP,
+  extend_amr, AuthMethod, CreateTokenByEmailOtpInput, CreateTokenByMagicLinkInput,
+  CreateTokenByMfaChallengeInput, CreateTokenByPasswordInput, CreateTokenByPhoneOtpInput,
+  CreateTokenOutput, CreateTokenResult, ReauthenticateInput, RefreshToken, SecondFactorInput,
+  SessionAuth, ACCESS_TOKEN_EXP,
+};
+use crate::model::user::{
+  normalize_phone, FindOneUserCondition, OneTimeCode, OtpChannel, PhoneSignUp, UpdateOneUserField,
+  User,
 };
-use crate::model::user::{FindOneUserCondition, User};
+use crate::model::webauthn::PasskeyAssertionInput;
+use crate::repository::identity_repo::IdentityRepo;
+use crate::repository::mailer::Mailer;
+use crate::repository::mfa_challenge_repo::MfaChallengeRepo;
+use crate::repository::mfa_factor_repo::MfaFactorRepo;
+use crate::repository::oauth_client_repo::OAuthClientRepo;
+use crate::repository::phone_sign_up_repo::PhoneSignUpRepo;
+use crate::repository::recovery_code_repo::RecoveryCodeRepo;
 use crate::repository::refresh_token_repo::RefreshTokenRepo;
+use crate::repository::sms_send_repo::SmsSendRepo;
+use crate::repository::sms_sender::SmsSender;
+use crate::repository::trusted_device_repo::TrustedDeviceRepo;
 use crate::repository::user_repo::UserRepo;
-use anyhow::{bail, Result};
+use crate::repository::webauthn_repo::WebauthnRepo;
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Duration, Utc};
 use config::Config;
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-pub fn create_user_claims(ctx: &Context, user: &User, expires_in: usize) -> Result<Claims> {
-  let aud = ctx.settings.get::<String>("aud").expect("aud must set");
+pub fn create_user_claims(
+  user: &User,
+  aud: String,
+  expires_in: usize,
+  authenticated_at: DateTime<Utc>,
+  amr: Vec<String>,
+  scopes: Vec<String>,
+  client_id: Option<Id>,
+) -> Result<Claims> {
   let unix_time = SystemTime::now()
     .duration_since(UNIX_EPOCH)
     .map_err(|err| FormatError::ServerError(err.to_string()))?;
@@ -22,7 +56,16 @@ pub fn create_user_claims(ctx: &Context, user: &User, expires_in: usize) -> Resu
   let unix_time_secs = unix_time.as_secs();
   let exp = unix_time_secs as usize + expires_in;
 
-  Ok(Claims::new(user.id.clone(), aud, exp))
+  Ok(Claims::new(
+    user.id.clone(),
+    aud,
+    exp,
+    user.is_anonymous,
+    authenticated_at.timestamp() as usize,
+    amr,
+    scopes,
+    client_id,
+  ))
 }
 
 pub fn create_access_token(ctx: &Context, claims: &Claims) -> String {
@@ -33,12 +76,10 @@ pub fn create_access_token(ctx: &Context, claims: &Claims) -> String {
   let encoding_key = &EncodingKey::from_rsa_pem(private_key.as_bytes()).unwrap();
 
   let header = Header::new(Algorithm::RS256);
-  let token = encode::<Claims>(&header, claims, &encoding_key).unwrap();
-
-  token
+  encode::<Claims>(&header, claims, encoding_key).unwrap()
 }
 
-pub fn decode_access_token(settings: &Config, jwt: &String) -> Result<Claims> {
+pub fn decode_access_token(settings: &Config, jwt: &str) -> Result<Claims> {
   let public_key = settings
     .get::<String>("public_key")
     .expect("public_key must set");
@@ -53,20 +94,169 @@ pub fn decode_access_token(settings: &Config, jwt: &String) -> Result<Claims> {
   Ok(token.claims)
 }
 
+// the ID token is signed by the key of the access tokens, the kid points to it in the JWKS
+pub fn create_id_token(ctx: &Context, claims: &IdTokenClaims) -> Result<String> {
+  let public_key = ctx
+    .settings
+    .get::<String>("public_key")
+    .expect("public_key must set");
+  let private_key = ctx
+    .settings
+    .get::<String>("private_key")
+    .expect("private_key must set");
+  let encoding_key = &EncodingKey::from_rsa_pem(private_key.as_bytes()).unwrap();
+
+  let mut header = Header::new(Algorithm::RS256);
+  header.kid = Some(Jwk::from_rsa_pem(&public_key)?.kid);
+  Ok(encode(&header, claims, encoding_key).unwrap())
+}
+
+// the device token is a jwt with its own audience, so it cannot be used as an access token
+pub fn create_device_token(ctx: &Context, device: &TrustedDevice) -> String {
+  let aud = ctx.settings.get::<String>("aud").expect("aud must set");
+  let private_key = ctx
+    .settings
+    .get::<String>("private_key")
+    .expect("private_key must set");
+  let encoding_key = &EncodingKey::from_rsa_pem(private_key.as_bytes()).unwrap();
+
+  let claims = DeviceClaims {
+    sub: device.user_id.clone(),
+    jti: device.id.clone(),
+    aud: device_audience(&aud),
+    exp: device.expires_at.timestamp() as usize,
+  };
+  encode(&Header::new(Algorithm::RS256), &claims, encoding_key).unwrap()
+}
+
+fn device_audience(aud: &str) -> String {
+  format!("{}:device", aud)
+}
+
+async fn trust_device<D: TrustedDeviceRepo>(
+  ctx: &Context,
+  device_repo: &D,
+  user_id: &Id,
+  device_name: Option<String>,
+) -> Result<TrustedDevice> {
+  let trusted_device_exp = ctx
+    .settings
+    .get::<i64>("trusted_device_exp")
+    .expect("trusted_device_exp must set");
+
+  let device = TrustedDevice::new(user_id, device_name, trusted_device_exp);
+  device_repo.insert(&device).await?;
+
+  Ok(device)
+}
+
+// check the device token of the user. An invalid, expired or revoked token
+// is not an error, the sign in falls back to the second factor.
+async fn verify_device_token<D: TrustedDeviceRepo>(
+  ctx: &Context,
+  device_repo: &D,
+  user_id: &Id,
+  device_token: &str,
+) -> Result<bool> {
+  let aud = ctx.settings.get::<String>("aud").expect("aud must set");
+  let public_key = ctx
+    .settings
+    .get::<String>("public_key")
+    .expect("public_key must set");
+  let decode_key = &DecodingKey::from_rsa_pem(public_key.as_bytes()).unwrap();
+  let mut validation = Validation::new(Algorithm::RS256);
+  validation.set_audience(&[device_audience(&aud)]);
+
+  let claims = match decode::<DeviceClaims>(device_token, decode_key, &validation) {
+    Ok(token) if token.claims.sub == *user_id => token.claims,
+    _ => return Ok(false),
+  };
+
+  let device = match device_repo.find_one(user_id, &claims.jti).await {
+    Ok(device) => device,
+    Err(err) if err.get_code() == "NOT_FOUND" => return Ok(false),
+    Err(err) => return Err(err),
+  };
+  if device.is_expired() {
+    return Ok(false);
+  }
+
+  device_repo.touch(&device.id).await?;
+
+  Ok(true)
+}
+
 // TODO: hash token before save
+// A refreshed session keeps when and how the swapped session was authenticated.
 pub async fn create_token_output<K: RefreshTokenRepo>(
   ctx: &Context,
   token_repo: &K,
   user: &User,
-  swap_refresh_token: Option<String>,
+  session_auth: SessionAuth,
+) -> Result<CreateTokenOutput> {
+  create_client_token_output(ctx, token_repo, user, session_auth, None).await
+}
+
+// tokens of a session of the OAuth client, the client decides the aud and the lifetime
+// of the access token. The refresh token can only be refreshed for the same client.
+pub async fn create_client_token_output<K: RefreshTokenRepo>(
+  ctx: &Context,
+  token_repo: &K,
+  user: &User,
+  session_auth: SessionAuth,
+  client: Option<&OAuthClient>,
 ) -> Result<CreateTokenOutput> {
-  let claims = create_user_claims(ctx, user, ACCESS_TOKEN_EXP)?;
+  user.check_can_sign_in()?;
+
+  let (aud, expires_in, client_id) = match client {
+    Some(client) => (
+      client.audience(),
+      client.access_token_exp as usize,
+      Some(client.id.clone()),
+    ),
+    None => (
+      ctx.settings.get::<String>("aud").expect("aud must set"),
+      ACCESS_TOKEN_EXP,
+      None,
+    ),
+  };
+
+  let (authenticated_at, amr, scopes) = match &session_auth {
+    SessionAuth::Authenticated(amr) => (Utc::now(), amr.clone(), vec![]),
+    SessionAuth::Refreshed(refresh_token) => (
+      refresh_token.authenticated_at,
+      refresh_token.amr.clone(),
+      refresh_token.scopes.clone(),
+    ),
+    SessionAuth::Reauthenticated { refresh_token, amr } => {
+      (Utc::now(), amr.clone(), refresh_token.scopes.clone())
+    }
+    SessionAuth::Continued {
+      authenticated_at,
+      amr,
+      scopes,
+    } => (*authenticated_at, amr.clone(), scopes.clone()),
+  };
+  let claims = create_user_claims(
+    user,
+    aud,
+    expires_in,
+    authenticated_at,
+    amr.clone(),
+    scopes.clone(),
+    client_id.clone(),
+  )?;
   let access_token = create_access_token(ctx, &claims);
-  let refresh_token = RefreshToken::new(&user.id);
+  let refresh_token = RefreshToken::new(&user.id, authenticated_at, amr, client_id, scopes);
 
-  if swap_refresh_token.is_some() {
+  if let SessionAuth::Refreshed(swap_refresh_token)
+  | SessionAuth::Reauthenticated {
+    refresh_token: swap_refresh_token,
+    ..
+  } = session_auth
+  {
     token_repo
-      .swap_token(&user.id, swap_refresh_token.unwrap(), &refresh_token)
+      .swap_token(&user.id, swap_refresh_token.token, &refresh_token)
       .await?;
   } else {
     token_repo.insert(&refresh_token).await?;
@@ -76,37 +266,1176 @@ pub async fn create_token_output<K: RefreshTokenRepo>(
     access_token,
     refresh_token: refresh_token.token,
     token_type: "bearer".to_string(),
-    expires_in: ACCESS_TOKEN_EXP as i32,
+    expires_in: expires_in as i32,
+    device_token: None,
   })
 }
 
-pub async fn auth_by_password<T: UserRepo, K: RefreshTokenRepo>(
+// A user with a verified factor gets MfaRequired with a challenge token,
+// the sign in is completed by auth_by_mfa_challenge. A trusted device skips the challenge.
+#[allow(clippy::too_many_arguments)]
+pub async fn auth_by_password<
+  T: UserRepo,
+  K: RefreshTokenRepo,
+  I: IdentityRepo,
+  F: MfaFactorRepo,
+  C: MfaChallengeRepo,
+  D: TrustedDeviceRepo,
+  M: Mailer,
+>(
   ctx: &Context,
   user_repo: T,
   token_repo: K,
+  identity_repo: &I,
+  factor_repo: &F,
+  challenge_repo: &C,
+  device_repo: &D,
+  mailer: &M,
   sign_in_input: &CreateTokenByPasswordInput,
-) -> Result<CreateTokenOutput> {
-  let email = sign_in_input.email.clone();
+) -> Result<CreateTokenResult> {
+  let identifier = sign_in_input.identifier.clone();
   let password = sign_in_input.password.clone();
 
-  let filter = FindOneUserCondition::Email(email);
+  // usernames cannot contain '@', so the identifier is an email address when it has one
+  let is_email = identifier.contains('@');
+  let filter = if is_email {
+    FindOneUserCondition::Email(identifier.clone())
+  } else {
+    FindOneUserCondition::Username(identifier.clone())
+  };
   let user = user_repo.find_one(&filter).await?;
 
+  if user.is_locked() {
+    return Err(account_locked_error(&user));
+  }
+
   // TODO: edit 404 error
-  user.check_password(&password)?;
+  if let Err(err) = user.check_password(&password) {
+    return Err(count_failed_sign_in(ctx, &user_repo, mailer, user, err).await?);
+  }
+  if user.failed_sign_in_attempts > 0 || user.locked_until.is_some() {
+    user_repo
+      .update_one(
+        &FindOneUserCondition::Id(user.id.clone()),
+        &[
+          UpdateOneUserField::FailedSignInAttempts(0),
+          UpdateOneUserField::LockedUntil(None),
+        ],
+      )
+      .await?;
+  }
   if !user.is_confirmed() {
     bail!(FormatError::Unauthenticated(
       "Your account is not confirmed yet".to_string()
     ));
   }
 
-  create_token_output(ctx, &token_repo, &user, None).await
+  if is_email && user.email.as_deref() != Some(&identifier) {
+    touch_email_identity(identity_repo, &identifier).await?;
+  }
+
+  create_token_or_challenge(
+    ctx,
+    &token_repo,
+    factor_repo,
+    challenge_repo,
+    device_repo,
+    &user,
+    AuthMethod::Password,
+    sign_in_input.device_token.as_ref(),
+  )
+  .await
+}
+
+// every first factor sign in ends here. A user with a verified factor gets MfaRequired
+// with a challenge token instead of the tokens, unless the device is trusted.
+#[allow(clippy::too_many_arguments)]
+pub async fn create_token_or_challenge<
+  K: RefreshTokenRepo,
+  F: MfaFactorRepo,
+  C: MfaChallengeRepo,
+  D: TrustedDeviceRepo,
+>(
+  ctx: &Context,
+  token_repo: &K,
+  factor_repo: &F,
+  challenge_repo: &C,
+  device_repo: &D,
+  user: &User,
+  method: AuthMethod,
+  device_token: Option<&String>,
+) -> Result<CreateTokenResult> {
+  let mut amr = vec![method.name().to_string()];
+  let verified_factors: Vec<MfaFactor> = factor_repo
+    .find_by_user_id(&user.id)
+    .await?
+    .into_iter()
+    .filter(|factor| factor.is_verified())
+    .collect();
+  let is_trusted_device = match device_token {
+    Some(device_token) => verify_device_token(ctx, device_repo, &user.id, device_token).await?,
+    None => false,
+  };
+
+  if !verified_factors.is_empty() && is_trusted_device {
+    amr.push(AuthMethod::TrustedDevice.name().to_string());
+  } else if !verified_factors.is_empty() {
+    user.check_can_sign_in()?;
+    let mfa_required =
+      create_mfa_challenge(ctx, challenge_repo, user, amr, verified_factors).await?;
+
+    return Ok(CreateTokenResult::MfaRequired(mfa_required));
+  }
+
+  create_token_output(ctx, token_repo, user, SessionAuth::Authenticated(amr))
+    .await
+    .map(CreateTokenResult::Token)
+}
+
+// create a challenge which waits for a second factor of the sign in
+pub async fn create_mfa_challenge<C: MfaChallengeRepo>(
+  ctx: &Context,
+  challenge_repo: &C,
+  user: &User,
+  amr: Vec<String>,
+  available_factors: Vec<MfaFactor>,
+) -> Result<MfaRequired> {
+  let challenge_exp = ctx
+    .settings
+    .get::<i64>("mfa_challenge_exp")
+    .expect("mfa_challenge_exp must set");
+
+  let (challenge, challenge_token) = MfaChallenge::new(&user.id, amr, challenge_exp);
+  challenge_repo.insert(&challenge).await?;
+
+  Ok(MfaRequired {
+    challenge_token,
+    available_factors,
+  })
+}
+
+pub async fn find_mfa_challenge<C: MfaChallengeRepo>(
+  challenge_repo: &C,
+  challenge_token: &str,
+) -> Result<MfaChallenge> {
+  let challenge = match challenge_repo
+    .find_by_token(&hash_token(challenge_token))
+    .await
+  {
+    Ok(challenge) => challenge,
+    Err(err) if err.get_code() == "NOT_FOUND" => bail!(FormatError::Unauthenticated(
+      "MFA challenge is invalid or completed".to_string()
+    )),
+    Err(err) => return Err(err),
+  };
+
+  if challenge.is_expired() {
+    bail!(FormatError::Unauthenticated(
+      "MFA challenge is expired, please sign in again".to_string()
+    ));
+  }
+
+  Ok(challenge)
 }
 
-pub async fn auth_by_refresh_token<T: UserRepo, K: RefreshTokenRepo>(
+// complete a sign in which returned MfaRequired with a second factor.
+// The challenge is deleted when it succeeds, so it completes one sign in only.
+#[allow(clippy::too_many_arguments)]
+pub async fn auth_by_mfa_challenge<
+  T: UserRepo,
+  K: RefreshTokenRepo,
+  F: MfaFactorRepo,
+  C: MfaChallengeRepo,
+  R: RecoveryCodeRepo,
+  W: WebauthnRepo,
+  D: TrustedDeviceRepo,
+  M: Mailer,
+>(
   ctx: &Context,
   user_repo: T,
   token_repo: K,
+  factor_repo: &F,
+  challenge_repo: &C,
+  recovery_code_repo: &R,
+  webauthn_repo: &W,
+  device_repo: &D,
+  mailer: &M,
+  sign_in_input: &CreateTokenByMfaChallengeInput,
+) -> Result<CreateTokenOutput> {
+  let challenge = find_mfa_challenge(challenge_repo, &sign_in_input.challenge_token).await?;
+  let user = user_repo
+    .find_one(&FindOneUserCondition::Id(challenge.user_id.clone()))
+    .await?;
+
+  if user.is_locked() {
+    return Err(account_locked_error(&user));
+  }
+
+  let verified = verify_second_factor(
+    ctx,
+    factor_repo,
+    recovery_code_repo,
+    webauthn_repo,
+    &user.id,
+    &sign_in_input.second_factor,
+  )
+  .await;
+  let method = match verified {
+    Ok(method) => method,
+    // wrong codes count as failed sign in attempts, so they cannot be brute forced
+    Err(err) if err.get_code() == "UNAUTHENTICATED" => {
+      return Err(count_failed_sign_in(ctx, &user_repo, mailer, user, err).await?)
+    }
+    Err(err) => return Err(err),
+  };
+
+  // only one of concurrent requests with the same challenge completes the sign in
+  if !challenge_repo.delete_one(&challenge.id).await? {
+    bail!(FormatError::Unauthenticated(
+      "MFA challenge is invalid or completed".to_string()
+    ));
+  }
+
+  let mut create_token_output = create_token_output(
+    ctx,
+    &token_repo,
+    &user,
+    SessionAuth::extend(&challenge.amr, method),
+  )
+  .await?;
+
+  if sign_in_input.trust_device {
+    let device = trust_device(
+      ctx,
+      device_repo,
+      &user.id,
+      sign_in_input.device_name.clone(),
+    )
+    .await?;
+    create_token_output.device_token = Some(create_device_token(ctx, &device));
+  }
+
+  Ok(create_token_output)
+}
+
+// check a verified second factor, e.g. of an MFA challenge, it returns the method used.
+// A recovery code cannot be used again.
+async fn verify_second_factor<F: MfaFactorRepo, R: RecoveryCodeRepo, W: WebauthnRepo>(
+  ctx: &Context,
+  factor_repo: &F,
+  recovery_code_repo: &R,
+  webauthn_repo: &W,
+  user_id: &Id,
+  second_factor: &SecondFactorInput,
+) -> Result<AuthMethod> {
+  let factor_id = match &second_factor.factor_id {
+    Some(factor_id) => factor_id,
+    None => {
+      let code = required_code(&second_factor.code)?;
+      if !recovery_code_repo
+        .use_code(user_id, &hash_recovery_code(code))
+        .await?
+      {
+        bail!(FormatError::Unauthenticated(
+          "recovery code is incorrect".to_string()
+        ));
+      }
+
+      return Ok(AuthMethod::Recovery);
+    }
+  };
+
+  let factor = factor_repo.find_one(user_id, factor_id).await?;
+  if !factor.is_verified() {
+    bail!(FormatError::BadRequest(
+      "the factor is not verified".to_string()
+    ));
+  }
+
+  match factor.factor_type {
+    FactorType::Totp => {
+      let code = required_code(&second_factor.code)?;
+      verify_totp_code(ctx, factor_repo, user_id, Some(&factor.id), code).await?;
+
+      Ok(AuthMethod::Totp)
+    }
+    FactorType::Webauthn => {
+      let assertion = second_factor
+        .passkey
+        .as_ref()
+        .ok_or_else(|| FormatError::ValidationFailed("passkey is required".to_string()))?;
+      let credential =
+        verify_passkey_assertion(ctx, webauthn_repo, Some(user_id), assertion).await?;
+      if credential.factor_id != factor.id {
+        bail!(FormatError::Unauthenticated(
+          "the passkey does not belong to the factor".to_string()
+        ));
+      }
+
+      Ok(AuthMethod::Webauthn)
+    }
+    FactorType::Email => {
+      let code = required_code(&second_factor.code)?;
+      verify_email_factor_code(ctx, factor_repo, &factor, code).await?;
+
+      Ok(AuthMethod::OtpEmail)
+    }
+  }
+}
+
+fn required_code(code: &Option<String>) -> Result<&str> {
+  code.as_deref().ok_or_else(|| {
+    anyhow!(FormatError::ValidationFailed(
+      "code is required".to_string()
+    ))
+  })
+}
+
+// sign in with a passkey, without password
+pub async fn auth_by_passkey<T: UserRepo, K: RefreshTokenRepo, W: WebauthnRepo>(
+  ctx: &Context,
+  user_repo: T,
+  token_repo: K,
+  webauthn_repo: &W,
+  assertion: &PasskeyAssertionInput,
+) -> Result<CreateTokenOutput> {
+  let credential = verify_passkey_assertion(ctx, webauthn_repo, None, assertion).await?;
+  let user = user_repo
+    .find_one(&FindOneUserCondition::Id(credential.user_id))
+    .await?;
+
+  if user.is_locked() {
+    return Err(account_locked_error(&user));
+  }
+
+  create_token_output(
+    ctx,
+    &token_repo,
+    &user,
+    SessionAuth::by(AuthMethod::Webauthn),
+  )
+  .await
+}
+
+// check a TOTP code against the factor, or against every verified TOTP factor
+// when factor_id is None. The matched factor is marked verified.
+pub async fn verify_totp_code<F: MfaFactorRepo>(
+  ctx: &Context,
+  factor_repo: &F,
+  user_id: &Id,
+  factor_id: Option<&Id>,
+  code: &str,
+) -> Result<()> {
+  let encryption_key = ctx
+    .settings
+    .get::<String>("mfa_encryption_key")
+    .expect("mfa_encryption_key must set");
+
+  let factors = match factor_id {
+    Some(factor_id) => {
+      let factor = factor_repo.find_one(user_id, factor_id).await?;
+      if factor.factor_type != FactorType::Totp {
+        bail!(FormatError::BadRequest(
+          "the factor is not a TOTP factor".to_string()
+        ));
+      }
+      vec![factor]
+    }
+    None => factor_repo
+      .find_by_user_id(user_id)
+      .await?
+      .into_iter()
+      .filter(|factor| factor.factor_type == FactorType::Totp && factor.is_verified())
+      .collect(),
+  };
+
+  for factor in factors {
+    if let Some(step) = factor.verify_totp(&encryption_key, code)? {
+      // the step is checked again when it is saved, a concurrent request may have used it
+      let is_saved = if factor.is_verified() {
+        factor_repo.update_last_used_step(&factor.id, step).await?
+      } else {
+        factor_repo.mark_verified(&factor.id, step).await?
+      };
+      if !is_saved {
+        bail!(FormatError::Unauthenticated(
+          "TOTP code has already been used".to_string()
+        ));
+      }
+
+      return Ok(());
+    }
+  }
+
+  bail!(FormatError::Unauthenticated(
+    "TOTP code is incorrect".to_string()
+  ))
+}
+
+// send a new code of the email factor, it replaces the previous code.
+// A code can be sent once every mfa_email_otp_resend_interval seconds.
+pub async fn send_email_factor_code<F: MfaFactorRepo, M: Mailer>(
+  ctx: &Context,
+  factor_repo: &F,
+  mailer: &M,
+  user: &User,
+  factor: &MfaFactor,
+) -> Result<()> {
+  let otp_length = ctx
+    .settings
+    .get::<usize>("mfa_email_otp_length")
+    .expect("mfa_email_otp_length must set");
+  if !(6..=8).contains(&otp_length) {
+    bail!(FormatError::ServerError(
+      "mfa_email_otp_length must be from 6 to 8".to_string()
+    ));
+  }
+  let resend_interval = ctx
+    .settings
+    .get::<i64>("mfa_email_otp_resend_interval")
+    .expect("mfa_email_otp_resend_interval must set");
+
+  if factor.factor_type != FactorType::Email {
+    bail!(FormatError::BadRequest(
+      "the factor is not an email factor".to_string()
+    ));
+  }
+  let email = user.email.as_ref().ok_or_else(|| {
+    FormatError::BadRequest("the user does not have an email address".to_string())
+  })?;
+
+  let wait = factor.otp.resend_wait(resend_interval);
+  if wait > 0 {
+    return Err(
+      anyhow!(FormatError::TooManyRequests(
+        "a code has been sent recently".to_string()
+      ))
+      .context(ErrorHint::RetryAfter(wait)),
+    );
+  }
+
+  let mut otp = factor.otp.clone();
+  let code = otp.renew(otp_length);
+  factor_repo.update_otp(&factor.id, &otp).await?;
+
+  mailer
+    .send(
+      email,
+      "Your verification code",
+      &format!("Your verification code is: {}", code),
+    )
+    .await
+}
+
+// check the code of the email factor, the code is used once.
+// A failed attempt is counted, the code is invalidated when it has expired
+// or there is no attempt left. An unverified factor is marked verified.
+pub async fn verify_email_factor_code<F: MfaFactorRepo>(
+  ctx: &Context,
+  factor_repo: &F,
+  factor: &MfaFactor,
+  code: &str,
+) -> Result<()> {
+  let otp_exp = ctx
+    .settings
+    .get::<i64>("mfa_email_otp_exp")
+    .expect("mfa_email_otp_exp must set");
+  let otp_max_attempts = ctx
+    .settings
+    .get::<i32>("mfa_email_otp_max_attempts")
+    .expect("mfa_email_otp_max_attempts must set");
+
+  if factor.factor_type != FactorType::Email {
+    bail!(FormatError::BadRequest(
+      "the factor is not an email factor".to_string()
+    ));
+  }
+
+  if factor.otp.code.is_none() {
+    bail!(FormatError::Unauthenticated(
+      "one-time code is invalid".to_string()
+    ));
+  }
+
+  // concurrent attempts cannot share a count, each one takes its own attempt
+  let otp = match factor_repo
+    .count_otp_attempt(&factor.id, otp_max_attempts)
+    .await
+  {
+    Ok(otp) => otp,
+    Err(err) if err.get_code() == "NOT_FOUND" => bail!(FormatError::Unauthenticated(
+      "one-time code is invalid".to_string()
+    )),
+    Err(err) => return Err(err),
+  };
+
+  if let Err(err) = check_counted_otp(&otp, code, otp_exp, otp_max_attempts) {
+    if otp.is_expired(otp_exp) || otp.attempts >= otp_max_attempts {
+      factor_repo
+        .update_otp(&factor.id, &OneTimeCode::default())
+        .await?;
+    }
+
+    return Err(err);
+  }
+
+  // only one of concurrent requests with the right code uses it
+  if !factor_repo.take_otp(&factor.id, &hash_token(code)).await? {
+    bail!(FormatError::Unauthenticated(
+      "one-time code is invalid".to_string()
+    ));
+  }
+
+  if !factor.is_verified() {
+    factor_repo.mark_verified(&factor.id, 0).await?;
+  }
+
+  Ok(())
+}
+
+// send the code of an email factor to complete an MFA challenge
+pub async fn send_mfa_challenge_code<
+  T: UserRepo,
+  F: MfaFactorRepo,
+  C: MfaChallengeRepo,
+  M: Mailer,
+>(
+  ctx: &Context,
+  user_repo: &T,
+  factor_repo: &F,
+  challenge_repo: &C,
+  mailer: &M,
+  challenge_token: &str,
+  factor_id: &Id,
+) -> Result<()> {
+  let challenge = find_mfa_challenge(challenge_repo, challenge_token).await?;
+  let factor = factor_repo.find_one(&challenge.user_id, factor_id).await?;
+  if !factor.is_verified() {
+    bail!(FormatError::BadRequest(
+      "the factor is not verified".to_string()
+    ));
+  }
+
+  let user = user_repo
+    .find_one(&FindOneUserCondition::Id(challenge.user_id))
+    .await?;
+
+  send_email_factor_code(ctx, factor_repo, mailer, &user, &factor).await
+}
+
+// check the password of a signed in user, e.g. to reauthenticate. A wrong password
+// counts as a failed sign in attempt, so it cannot be brute forced with a stolen session.
+pub async fn check_password_attempt<T: UserRepo, M: Mailer>(
+  ctx: &Context,
+  user_repo: &T,
+  mailer: &M,
+  user: &User,
+  password: &String,
+) -> Result<()> {
+  if user.is_locked() {
+    return Err(account_locked_error(user));
+  }
+
+  if let Err(err) = user.check_password(password) {
+    return Err(count_failed_sign_in(ctx, user_repo, mailer, user.clone(), err).await?);
+  }
+
+  Ok(())
+}
+
+// count a wrong password. The account is locked when there is no attempt left,
+// and an unlock token is sent to the email address. It returns the error to report.
+pub async fn count_failed_sign_in<T: UserRepo, M: Mailer>(
+  ctx: &Context,
+  user_repo: &T,
+  mailer: &M,
+  mut user: User,
+  err: anyhow::Error,
+) -> Result<anyhow::Error> {
+  let max_failed_sign_in_attempts = ctx
+    .settings
+    .get::<i32>("max_failed_sign_in_attempts")
+    .expect("max_failed_sign_in_attempts must set");
+  let account_lock_duration = ctx
+    .settings
+    .get::<i64>("account_lock_duration")
+    .expect("account_lock_duration must set");
+
+  // concurrent failures cannot share a count, each one gets its own number
+  let attempts = user_repo.count_failed_sign_in_attempt(&user.id).await?;
+  let remaining_attempts = (max_failed_sign_in_attempts - attempts).max(0);
+
+  if remaining_attempts > 0 {
+    return Ok(err.context(ErrorHint::RemainingAttempts(remaining_attempts)));
+  }
+
+  // only the attempt which reached the max locks the account and sends the unlock token
+  if attempts > max_failed_sign_in_attempts {
+    user.locked_until = Some(Utc::now() + Duration::seconds(account_lock_duration));
+    return Ok(account_locked_error(&user));
+  }
+
+  let find_me = FindOneUserCondition::Id(user.id.clone());
+
+  let unlock_token = user.lock(account_lock_duration);
+  let lock_data = vec![
+    UpdateOneUserField::FailedSignInAttempts(user.failed_sign_in_attempts),
+    UpdateOneUserField::LockedUntil(user.locked_until),
+    UpdateOneUserField::UnlockToken(user.unlock_token.clone()),
+    UpdateOneUserField::UnlockSentAt(user.unlock_sent_at),
+  ];
+  user_repo.update_one(&find_me, &lock_data).await?;
+
+  if let Some(email) = &user.email {
+    mailer
+      .send(
+        email,
+        "Your account has been locked",
+        &format!(
+          "Your account has been locked after too many failed sign in attempts. Use this token to unlock it: {}",
+          unlock_token
+        ),
+      )
+      .await?;
+  }
+
+  Ok(account_locked_error(&user))
+}
+
+pub fn account_locked_error(user: &User) -> anyhow::Error {
+  anyhow!(FormatError::AccountLocked(
+    "too many failed sign in attempts, try again later or unlock the account with the token sent to the email address".to_string()
+  ))
+  .context(ErrorHint::RetryAfter(user.locked_retry_after()))
+}
+
+pub async fn send_email_otp<T: UserRepo, M: Mailer>(
+  ctx: &Context,
+  user_repo: &T,
+  mailer: &M,
+  email: String,
+) -> Result<()> {
+  let find_one_user_condition = FindOneUserCondition::Email(email.clone());
+  let mut user = match user_repo.find_one(&find_one_user_condition).await {
+    Ok(user) => user,
+    // do not let the caller know whether the email address is registered
+    Err(err) if err.get_code() == "NOT_FOUND" => return Ok(()),
+    Err(err) => return Err(err),
+  };
+
+  let email_otp = renew_otp(ctx, user_repo, &mut user, OtpChannel::Email).await?;
+
+  mailer
+    .send(
+      &email,
+      "Your sign in code",
+      &format!("Your sign in code is: {}", email_otp),
+    )
+    .await
+}
+
+// send a one-time code to the phone number. When no user has this phone number,
+// the code is kept as a sign up and the user is created when the code is verified.
+pub async fn send_phone_otp<T: UserRepo, P: PhoneSignUpRepo, L: SmsSendRepo, S: SmsSender>(
+  ctx: &Context,
+  user_repo: &T,
+  sign_up_repo: &P,
+  sms_send_repo: &L,
+  sms_sender: &S,
+  phone: String,
+) -> Result<()> {
+  let phone = normalize_phone(&phone)?;
+  check_sms_rate_limit(ctx, sms_send_repo, &phone).await?;
+
+  let find_one_user_condition = FindOneUserCondition::Phone(phone.clone());
+  let phone_otp = match user_repo.find_one(&find_one_user_condition).await {
+    Ok(mut user) => renew_otp(ctx, user_repo, &mut user, OtpChannel::Phone).await?,
+    Err(err) if err.get_code() == "NOT_FOUND" => {
+      let mut sign_up = match sign_up_repo.find_one(&phone).await {
+        Ok(sign_up) => sign_up,
+        Err(err) if err.get_code() == "NOT_FOUND" => PhoneSignUp {
+          phone: phone.clone(),
+          otp: OneTimeCode::default(),
+        },
+        Err(err) => return Err(err),
+      };
+      let code = renew_code(ctx, &mut sign_up.otp, OtpChannel::Phone)?;
+      sign_up_repo.upsert(&sign_up).await?;
+
+      code
+    }
+    Err(err) => return Err(err),
+  };
+
+  sms_send_repo.insert(&phone, ctx.ip.as_deref()).await?;
+  sms_sender
+    .send(&phone, &format!("Your sign in code is: {}", phone_otp))
+    .await
+}
+
+// a text message costs money, so the messages to a phone number
+// and the messages requested by an IP address are limited
+async fn check_sms_rate_limit<L: SmsSendRepo>(
+  ctx: &Context,
+  sms_send_repo: &L,
+  phone: &str,
+) -> Result<()> {
+  let sms_send_window = ctx
+    .settings
+    .get::<i64>("sms_send_window")
+    .expect("sms_send_window must set");
+  let sms_max_sends_per_phone = ctx
+    .settings
+    .get::<i64>("sms_max_sends_per_phone")
+    .expect("sms_max_sends_per_phone must set");
+  let sms_max_sends_per_ip = ctx
+    .settings
+    .get::<i64>("sms_max_sends_per_ip")
+    .expect("sms_max_sends_per_ip must set");
+
+  let since = Utc::now() - Duration::seconds(sms_send_window);
+  sms_send_repo.delete_before(since).await?;
+
+  let is_limited = sms_send_repo.count_by_phone(phone, since).await? >= sms_max_sends_per_phone
+    || match &ctx.ip {
+      Some(ip) => sms_send_repo.count_by_ip(ip, since).await? >= sms_max_sends_per_ip,
+      None => false,
+    };
+  if is_limited {
+    return Err(
+      anyhow!(FormatError::TooManyRequests(
+        "too many text messages have been sent, try again later".to_string()
+      ))
+      .context(ErrorHint::RetryAfter(sms_send_window)),
+    );
+  }
+
+  Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn auth_by_email_otp<
+  T: UserRepo,
+  K: RefreshTokenRepo,
+  F: MfaFactorRepo,
+  C: MfaChallengeRepo,
+  D: TrustedDeviceRepo,
+>(
+  ctx: &Context,
+  user_repo: T,
+  token_repo: K,
+  factor_repo: &F,
+  challenge_repo: &C,
+  device_repo: &D,
+  sign_in_input: &CreateTokenByEmailOtpInput,
+) -> Result<CreateTokenResult> {
+  let find_one_user_condition = FindOneUserCondition::Email(sign_in_input.email.clone());
+  let user = user_repo.find_one(&find_one_user_condition).await?;
+
+  verify_otp(
+    ctx,
+    &user_repo,
+    &user,
+    OtpChannel::Email,
+    &sign_in_input.code,
+  )
+  .await?;
+
+  let mut update_email_otp_data = vec![UpdateOneUserField::Otp(
+    OtpChannel::Email,
+    OneTimeCode::default(),
+  )];
+  // the code was sent to the user's email address, so it also confirms the address
+  if !user.is_confirmed() {
+    update_email_otp_data.push(UpdateOneUserField::ConfirmationToken(None));
+    update_email_otp_data.push(UpdateOneUserField::ConfirmedAt(Some(Utc::now())));
+  }
+  user_repo
+    .update_one(
+      &FindOneUserCondition::Id(user.id.clone()),
+      &update_email_otp_data,
+    )
+    .await?;
+
+  create_token_or_challenge(
+    ctx,
+    &token_repo,
+    factor_repo,
+    challenge_repo,
+    device_repo,
+    &user,
+    AuthMethod::Otp,
+    sign_in_input.device_token.as_ref(),
+  )
+  .await
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn auth_by_phone_otp<
+  T: UserRepo,
+  K: RefreshTokenRepo,
+  F: MfaFactorRepo,
+  C: MfaChallengeRepo,
+  D: TrustedDeviceRepo,
+  P: PhoneSignUpRepo,
+>(
+  ctx: &Context,
+  user_repo: T,
+  token_repo: K,
+  factor_repo: &F,
+  challenge_repo: &C,
+  device_repo: &D,
+  sign_up_repo: &P,
+  sign_in_input: &CreateTokenByPhoneOtpInput,
+) -> Result<CreateTokenResult> {
+  let phone = normalize_phone(&sign_in_input.phone)?;
+  let find_one_user_condition = FindOneUserCondition::Phone(phone.clone());
+  let user = match user_repo.find_one(&find_one_user_condition).await {
+    Ok(user) => user,
+    Err(err) if err.get_code() == "NOT_FOUND" => {
+      // a new user has no factors yet
+      let user =
+        sign_up_by_phone_otp(ctx, &user_repo, sign_up_repo, &phone, &sign_in_input.code).await?;
+      return create_token_output(ctx, &token_repo, &user, SessionAuth::by(AuthMethod::Sms))
+        .await
+        .map(CreateTokenResult::Token);
+    }
+    Err(err) => return Err(err),
+  };
+
+  verify_otp(
+    ctx,
+    &user_repo,
+    &user,
+    OtpChannel::Phone,
+    &sign_in_input.code,
+  )
+  .await?;
+
+  let mut update_phone_otp_data = vec![UpdateOneUserField::Otp(
+    OtpChannel::Phone,
+    OneTimeCode::default(),
+  )];
+  if !user.is_phone_confirmed() {
+    update_phone_otp_data.push(UpdateOneUserField::PhoneConfirmedAt(Some(Utc::now())));
+  }
+  user_repo
+    .update_one(
+      &FindOneUserCondition::Id(user.id.clone()),
+      &update_phone_otp_data,
+    )
+    .await?;
+
+  create_token_or_challenge(
+    ctx,
+    &token_repo,
+    factor_repo,
+    challenge_repo,
+    device_repo,
+    &user,
+    AuthMethod::Sms,
+    sign_in_input.device_token.as_ref(),
+  )
+  .await
+}
+
+// verify the code of a phone sign up, and create the user with the confirmed phone number
+async fn sign_up_by_phone_otp<T: UserRepo, P: PhoneSignUpRepo>(
+  ctx: &Context,
+  user_repo: &T,
+  sign_up_repo: &P,
+  phone: &str,
+  code: &str,
+) -> Result<User> {
+  let (otp_exp, otp_max_attempts) = otp_limits(ctx, OtpChannel::Phone);
+
+  let otp = match sign_up_repo
+    .count_otp_attempt(phone, otp_max_attempts)
+    .await
+  {
+    Ok(otp) => otp,
+    Err(err) if err.get_code() == "NOT_FOUND" => bail!(FormatError::Unauthenticated(
+      "one-time code is invalid".to_string()
+    )),
+    Err(err) => return Err(err),
+  };
+
+  if let Err(err) = check_counted_otp(&otp, code, otp_exp, otp_max_attempts) {
+    if otp.is_expired(otp_exp) || otp.attempts >= otp_max_attempts {
+      sign_up_repo.delete_one(phone).await?;
+    }
+
+    return Err(err);
+  }
+
+  // only one of concurrent requests with the right code creates the user
+  if !sign_up_repo.delete_one(phone).await? {
+    bail!(FormatError::Unauthenticated(
+      "one-time code is invalid".to_string()
+    ));
+  }
+
+  let aud = ctx.settings.get::<String>("aud").expect("aud must set");
+  let mut user = User::new_with_phone(aud, phone.to_string());
+  user_repo.insert(&user).await?;
+
+  user.phone_confirmed_at = Some(Utc::now());
+  user_repo
+    .update_one(
+      &FindOneUserCondition::Id(user.id.clone()),
+      &[UpdateOneUserField::PhoneConfirmedAt(
+        user.phone_confirmed_at,
+      )],
+    )
+    .await?;
+
+  Ok(user)
+}
+
+// create and save a new one-time code for the channel, it returns the raw code
+async fn renew_otp<T: UserRepo>(
+  ctx: &Context,
+  user_repo: &T,
+  user: &mut User,
+  channel: OtpChannel,
+) -> Result<String> {
+  let code = renew_code(ctx, user.otp_mut(channel), channel)?;
+  user_repo
+    .update_one(
+      &FindOneUserCondition::Id(user.id.clone()),
+      &[UpdateOneUserField::Otp(channel, user.otp(channel).clone())],
+    )
+    .await?;
+
+  Ok(code)
+}
+
+// replace the one-time code by a new one of the channel's length, it returns the raw code
+fn renew_code(ctx: &Context, otp: &mut OneTimeCode, channel: OtpChannel) -> Result<String> {
+  let otp_length_key = format!("{}_length", channel.name());
+  let otp_length = ctx
+    .settings
+    .get::<usize>(&otp_length_key)
+    .unwrap_or_else(|_| panic!("{} must set", otp_length_key));
+  if !(6..=8).contains(&otp_length) {
+    bail!(FormatError::ServerError(format!(
+      "{} must be from 6 to 8",
+      otp_length_key
+    )));
+  }
+
+  let otp_resend_interval_key = format!("{}_resend_interval", channel.name());
+  let otp_resend_interval = ctx
+    .settings
+    .get::<i64>(&otp_resend_interval_key)
+    .unwrap_or_else(|_| panic!("{} must set", otp_resend_interval_key));
+  // a new code resets the attempts, so codes cannot be sent in a row
+  let wait = otp.resend_wait(otp_resend_interval);
+  if wait > 0 {
+    return Err(
+      anyhow!(FormatError::TooManyRequests(
+        "a code has been sent recently".to_string()
+      ))
+      .context(ErrorHint::RetryAfter(wait)),
+    );
+  }
+
+  Ok(otp.renew(otp_length))
+}
+
+// check the one-time code of the channel. Every attempt is counted before the check,
+// the code is invalidated when it has expired or there is no attempt left.
+async fn verify_otp<T: UserRepo>(
+  ctx: &Context,
+  user_repo: &T,
+  user: &User,
+  channel: OtpChannel,
+  code: &str,
+) -> Result<()> {
+  let (otp_exp, otp_max_attempts) = otp_limits(ctx, channel);
+
+  if user.otp(channel).code.is_none() {
+    bail!(FormatError::Unauthenticated(
+      "one-time code is invalid".to_string()
+    ));
+  }
+
+  // concurrent attempts cannot share a count, each one takes its own attempt
+  let otp = match user_repo
+    .count_otp_attempt(&user.id, channel, otp_max_attempts)
+    .await
+  {
+    Ok(otp) => otp,
+    Err(err) if err.get_code() == "NOT_FOUND" => bail!(FormatError::Unauthenticated(
+      "one-time code is invalid".to_string()
+    )),
+    Err(err) => return Err(err),
+  };
+
+  if let Err(err) = check_counted_otp(&otp, code, otp_exp, otp_max_attempts) {
+    if otp.is_expired(otp_exp) || otp.attempts >= otp_max_attempts {
+      user_repo
+        .update_one(
+          &FindOneUserCondition::Id(user.id.clone()),
+          &[UpdateOneUserField::Otp(channel, OneTimeCode::default())],
+        )
+        .await?;
+    }
+
+    return Err(err);
+  }
+
+  Ok(())
+}
+
+// expiry in seconds and max attempts of the one-time codes of the channel
+fn otp_limits(ctx: &Context, channel: OtpChannel) -> (i64, i32) {
+  let otp_exp_key = format!("{}_exp", channel.name());
+  let otp_exp = ctx
+    .settings
+    .get::<i64>(&otp_exp_key)
+    .unwrap_or_else(|_| panic!("{} must set", otp_exp_key));
+  let otp_max_attempts_key = format!("{}_max_attempts", channel.name());
+  let otp_max_attempts = ctx
+    .settings
+    .get::<i32>(&otp_max_attempts_key)
+    .unwrap_or_else(|_| panic!("{} must set", otp_max_attempts_key));
+
+  (otp_exp, otp_max_attempts)
+}
+
+// check a one-time code whose attempt has already been counted
+fn check_counted_otp(otp: &OneTimeCode, code: &str, otp_exp: i64, max_attempts: i32) -> Result<()> {
+  if otp.is_expired(otp_exp) {
+    bail!(FormatError::Unauthenticated(
+      "one-time code has expired".to_string()
+    ));
+  }
+
+  if !otp.is_matched(code) {
+    return Err(
+      anyhow!(FormatError::Unauthenticated(
+        "one-time code is incorrect".to_string()
+      ))
+      .context(ErrorHint::RemainingAttempts(
+        (max_attempts - otp.attempts).max(0),
+      )),
+    );
+  }
+
+  Ok(())
+}
+
+// prove the identity again with the password or a verified second factor. The session
+// of the refresh token is swapped for one with a fresh auth_time for sensitive operations,
+// the methods of the session are kept, so a reauthenticated session keeps its aal
+#[allow(clippy::too_many_arguments)]
+pub async fn reauthenticate<
+  T: UserRepo,
+  K: RefreshTokenRepo,
+  F: MfaFactorRepo,
+  R: RecoveryCodeRepo,
+  W: WebauthnRepo,
+  M: Mailer,
+>(
+  ctx: &Context,
+  user_repo: &T,
+  token_repo: &K,
+  factor_repo: &F,
+  recovery_code_repo: &R,
+  webauthn_repo: &W,
+  mailer: &M,
+  claims: &Claims,
+  reauthenticate_input: &ReauthenticateInput,
+) -> Result<CreateTokenOutput> {
+  let find_me = FindOneUserCondition::Id(claims.user_id.clone());
+  let user = user_repo.find_one(&find_me).await?;
+
+  let refresh_token = token_repo
+    .find_one_by_token(reauthenticate_input.refresh_token.clone())
+    .await?;
+  if refresh_token.user_id != claims.user_id
+    || refresh_token.is_revoked()
+    || refresh_token.client_id.is_some()
+  {
+    bail!(FormatError::Unauthenticated(
+      "the refresh token is not a session of the user".to_string()
+    ));
+  }
+
+  let method = match &reauthenticate_input.password {
+    Some(password) => {
+      check_password_attempt(ctx, user_repo, mailer, &user, password).await?;
+      AuthMethod::Password
+    }
+    None if reauthenticate_input.factor_id.is_some() || reauthenticate_input.code.is_some() => {
+      let second_factor = SecondFactorInput {
+        factor_id: reauthenticate_input.factor_id.clone(),
+        code: reauthenticate_input.code.clone(),
+        passkey: reauthenticate_input.passkey.clone(),
+      };
+      if user.is_locked() {
+        return Err(account_locked_error(&user));
+      }
+
+      let verified = verify_second_factor(
+        ctx,
+        factor_repo,
+        recovery_code_repo,
+        webauthn_repo,
+        &user.id,
+        &second_factor,
+      )
+      .await;
+      match verified {
+        Ok(method) => method,
+        Err(err) if err.get_code() == "UNAUTHENTICATED" => {
+          return Err(count_failed_sign_in(ctx, user_repo, mailer, user, err).await?)
+        }
+        Err(err) => return Err(err),
+      }
+    }
+    None => bail!(FormatError::ValidationFailed(
+      "password or a second factor is required to reauthenticate, a user without them signs in again".to_string()
+    )),
+  };
+
+  let amr = extend_amr(&refresh_token.amr, method);
+  create_token_output(
+    ctx,
+    token_repo,
+    &user,
+    SessionAuth::Reauthenticated { refresh_token, amr },
+  )
+  .await
+}
+
+pub async fn sign_in_anonymously<T: UserRepo, K: RefreshTokenRepo>(
+  ctx: &Context,
+  user_repo: T,
+  token_repo: K,
+) -> Result<CreateTokenOutput> {
+  let aud = ctx.settings.get::<String>("aud").expect("aud must set");
+  let user = User::new_anonymous(aud);
+  user_repo.insert(&user).await?;
+
+  create_token_output(
+    ctx,
+    &token_repo,
+    &user,
+    SessionAuth::by(AuthMethod::Anonymous),
+  )
+  .await
+}
+
+// client_id is the OAuth client which refreshes the session, None for ez-auth itself.
+// A session is refreshed by its own client only.
+pub async fn auth_by_refresh_token<T: UserRepo, K: RefreshTokenRepo, O: OAuthClientRepo>(
+  ctx: &Context,
+  user_repo: T,
+  token_repo: K,
+  client_repo: &O,
+  client_id: Option<&Id>,
   refresh_token_value: String,
 ) -> Result<CreateTokenOutput> {
   let refresh_token = token_repo.find_one_by_token(refresh_token_value).await?;
@@ -115,10 +1444,130 @@ pub async fn auth_by_refresh_token<T: UserRepo, K: RefreshTokenRepo>(
       "refresh token is revoked".to_string()
     ));
   }
-  let find_one_user_condition = FindOneUserCondition::Id(refresh_token.user_id);
+  if refresh_token.client_id.as_ref() != client_id {
+    bail!(FormatError::Unauthenticated(
+      "refresh token was issued to another client".to_string()
+    ));
+  }
+
+  let client = match &refresh_token.client_id {
+    Some(client_id) => Some(client_repo.find_one(client_id).await?),
+    None => None,
+  };
+  if let Some(refresh_token_exp) = client.as_ref().and_then(|client| client.refresh_token_exp) {
+    let session_age = Utc::now() - refresh_token.authenticated_at;
+    if session_age.num_seconds() > refresh_token_exp as i64 {
+      bail!(FormatError::Unauthenticated(
+        "refresh token has expired, please sign in again".to_string()
+      ));
+    }
+  }
+
+  let find_one_user_condition = FindOneUserCondition::Id(refresh_token.user_id.clone());
   let user = user_repo.find_one(&find_one_user_condition).await?;
 
-  create_token_output(ctx, &token_repo, &user, Some(refresh_token.token)).await
+  create_client_token_output(
+    ctx,
+    &token_repo,
+    &user,
+    SessionAuth::Refreshed(refresh_token),
+    client.as_ref(),
+  )
+  .await
+}
+
+pub async fn send_magic_link<T: UserRepo, M: Mailer>(
+  user_repo: &T,
+  mailer: &M,
+  email: String,
+) -> Result<()> {
+  let find_one_user_condition = FindOneUserCondition::Email(email.clone());
+  let mut user = match user_repo.find_one(&find_one_user_condition).await {
+    Ok(user) => user,
+    // do not let the caller know whether the email address is registered
+    Err(err) if err.get_code() == "NOT_FOUND" => return Ok(()),
+    Err(err) => return Err(err),
+  };
+
+  let magic_link_token = user.create_magic_link_token();
+  let update_magic_link_data = vec![
+    UpdateOneUserField::MagicLinkToken(user.magic_link_token.clone()),
+    UpdateOneUserField::MagicLinkSentAt(user.magic_link_sent_at),
+  ];
+  user_repo
+    .update_one(
+      &FindOneUserCondition::Id(user.id.clone()),
+      &update_magic_link_data,
+    )
+    .await?;
+
+  mailer
+    .send(
+      &email,
+      "Your sign in link",
+      &format!("Use this token to sign in: {}", magic_link_token),
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn auth_by_magic_link<
+  T: UserRepo,
+  K: RefreshTokenRepo,
+  F: MfaFactorRepo,
+  C: MfaChallengeRepo,
+  D: TrustedDeviceRepo,
+>(
+  ctx: &Context,
+  user_repo: T,
+  token_repo: K,
+  factor_repo: &F,
+  challenge_repo: &C,
+  device_repo: &D,
+  sign_in_input: &CreateTokenByMagicLinkInput,
+) -> Result<CreateTokenResult> {
+  let magic_link_exp = ctx
+    .settings
+    .get::<i64>("magic_link_exp")
+    .expect("magic_link_exp must set");
+
+  // a magic link can be used only once, even when it has expired
+  let user = user_repo
+    .take_magic_link(&hash_token(&sign_in_input.token))
+    .await?;
+
+  if user.is_magic_link_token_expired(magic_link_exp) {
+    bail!(FormatError::Unauthenticated(
+      "magic link has expired".to_string()
+    ));
+  }
+
+  let mut update_magic_link_data = vec![UpdateOneUserField::MagicLinkSentAt(None)];
+
+  // the link was sent to the user's email address, so it also confirms the address
+  if !user.is_confirmed() {
+    update_magic_link_data.push(UpdateOneUserField::ConfirmationToken(None));
+    update_magic_link_data.push(UpdateOneUserField::ConfirmedAt(Some(Utc::now())));
+  }
+
+  user_repo
+    .update_one(
+      &FindOneUserCondition::Id(user.id.clone()),
+      &update_magic_link_data,
+    )
+    .await?;
+
+  create_token_or_challenge(
+    ctx,
+    &token_repo,
+    factor_repo,
+    challenge_repo,
+    device_repo,
+    &user,
+    AuthMethod::MagicLink,
+    sign_in_input.device_token.as_ref(),
+  )
+  .await
 }
 
 pub async fn logout<T: RefreshTokenRepo>(
@@ -1,16 +1,26 @@
-use super::token_usecase::create_token_output;
+use super::identity_usecase::confirm_identity;
+use super::token_usecase::{check_password_attempt, create_token_or_challenge};
 use crate::context::Context;
-use crate::model::error::{FormatError, SpecificError};
-use crate::model::token::CreateTokenOutput;
+use crate::model::crypto::hash_token;
+use crate::model::error::{ErrorHint, FormatError, SpecificError};
+use crate::model::id::Id;
+use crate::model::token::{AuthMethod, CreateTokenResult};
 use crate::model::user::{
-  CreateUserInput, FindAUserInput, FindOneUserCondition, UpdateOneUserField, User, UserMetaData,
-  Users, VerifyUserInput,
+  validate_email, validate_username, BanUserInput, ChangePasswordInput, CreateUserInput,
+  DefaultUserRole, FindAUserInput, FindOneUserCondition, InviteUserInput, LinkEmailPasswordInput,
+  RestoreAccountInput, UpdateEmailInput, UpdateOneUserField, User, UserMetaData, VerifyUserInput,
+  VerifyUserType,
 };
+use crate::repository::identity_repo::IdentityRepo;
+use crate::repository::mailer::Mailer;
+use crate::repository::mfa_challenge_repo::MfaChallengeRepo;
+use crate::repository::mfa_factor_repo::MfaFactorRepo;
 use crate::repository::refresh_token_repo::RefreshTokenRepo;
+use crate::repository::trusted_device_repo::TrustedDeviceRepo;
 use crate::repository::user_repo::UserRepo;
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use bson::bson;
-use chrono::Utc;
+use chrono::{Duration, Utc};
 
 pub struct UserUsecase<T>
 where
@@ -27,90 +37,647 @@ where
     UserUsecase { user_repo }
   }
 
-  pub async fn find_many_users(&mut self) -> Result<Users> {
-    let users = self.user_repo.find().await?;
-
-    Ok(Users { items: users })
+  pub async fn find_many_users(&mut self) -> Result<Vec<User>> {
+    self.user_repo.find().await
   }
 
   pub async fn find_user(&mut self, find_a_user_input: FindAUserInput) -> Result<User> {
-    let condition: FindOneUserCondition;
-
-    if find_a_user_input.id.is_some() {
-      condition = FindOneUserCondition::Id(find_a_user_input.id.unwrap());
-    } else if find_a_user_input.email.is_some() {
-      condition = FindOneUserCondition::Email(find_a_user_input.email.unwrap());
+    let condition = if let Some(id) = find_a_user_input.id {
+      FindOneUserCondition::Id(id)
+    } else if let Some(email) = find_a_user_input.email {
+      FindOneUserCondition::Email(email)
     } else {
       bail!(FormatError::ValidationFailed(
         "FindAUserInput invalid".to_string()
       ));
-    }
+    };
 
     let user = self.user_repo.find_one(&condition).await?;
     Ok(user)
   }
 }
 
-pub async fn create_user<R: UserRepo>(
+pub async fn create_user<R: UserRepo, M: Mailer>(
   aud: String,
   user_repo: &R,
+  mailer: &M,
   create_user_req: CreateUserInput,
 ) -> Result<User> {
-  let find_one_user_condition = FindOneUserCondition::Email(create_user_req.email.clone());
-  let check_exist_user_res = user_repo.find_one(&find_one_user_condition).await;
-
-  match check_exist_user_res {
-    Ok(exist_user) => {
-      if exist_user.is_confirmed() {
-        bail!(FormatError::DuplicateError(
-          "A user with this email address has already been registered".to_string()
-        ));
-      } else {
-        // delete not confirm user then create new one
-        user_repo.delete_one(&find_one_user_condition).await?;
-      }
-    }
-    Err(err) => {
-      // NOT_FOUND error mean this email address is available,
-      // then user can use this email address to sign up
-      if err.get_code() != "NOT_FOUND" {
-        return Err(err);
-      }
-    }
+  validate_email(&create_user_req.email)?;
+  ensure_email_available(user_repo, &create_user_req.email).await?;
+  if let Some(username) = &create_user_req.username {
+    ensure_username_available(user_repo, username, None).await?;
   }
 
   let user_data = UserMetaData::new(bson!({}));
 
-  let user = User::new(
+  let mut user = User::new(
     aud,
     create_user_req.email,
     create_user_req.password,
     user_data,
   )?;
+  user.username = create_user_req.username;
 
   user_repo.insert(&user).await?;
   // TODO: format error
-  // TODO: Send a verify email
-  println!(
-    "confirmation_token: {}",
-    user.confirmation_token.clone().unwrap()
+  send_confirmation(mailer, &user).await?;
+
+  Ok(user)
+}
+
+// upgrade an anonymous user to a full account. The user keeps the same id
+// and user meta data, so everything the guest has done is kept.
+pub async fn link_email_password<R: UserRepo, M: Mailer>(
+  user_repo: &R,
+  mailer: &M,
+  user_id: &Id,
+  link_email_password_input: LinkEmailPasswordInput,
+) -> Result<User> {
+  let find_me = FindOneUserCondition::Id(user_id.clone());
+  let mut user = user_repo.find_one(&find_me).await?;
+
+  if !user.is_anonymous {
+    bail!(FormatError::BadRequest(
+      "only an anonymous user can link an email address and a password".to_string()
+    ));
+  }
+
+  validate_email(&link_email_password_input.email)?;
+  ensure_email_available(user_repo, &link_email_password_input.email).await?;
+
+  user.change_password(&link_email_password_input.password)?;
+  user.email = Some(link_email_password_input.email.clone());
+  user.is_anonymous = false;
+  user.renew_confirmation_token();
+
+  let link_email_password_data = vec![
+    UpdateOneUserField::Email(link_email_password_input.email),
+    UpdateOneUserField::EncryptedPassword(user.encrypted_password.clone()),
+    UpdateOneUserField::IsAnonymous(false),
+    UpdateOneUserField::ConfirmationToken(user.confirmation_token.clone()),
+    UpdateOneUserField::ConfirmationSentAt(user.confirmation_sent_at),
+  ];
+  user_repo
+    .update_one(&find_me, &link_email_password_data)
+    .await?;
+
+  send_confirmation(mailer, &user).await?;
+
+  Ok(user)
+}
+
+// change the password and sign out the other sessions,
+// the session of the given refresh token stays alive. Trusted devices are forgotten.
+pub async fn change_password<T: UserRepo, K: RefreshTokenRepo, D: TrustedDeviceRepo, M: Mailer>(
+  ctx: &Context,
+  user_repo: &T,
+  refresh_token_repo: &K,
+  device_repo: &D,
+  mailer: &M,
+  user_id: &Id,
+  change_password_input: ChangePasswordInput,
+) -> Result<()> {
+  let find_me = FindOneUserCondition::Id(user_id.clone());
+  let mut user = user_repo.find_one(&find_me).await?;
+
+  check_password_attempt(
+    ctx,
+    user_repo,
+    mailer,
+    &user,
+    &change_password_input.current_password,
+  )
+  .await?;
+  user.change_password(&change_password_input.new_password)?;
+  user_repo
+    .update_one(
+      &find_me,
+      &[UpdateOneUserField::EncryptedPassword(
+        user.encrypted_password.clone(),
+      )],
+    )
+    .await?;
+  device_repo.delete_by_user_id(user_id).await?;
+
+  match &change_password_input.refresh_token {
+    Some(refresh_token) => {
+      refresh_token_repo
+        .delete_by_user_id_except(user_id, refresh_token)
+        .await
+    }
+    None => refresh_token_repo.delete_by_user_id(user_id).await,
+  }
+}
+
+pub async fn resend_confirmation<R: UserRepo, M: Mailer>(
+  ctx: &Context,
+  user_repo: &R,
+  mailer: &M,
+  email: String,
+) -> Result<()> {
+  let confirmation_resend_interval = ctx
+    .settings
+    .get::<i64>("confirmation_resend_interval")
+    .expect("confirmation_resend_interval must set");
+
+  let find_one_user_condition = FindOneUserCondition::Email(email);
+  let mut user = match user_repo.find_one(&find_one_user_condition).await {
+    Ok(user) => user,
+    // do not let the caller know whether the email address is registered
+    Err(err) if err.get_code() == "NOT_FOUND" => return Ok(()),
+    Err(err) => return Err(err),
+  };
+
+  if user.is_confirmed() {
+    return Ok(());
+  }
+
+  let wait = user.confirmation_resend_wait(confirmation_resend_interval);
+  if wait > 0 {
+    return Err(
+      anyhow!(FormatError::TooManyRequests(
+        "a confirmation has been sent recently".to_string()
+      ))
+      .context(ErrorHint::RetryAfter(wait)),
+    );
+  }
+
+  user.renew_confirmation_token();
+  let update_confirmation_data = vec![
+    UpdateOneUserField::ConfirmationToken(user.confirmation_token.clone()),
+    UpdateOneUserField::ConfirmationSentAt(user.confirmation_sent_at),
+  ];
+  user_repo
+    .update_one(
+      &FindOneUserCondition::Id(user.id.clone()),
+      &update_confirmation_data,
+    )
+    .await?;
+
+  send_confirmation(mailer, &user).await
+}
+
+// send the sign up confirmation token, or the invitation token for invited users
+async fn send_confirmation<M: Mailer>(mailer: &M, user: &User) -> Result<()> {
+  let email = match &user.email {
+    Some(email) => email,
+    None => return Ok(()),
+  };
+  let confirmation_token = user.confirmation_token.clone().unwrap_or_default();
+
+  if user.is_invited() {
+    return mailer
+      .send(
+        email,
+        "You have been invited",
+        &format!(
+          "Use this token to accept the invitation and set your password: {}",
+          confirmation_token
+        ),
+      )
+      .await;
+  }
+
+  mailer
+    .send(
+      email,
+      "Confirm your sign up",
+      &format!(
+        "Use this token to confirm your email address: {}",
+        confirmation_token
+      ),
+    )
+    .await
+}
+
+pub async fn check_admin<R: UserRepo>(user_repo: &R, user_id: &Id) -> Result<()> {
+  let user = user_repo
+    .find_one(&FindOneUserCondition::Id(user_id.clone()))
+    .await?;
+
+  if !user.is_admin() {
+    bail!(FormatError::Forbidden(
+      "only admin can do this action".to_string()
+    ));
+  }
+
+  Ok(())
+}
+
+// ban the user and sign out all sessions, a banned user cannot get new tokens
+pub async fn ban_user<R: UserRepo, K: RefreshTokenRepo>(
+  user_repo: &R,
+  refresh_token_repo: &K,
+  ban_user_input: BanUserInput,
+) -> Result<User> {
+  if let Some(duration) = ban_user_input.duration {
+    if duration <= 0 {
+      bail!(FormatError::ValidationFailed(
+        "duration must be a positive number of seconds".to_string()
+      ));
+    }
+  }
+
+  let find_user = FindOneUserCondition::Id(ban_user_input.id.clone());
+  let mut user = user_repo.find_one(&find_user).await?;
+
+  user.ban(
+    ban_user_input.duration.map(i64::from),
+    ban_user_input.reason,
   );
+  let ban_data = vec![
+    UpdateOneUserField::BannedUntil(user.banned_until),
+    UpdateOneUserField::BanReason(user.ban_reason.clone()),
+  ];
+  user_repo.update_one(&find_user, &ban_data).await?;
+  refresh_token_repo.delete_by_user_id(&user.id).await?;
+
+  Ok(user)
+}
+
+pub async fn unban_user<R: UserRepo>(user_repo: &R, user_id: &Id) -> Result<User> {
+  let find_user = FindOneUserCondition::Id(user_id.clone());
+  let mut user = user_repo.find_one(&find_user).await?;
+
+  let unban_data = vec![
+    UpdateOneUserField::BannedUntil(None),
+    UpdateOneUserField::BanReason(None),
+  ];
+  user_repo.update_one(&find_user, &unban_data).await?;
+  user.banned_until = None;
+  user.ban_reason = None;
+
+  Ok(user)
+}
+
+pub async fn invite_user<R: UserRepo, M: Mailer>(
+  aud: String,
+  user_repo: &R,
+  mailer: &M,
+  invite_user_input: InviteUserInput,
+) -> Result<User> {
+  let role = invite_user_input
+    .role
+    .unwrap_or_else(|| DefaultUserRole::GeneralUser.to_string());
+  if role.is_empty() || role.len() > 40 {
+    bail!(FormatError::ValidationFailed(
+      "role must have from 1 to 40 characters".to_string()
+    ));
+  }
+
+  validate_email(&invite_user_input.email)?;
+  ensure_email_available(user_repo, &invite_user_input.email).await?;
+
+  let user_data = invite_user_input
+    .data
+    .unwrap_or_else(|| UserMetaData::new(bson!({})));
+  let user = User::new_invited(aud, invite_user_input.email, role, user_data);
+
+  user_repo.insert(&user).await?;
+  send_confirmation(mailer, &user).await?;
+
+  Ok(user)
+}
+
+pub async fn update_email<R: UserRepo, M: Mailer>(
+  ctx: &Context,
+  user_repo: &R,
+  mailer: &M,
+  user_id: &Id,
+  update_email_input: UpdateEmailInput,
+) -> Result<()> {
+  let secure_email_change = ctx
+    .settings
+    .get::<bool>("secure_email_change")
+    .expect("secure_email_change must set");
+  let new_email = update_email_input.new_email;
+
+  validate_email(&new_email)?;
+
+  let find_me = FindOneUserCondition::Id(user_id.clone());
+  let mut user = user_repo.find_one(&find_me).await?;
+  if user.email.as_ref() == Some(&new_email) {
+    bail!(FormatError::ValidationFailed(
+      "new email address is the same as the current one".to_string()
+    ));
+  }
+
+  // the address is not proved yet, a pending sign up with it is deleted by confirm_email_change
+  check_email_free(user_repo, &new_email).await?;
+
+  // a user without email address, e.g. a phone user, only confirms the new address
+  let secure_email_change = secure_email_change && user.email.is_some();
+  let (token_new, token_current) = user.create_email_change(new_email.clone(), secure_email_change);
+  let update_email_change_data = vec![
+    UpdateOneUserField::EmailChange(user.email_change.clone()),
+    UpdateOneUserField::EmailChangeTokenNew(user.email_change_token_new.clone()),
+    UpdateOneUserField::EmailChangeTokenCurrent(user.email_change_token_current.clone()),
+    UpdateOneUserField::EmailChangeSentAt(user.email_change_sent_at),
+  ];
+  user_repo
+    .update_one(&find_me, &update_email_change_data)
+    .await?;
+
+  mailer
+    .send(
+      &new_email,
+      "Confirm your new email address",
+      &format!(
+        "Use this token to confirm your new email address: {}",
+        token_new
+      ),
+    )
+    .await?;
+
+  if let (Some(token_current), Some(email)) = (token_current, &user.email) {
+    mailer
+      .send(
+        email,
+        "Confirm your email address change",
+        &format!(
+          "Use this token to confirm changing your email address to {}: {}",
+          new_email, token_current
+        ),
+      )
+      .await?;
+  }
+
+  Ok(())
+}
+
+// set or remove the username of the current user
+pub async fn update_username<R: UserRepo>(
+  user_repo: &R,
+  user_id: &Id,
+  username: Option<String>,
+) -> Result<User> {
+  if let Some(username) = &username {
+    ensure_username_available(user_repo, username, Some(user_id)).await?;
+  }
+
+  let find_me = FindOneUserCondition::Id(user_id.clone());
+  let mut user = user_repo.find_one(&find_me).await?;
+  user_repo
+    .update_one(&find_me, &[UpdateOneUserField::Username(username.clone())])
+    .await?;
+  user.username = username;
 
   Ok(user)
 }
 
+// check the username is valid and not taken by another user than owner_id
+async fn ensure_username_available<R: UserRepo>(
+  user_repo: &R,
+  username: &str,
+  owner_id: Option<&Id>,
+) -> Result<()> {
+  validate_username(username)?;
+
+  let find_one_user_condition = FindOneUserCondition::Username(username.to_string());
+  match user_repo.find_one(&find_one_user_condition).await {
+    Ok(exist_user) if Some(&exist_user.id) == owner_id => Ok(()),
+    Ok(_) => bail!(FormatError::DuplicateError(
+      "this username has already been taken".to_string()
+    )),
+    Err(err) if err.get_code() == "NOT_FOUND" => Ok(()),
+    Err(err) => Err(err),
+  }
+}
+
+// An email address is free when no confirmed or invited user uses it,
+// it returns the not confirmed user who signed up with the email address
+async fn check_email_free<R: UserRepo>(user_repo: &R, email: &str) -> Result<Option<User>> {
+  let find_one_user_condition = FindOneUserCondition::Email(email.to_string());
+  let check_exist_user_res = user_repo.find_one(&find_one_user_condition).await;
+
+  match check_exist_user_res {
+    Ok(exist_user) => {
+      // the email address may belong to a verified identity of the user,
+      // and an invitation keeps the role the admin gave it until it is accepted
+      if exist_user.is_confirmed()
+        || exist_user.is_invited()
+        || exist_user.email.as_deref() != Some(email)
+      {
+        bail!(FormatError::DuplicateError(
+          "A user with this email address has already been registered".to_string()
+        ));
+      }
+
+      Ok(Some(exist_user))
+    }
+    // NOT_FOUND error mean this email address is available,
+    // then user can use this email address to sign up
+    Err(err) if err.get_code() == "NOT_FOUND" => Ok(None),
+    Err(err) => Err(err),
+  }
+}
+
+// An email address is available when it is free.
+// A not confirmed user with the same email address is deleted.
+async fn ensure_email_available<R: UserRepo>(user_repo: &R, email: &str) -> Result<()> {
+  if let Some(exist_user) = check_email_free(user_repo, email).await? {
+    // delete not confirm user then create new one
+    user_repo
+      .delete_one(&FindOneUserCondition::Id(exist_user.id))
+      .await?;
+  }
+
+  Ok(())
+}
+
+pub async fn recover_password<T: UserRepo, M: Mailer>(
+  user_repo: &T,
+  mailer: &M,
+  email: String,
+) -> Result<()> {
+  let find_one_user_condition = FindOneUserCondition::Email(email.clone());
+  let mut user = match user_repo.find_one(&find_one_user_condition).await {
+    Ok(user) => user,
+    // do not let the caller know whether the email address is registered
+    Err(err) if err.get_code() == "NOT_FOUND" => return Ok(()),
+    Err(err) => return Err(err),
+  };
+
+  let recover_token = user.create_recover_token();
+  let update_recover_data = vec![
+    UpdateOneUserField::RecoverToken(user.recover_token.clone()),
+    UpdateOneUserField::RecoverSentAt(user.recover_sent_at),
+  ];
+  user_repo
+    .update_one(
+      &FindOneUserCondition::Id(user.id.clone()),
+      &update_recover_data,
+    )
+    .await?;
+
+  mailer
+    .send(
+      &email,
+      "Reset your password",
+      &format!("Use this token to reset your password: {}", recover_token),
+    )
+    .await
+}
+
+// soft delete the user and sign out all sessions. The account can be restored
+// during the grace period, then it is deleted for good by purge_deleted_users.
+pub async fn delete_me<T: UserRepo, K: RefreshTokenRepo>(
+  user_repo: &T,
+  refresh_token_repo: &K,
+  user_id: &Id,
+) -> Result<()> {
+  let find_me = FindOneUserCondition::Id(user_id.clone());
+  let user = user_repo.find_one(&find_me).await?;
+
+  if user.is_deleted() {
+    return Ok(());
+  }
+
+  user_repo
+    .update_one(&find_me, &[UpdateOneUserField::DeletedAt(Some(Utc::now()))])
+    .await?;
+  refresh_token_repo.delete_by_user_id(user_id).await?;
+
+  Ok(())
+}
+
+// send a restore token to a deleted user, the token is verified with the RESTORE type
+pub async fn restore_account<T: UserRepo, M: Mailer>(
+  ctx: &Context,
+  user_repo: &T,
+  mailer: &M,
+  restore_account_input: RestoreAccountInput,
+) -> Result<()> {
+  let grace_period = ctx
+    .settings
+    .get::<i64>("account_deletion_grace_period")
+    .expect("account_deletion_grace_period must set");
+
+  let email = restore_account_input.email;
+  let find_one_user_condition = FindOneUserCondition::Email(email.clone());
+  let mut user = match user_repo.find_one(&find_one_user_condition).await {
+    Ok(user) => user,
+    // do not let the caller know whether the email address is registered
+    Err(err) if err.get_code() == "NOT_FOUND" => return Ok(()),
+    Err(err) => return Err(err),
+  };
+
+  if !user.is_restorable(grace_period) {
+    return Ok(());
+  }
+
+  // the restore token shares the recover token columns,
+  // a deleted user cannot recover the password anyway
+  let restore_token = user.create_recover_token();
+  let update_restore_data = vec![
+    UpdateOneUserField::RecoverToken(user.recover_token.clone()),
+    UpdateOneUserField::RecoverSentAt(user.recover_sent_at),
+  ];
+  user_repo
+    .update_one(
+      &FindOneUserCondition::Id(user.id.clone()),
+      &update_restore_data,
+    )
+    .await?;
+
+  mailer
+    .send(
+      &email,
+      "Restore your account",
+      &format!("Use this token to restore your account: {}", restore_token),
+    )
+    .await
+}
+
+// delete for good the users whose grace period is over
+pub async fn purge_deleted_users<T: UserRepo>(ctx: &Context, user_repo: &T) -> Result<u64> {
+  let grace_period = ctx
+    .settings
+    .get::<i64>("account_deletion_grace_period")
+    .expect("account_deletion_grace_period must set");
+
+  user_repo
+    .purge_deleted(Utc::now() - Duration::seconds(grace_period))
+    .await
+}
+
 // TODO: remove ctx
-pub async fn verify_user<T: UserRepo, K: RefreshTokenRepo>(
+#[allow(clippy::too_many_arguments)]
+pub async fn verify_user<
+  T: UserRepo,
+  K: RefreshTokenRepo,
+  I: IdentityRepo,
+  F: MfaFactorRepo,
+  C: MfaChallengeRepo,
+  D: TrustedDeviceRepo,
+>(
   ctx: &Context,
   user_repo: &T,
   refresh_token_repo: &K,
+  identity_repo: &I,
+  factor_repo: &F,
+  challenge_repo: &C,
+  device_repo: &D,
   verify_user_input: &VerifyUserInput,
-) -> Result<CreateTokenOutput> {
-  // TODO: recover
+) -> Result<Option<CreateTokenResult>> {
+  let user = match verify_user_input.verify_type {
+    VerifyUserType::SignUp => confirm_sign_up(ctx, user_repo, verify_user_input).await,
+    VerifyUserType::Recover => {
+      recover_user(
+        ctx,
+        user_repo,
+        refresh_token_repo,
+        device_repo,
+        verify_user_input,
+      )
+      .await
+    }
+    VerifyUserType::Invite => accept_invitation(ctx, user_repo, verify_user_input).await,
+    // an email change only confirms the address, it does not sign in
+    VerifyUserType::EmailChange => {
+      confirm_email_change(ctx, user_repo, verify_user_input).await?;
+      return Ok(None);
+    }
+    VerifyUserType::Restore => restore_user(ctx, user_repo, verify_user_input).await,
+    VerifyUserType::Unlock => unlock_user(ctx, user_repo, verify_user_input).await,
+    VerifyUserType::Identity => {
+      confirm_identity(ctx, user_repo, identity_repo, &verify_user_input.token).await
+    }
+  }?;
+
+  // a verification link is a first factor, a user with a verified factor still needs the second one
+  create_token_or_challenge(
+    ctx,
+    refresh_token_repo,
+    factor_repo,
+    challenge_repo,
+    device_repo,
+    &user,
+    AuthMethod::EmailLink,
+    None,
+  )
+  .await
+  .map(Some)
+}
+
+async fn confirm_sign_up<T: UserRepo>(
+  ctx: &Context,
+  user_repo: &T,
+  verify_user_input: &VerifyUserInput,
+) -> Result<User> {
   let find_one_user_condition =
     FindOneUserCondition::ConfirmationToken(verify_user_input.token.clone());
   let mut user = user_repo.find_one(&find_one_user_condition).await?;
 
+  if user.is_invited() {
+    bail!(FormatError::ValidationFailed(
+      "this token is an invitation, verify it with the INVITE type".to_string()
+    ));
+  }
+
+  check_confirmation_token_exp(ctx, &user)?;
+
   // update confirmed_at
   let mut update_confirmation_data = vec![
     UpdateOneUserField::ConfirmationToken(None),
@@ -127,5 +694,261 @@ pub async fn verify_user<T: UserRepo, K: RefreshTokenRepo>(
     .update_one(&find_one_user_condition, &update_confirmation_data)
     .await?;
 
-  create_token_output(ctx, refresh_token_repo, &user, None).await
+  Ok(user)
+}
+
+async fn recover_user<T: UserRepo, K: RefreshTokenRepo, D: TrustedDeviceRepo>(
+  ctx: &Context,
+  user_repo: &T,
+  refresh_token_repo: &K,
+  device_repo: &D,
+  verify_user_input: &VerifyUserInput,
+) -> Result<User> {
+  let password = match &verify_user_input.password {
+    Some(password) => password,
+    None => bail!(FormatError::ValidationFailed(
+      "password is required to recover an account".to_string()
+    )),
+  };
+  let recover_token_exp = ctx
+    .settings
+    .get::<i64>("recover_token_exp")
+    .expect("recover_token_exp must set");
+
+  let find_one_user_condition =
+    FindOneUserCondition::RecoverToken(hash_token(&verify_user_input.token));
+  let mut user = user_repo.find_one(&find_one_user_condition).await?;
+
+  if user.is_deleted() {
+    bail!(FormatError::BadRequest(
+      "this account has been deleted, verify the token with the RESTORE type".to_string()
+    ));
+  }
+
+  if user.is_recover_token_expired(recover_token_exp) {
+    bail!(FormatError::Unauthenticated(
+      "recover token has expired".to_string()
+    ));
+  }
+
+  user.change_password(password)?;
+  // a new password also unlocks the account
+  let mut update_recover_data = vec![
+    UpdateOneUserField::EncryptedPassword(user.encrypted_password.clone()),
+    UpdateOneUserField::RecoverToken(None),
+    UpdateOneUserField::RecoverSentAt(None),
+    UpdateOneUserField::FailedSignInAttempts(0),
+    UpdateOneUserField::LockedUntil(None),
+    UpdateOneUserField::UnlockToken(None),
+    UpdateOneUserField::UnlockSentAt(None),
+  ];
+
+  // the recover token was sent to the user's email address,
+  // so using it also proves the user owns that address
+  if !user.is_confirmed() {
+    update_recover_data.push(UpdateOneUserField::ConfirmationToken(None));
+    update_recover_data.push(UpdateOneUserField::ConfirmedAt(Some(Utc::now())));
+  }
+
+  user_repo
+    .update_one(
+      &FindOneUserCondition::Id(user.id.clone()),
+      &update_recover_data,
+    )
+    .await?;
+
+  // sign out every session which was created with the old password
+  refresh_token_repo.delete_by_user_id(&user.id).await?;
+  device_repo.delete_by_user_id(&user.id).await?;
+
+  Ok(user)
+}
+
+async fn accept_invitation<T: UserRepo>(
+  ctx: &Context,
+  user_repo: &T,
+  verify_user_input: &VerifyUserInput,
+) -> Result<User> {
+  let password = match &verify_user_input.password {
+    Some(password) => password,
+    None => bail!(FormatError::ValidationFailed(
+      "password is required to accept an invitation".to_string()
+    )),
+  };
+
+  let find_one_user_condition =
+    FindOneUserCondition::ConfirmationToken(verify_user_input.token.clone());
+  let mut user = user_repo.find_one(&find_one_user_condition).await?;
+
+  if !user.is_invited() {
+    bail!(FormatError::ValidationFailed(
+      "this token is not an invitation".to_string()
+    ));
+  }
+
+  check_confirmation_token_exp(ctx, &user)?;
+
+  user.change_password(password)?;
+  let accept_invitation_data = vec![
+    UpdateOneUserField::ConfirmationToken(None),
+    UpdateOneUserField::ConfirmedAt(Some(Utc::now())),
+    UpdateOneUserField::EncryptedPassword(user.encrypted_password.clone()),
+  ];
+  user_repo
+    .update_one(&find_one_user_condition, &accept_invitation_data)
+    .await?;
+
+  Ok(user)
+}
+
+async fn confirm_email_change<T: UserRepo>(
+  ctx: &Context,
+  user_repo: &T,
+  verify_user_input: &VerifyUserInput,
+) -> Result<()> {
+  let email_change_token_exp = ctx
+    .settings
+    .get::<i64>("email_change_token_exp")
+    .expect("email_change_token_exp must set");
+
+  let token = hash_token(&verify_user_input.token);
+  let mut user = user_repo
+    .find_one(&FindOneUserCondition::EmailChangeToken(token.clone()))
+    .await?;
+  let find_me = FindOneUserCondition::Id(user.id.clone());
+
+  if user.is_email_change_token_expired(email_change_token_exp) {
+    bail!(FormatError::Unauthenticated(
+      "email change token has expired".to_string()
+    ));
+  }
+
+  // each token can be used once, the change is done when all tokens are used
+  let update_email_change_data = if user.email_change_token_new.as_ref() == Some(&token) {
+    user.email_change_token_new = None;
+    UpdateOneUserField::EmailChangeTokenNew(None)
+  } else {
+    user.email_change_token_current = None;
+    UpdateOneUserField::EmailChangeTokenCurrent(None)
+  };
+  let mut update_email_change_data = vec![update_email_change_data];
+
+  let confirmed_email = match (
+    &user.email_change_token_new,
+    &user.email_change_token_current,
+  ) {
+    (None, None) => user.email_change.take(),
+    _ => None,
+  };
+  if let Some(new_email) = confirmed_email {
+    // another user may take the new email address while waiting for the confirmations
+    ensure_email_available(user_repo, &new_email).await?;
+
+    update_email_change_data.extend(vec![
+      UpdateOneUserField::Email(new_email.clone()),
+      UpdateOneUserField::ConfirmedAt(Some(Utc::now())),
+      UpdateOneUserField::EmailChange(None),
+      UpdateOneUserField::EmailChangeSentAt(None),
+    ]);
+    user.email = Some(new_email);
+  }
+
+  user_repo
+    .update_one(&find_me, &update_email_change_data)
+    .await?;
+
+  Ok(())
+}
+
+fn check_confirmation_token_exp(ctx: &Context, user: &User) -> Result<()> {
+  let confirmation_token_exp = ctx
+    .settings
+    .get::<i64>("confirmation_token_exp")
+    .expect("confirmation_token_exp must set");
+
+  if user.is_confirmation_token_expired(confirmation_token_exp) {
+    bail!(FormatError::TokenExpired(
+      "confirmation token has expired, request a new one".to_string()
+    ));
+  }
+
+  Ok(())
+}
+
+async fn restore_user<T: UserRepo>(
+  ctx: &Context,
+  user_repo: &T,
+  verify_user_input: &VerifyUserInput,
+) -> Result<User> {
+  let recover_token_exp = ctx
+    .settings
+    .get::<i64>("recover_token_exp")
+    .expect("recover_token_exp must set");
+  let grace_period = ctx
+    .settings
+    .get::<i64>("account_deletion_grace_period")
+    .expect("account_deletion_grace_period must set");
+
+  let find_one_user_condition =
+    FindOneUserCondition::RecoverToken(hash_token(&verify_user_input.token));
+  let mut user = user_repo.find_one(&find_one_user_condition).await?;
+
+  if !user.is_restorable(grace_period) {
+    bail!(FormatError::BadRequest(
+      "this account cannot be restored".to_string()
+    ));
+  }
+
+  if user.is_recover_token_expired(recover_token_exp) {
+    bail!(FormatError::Unauthenticated(
+      "restore token has expired".to_string()
+    ));
+  }
+
+  let restore_data = vec![
+    UpdateOneUserField::DeletedAt(None),
+    UpdateOneUserField::RecoverToken(None),
+    UpdateOneUserField::RecoverSentAt(None),
+  ];
+  user_repo
+    .update_one(&FindOneUserCondition::Id(user.id.clone()), &restore_data)
+    .await?;
+  user.deleted_at = None;
+
+  Ok(user)
+}
+
+async fn unlock_user<T: UserRepo>(
+  ctx: &Context,
+  user_repo: &T,
+  verify_user_input: &VerifyUserInput,
+) -> Result<User> {
+  let unlock_token_exp = ctx
+    .settings
+    .get::<i64>("unlock_token_exp")
+    .expect("unlock_token_exp must set");
+
+  let find_one_user_condition =
+    FindOneUserCondition::UnlockToken(hash_token(&verify_user_input.token));
+  let mut user = user_repo.find_one(&find_one_user_condition).await?;
+
+  if user.is_unlock_token_expired(unlock_token_exp) {
+    bail!(FormatError::Unauthenticated(
+      "unlock token has expired".to_string()
+    ));
+  }
+
+  let unlock_data = vec![
+    UpdateOneUserField::FailedSignInAttempts(0),
+    UpdateOneUserField::LockedUntil(None),
+    UpdateOneUserField::UnlockToken(None),
+    UpdateOneUserField::UnlockSentAt(None),
+  ];
+  user_repo
+    .update_one(&FindOneUserCondition::Id(user.id.clone()), &unlock_data)
+    .await?;
+  user.failed_sign_in_attempts = 0;
+  user.locked_until = None;
+
+  Ok(user)
 }
@@ -0,0 +1,704 @@
+use super::mfa_usecase::check_factor_management;
+use super::token_usecase::find_mfa_challenge;
+use crate::context::Context;
+use crate::model::error::{FormatError, SpecificError};
+use crate::model::id::Id;
+use crate::model::mfa::MfaFactor;
+use crate::model::token::Claims;
+use crate::model::user::FindOneUserCondition;
+use crate::model::webauthn::{
+  base64url_encode, Ceremony, PasskeyAssertionInput, PasskeyAuthenticationOptions,
+  PasskeyRegistrationOptions, RegisterPasskeyInput, RelyingParty, WebauthnChallenge,
+  WebauthnCredential, SUPPORTED_ALGORITHMS,
+};
+use crate::repository::mfa_challenge_repo::MfaChallengeRepo;
+use crate::repository::mfa_factor_repo::MfaFactorRepo;
+use crate::repository::user_repo::UserRepo;
+use crate::repository::webauthn_repo::WebauthnRepo;
+use anyhow::{anyhow, bail, Result};
+
+fn relying_party(ctx: &Context) -> RelyingParty {
+  RelyingParty {
+    id: ctx
+      .settings
+      .get::<String>("webauthn_rp_id")
+      .expect("webauthn_rp_id must set"),
+    name: ctx
+      .settings
+      .get::<String>("webauthn_rp_name")
+      .expect("webauthn_rp_name must set"),
+    origin: ctx
+      .settings
+      .get::<String>("webauthn_origin")
+      .expect("webauthn_origin must set"),
+  }
+}
+
+async fn create_challenge<W: WebauthnRepo>(
+  ctx: &Context,
+  webauthn_repo: &W,
+  user_id: Option<Id>,
+  ceremony: Ceremony,
+) -> Result<WebauthnChallenge> {
+  let challenge_exp = ctx
+    .settings
+    .get::<i64>("webauthn_challenge_exp")
+    .expect("webauthn_challenge_exp must set");
+
+  let challenge = WebauthnChallenge::new(user_id, ceremony, challenge_exp);
+  webauthn_repo.insert_challenge(&challenge).await?;
+
+  Ok(challenge)
+}
+
+// the challenge must be unused, unexpired and for the ceremony
+async fn take_challenge<W: WebauthnRepo>(
+  webauthn_repo: &W,
+  challenge_id: &Id,
+  ceremony: Ceremony,
+) -> Result<WebauthnChallenge> {
+  let challenge = match webauthn_repo.take_challenge(challenge_id).await {
+    Ok(challenge) => challenge,
+    Err(err) if err.get_code() == "NOT_FOUND" => bail!(FormatError::Unauthenticated(
+      "passkey challenge is invalid or used".to_string()
+    )),
+    Err(err) => return Err(err),
+  };
+
+  if challenge.ceremony != ceremony || challenge.is_expired() {
+    bail!(FormatError::Unauthenticated(
+      "passkey challenge is invalid or expired".to_string()
+    ));
+  }
+
+  Ok(challenge)
+}
+
+// start registering a passkey of the signed in user,
+// a user with a verified factor has to verify one first like for the other factors
+pub async fn passkey_registration_options<R: UserRepo, F: MfaFactorRepo, W: WebauthnRepo>(
+  ctx: &Context,
+  user_repo: &R,
+  factor_repo: &F,
+  webauthn_repo: &W,
+  claims: &Claims,
+) -> Result<PasskeyRegistrationOptions> {
+  let user_id = &claims.user_id;
+  check_factor_management(factor_repo, claims).await?;
+  let user = user_repo
+    .find_one(&FindOneUserCondition::Id(user_id.clone()))
+    .await?;
+  if user.is_anonymous {
+    bail!(FormatError::BadRequest(
+      "an anonymous user cannot register a passkey".to_string()
+    ));
+  }
+
+  let rp = relying_party(ctx);
+  let exclude_credentials = webauthn_repo
+    .find_credentials_by_user_id(user_id)
+    .await?
+    .into_iter()
+    .map(|credential| credential.credential_id)
+    .collect();
+  let challenge = create_challenge(
+    ctx,
+    webauthn_repo,
+    Some(user_id.clone()),
+    Ceremony::Registration,
+  )
+  .await?;
+
+  let fallback_name = user.id.to_string();
+  let user_name = user
+    .email
+    .or(user.username)
+    .or(user.phone)
+    .unwrap_or(fallback_name);
+
+  Ok(PasskeyRegistrationOptions {
+    challenge_id: challenge.id,
+    challenge: challenge.challenge,
+    rp_id: rp.id,
+    rp_name: rp.name,
+    user_handle: base64url_encode(user.id.to_string().as_bytes()),
+    user_name,
+    algorithms: SUPPORTED_ALGORITHMS.to_vec(),
+    exclude_credentials,
+  })
+}
+
+// finish registering a passkey, it is a verified factor of the user
+pub async fn register_passkey<F: MfaFactorRepo, W: WebauthnRepo>(
+  ctx: &Context,
+  factor_repo: &F,
+  webauthn_repo: &W,
+  claims: &Claims,
+  input: RegisterPasskeyInput,
+) -> Result<MfaFactor> {
+  let user_id = &claims.user_id;
+  check_factor_management(factor_repo, claims).await?;
+  let challenge =
+    take_challenge(webauthn_repo, &input.challenge_id, Ceremony::Registration).await?;
+  if challenge.user_id.as_ref() != Some(user_id) {
+    bail!(FormatError::Unauthenticated(
+      "passkey challenge belongs to another user".to_string()
+    ));
+  }
+
+  let factor = MfaFactor::new_webauthn(user_id, input.friendly_name.clone());
+  let credential = WebauthnCredential::new(&relying_party(ctx), &challenge, &factor.id, &input)?;
+
+  factor_repo.insert(&factor).await?;
+  if let Err(err) = webauthn_repo.insert_credential(&credential).await {
+    factor_repo.delete_one(user_id, &factor.id).await?;
+    return Err(err);
+  }
+
+  Ok(factor)
+}
+
+// start a passkey sign in. With a challenge token the passkey is the second factor
+// and only the credentials of the user are allowed, otherwise any discoverable passkey.
+pub async fn passkey_authentication_options<W: WebauthnRepo, C: MfaChallengeRepo>(
+  ctx: &Context,
+  webauthn_repo: &W,
+  challenge_repo: &C,
+  challenge_token: Option<String>,
+) -> Result<PasskeyAuthenticationOptions> {
+  let user_id = match challenge_token {
+    Some(challenge_token) => Some(
+      find_mfa_challenge(challenge_repo, &challenge_token)
+        .await?
+        .user_id,
+    ),
+    None => None,
+  };
+
+  let allow_credentials = match &user_id {
+    Some(user_id) => webauthn_repo
+      .find_credentials_by_user_id(user_id)
+      .await?
+      .into_iter()
+      .map(|credential| credential.credential_id)
+      .collect(),
+    None => vec![],
+  };
+  let challenge = create_challenge(ctx, webauthn_repo, user_id, Ceremony::Authentication).await?;
+
+  Ok(PasskeyAuthenticationOptions {
+    challenge_id: challenge.id,
+    challenge: challenge.challenge,
+    rp_id: relying_party(ctx).id,
+    allow_credentials,
+  })
+}
+
+// check a passkey assertion, it returns the credential used.
+// user_id is the user of the MFA challenge when the passkey is a second factor.
+pub async fn verify_passkey_assertion<W: WebauthnRepo>(
+  ctx: &Context,
+  webauthn_repo: &W,
+  user_id: Option<&Id>,
+  assertion: &PasskeyAssertionInput,
+) -> Result<WebauthnCredential> {
+  let challenge = take_challenge(
+    webauthn_repo,
+    &assertion.challenge_id,
+    Ceremony::Authentication,
+  )
+  .await?;
+
+  let credential = match webauthn_repo
+    .find_credential(assertion.credential_id.trim_end_matches('='))
+    .await
+  {
+    Ok(credential) => credential,
+    Err(err) if err.get_code() == "NOT_FOUND" => {
+      return Err(anyhow!(FormatError::Unauthenticated(
+        "passkey is not registered".to_string()
+      )))
+    }
+    Err(err) => return Err(err),
+  };
+
+  let is_other_user = [user_id, challenge.user_id.as_ref()]
+    .iter()
+    .flatten()
+    .any(|user_id| **user_id != credential.user_id);
+  if is_other_user {
+    bail!(FormatError::Unauthenticated(
+      "passkey belongs to another user".to_string()
+    ));
+  }
+
+  let sign_count = credential.verify_assertion(
+    &relying_party(ctx),
+    &challenge,
+    assertion,
+    user_id.is_none(),
+  )?;
+  webauthn_repo
+    .update_sign_count(&credential.id, sign_count)
+    .await?;
+
+  Ok(credential)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::init_config;
+  use crate::model::mfa::{FactorType, MfaChallenge};
+  use crate::model::token::AuthMethod;
+  use crate::model::user::{OneTimeCode, OtpChannel, UpdateOneUserField, User};
+  use crate::model::webauthn::software_authenticator::{
+    SoftwareAuthenticator, FLAGS_USER_PRESENT, FLAGS_USER_VERIFIED,
+  };
+  use crate::model::webauthn::{COSE_ALG_EDDSA, COSE_ALG_ES256};
+  use actix_web::rt::System;
+  use async_trait::async_trait;
+  use chrono::{DateTime, Utc};
+  use sqlx::PgPool;
+  use std::future::Future;
+  use std::sync::Mutex;
+
+  // the repos of a passkey ceremony in memory
+  #[derive(Default)]
+  struct MemoryRepo {
+    users: Mutex<Vec<User>>,
+    factors: Mutex<Vec<MfaFactor>>,
+    mfa_challenges: Mutex<Vec<MfaChallenge>>,
+    webauthn_challenges: Mutex<Vec<WebauthnChallenge>>,
+    credentials: Mutex<Vec<WebauthnCredential>>,
+  }
+
+  // the repo methods the passkey usecases never call
+  fn unused<T>() -> Result<T> {
+    Err(anyhow!("not used by the passkey usecases"))
+  }
+
+  fn not_found(message: &str) -> anyhow::Error {
+    anyhow!(FormatError::NotFoundError(message.to_string()))
+  }
+
+  #[async_trait]
+  impl UserRepo for MemoryRepo {
+    async fn insert(&self, user: &User) -> Result<()> {
+      self.users.lock().unwrap().push(user.clone());
+      Ok(())
+    }
+
+    async fn find_one(&self, condition: &FindOneUserCondition) -> Result<User> {
+      let users = self.users.lock().unwrap();
+      match condition {
+        FindOneUserCondition::Id(id) => users.iter().find(|user| &user.id == id).cloned(),
+        _ => None,
+      }
+      .ok_or_else(|| not_found("user not found"))
+    }
+
+    async fn find(&self) -> Result<Vec<User>> {
+      Ok(self.users.lock().unwrap().clone())
+    }
+
+    async fn update_one(&self, _: &FindOneUserCondition, _: &[UpdateOneUserField]) -> Result<()> {
+      unused()
+    }
+
+    async fn take_magic_link(&self, _: &str) -> Result<User> {
+      unused()
+    }
+
+    async fn count_failed_sign_in_attempt(&self, _: &Id) -> Result<i32> {
+      unused()
+    }
+
+    async fn count_otp_attempt(&self, _: &Id, _: OtpChannel, _: i32) -> Result<OneTimeCode> {
+      unused()
+    }
+
+    async fn delete_one(&self, _: &FindOneUserCondition) -> Result<()> {
+      unused()
+    }
+
+    async fn purge_deleted(&self, _: DateTime<Utc>) -> Result<u64> {
+      unused()
+    }
+  }
+
+  #[async_trait]
+  impl MfaFactorRepo for MemoryRepo {
+    async fn insert(&self, factor: &MfaFactor) -> Result<()> {
+      self.factors.lock().unwrap().push(factor.clone());
+      Ok(())
+    }
+
+    async fn find_by_user_id(&self, user_id: &Id) -> Result<Vec<MfaFactor>> {
+      let factors = self.factors.lock().unwrap();
+      Ok(
+        factors
+          .iter()
+          .filter(|factor| &factor.user_id == user_id)
+          .cloned()
+          .collect(),
+      )
+    }
+
+    async fn find_one(&self, user_id: &Id, id: &Id) -> Result<MfaFactor> {
+      let factors = self.factors.lock().unwrap();
+      factors
+        .iter()
+        .find(|factor| &factor.user_id == user_id && &factor.id == id)
+        .cloned()
+        .ok_or_else(|| not_found("factor not found"))
+    }
+
+    async fn has_verified(&self, user_id: &Id) -> Result<bool> {
+      let factors = self.factors.lock().unwrap();
+      Ok(
+        factors
+          .iter()
+          .any(|factor| &factor.user_id == user_id && factor.is_verified()),
+      )
+    }
+
+    async fn mark_verified(&self, _: &Id, _: i64) -> Result<bool> {
+      unused()
+    }
+
+    async fn update_last_used_step(&self, _: &Id, _: i64) -> Result<bool> {
+      unused()
+    }
+
+    async fn update_otp(&self, _: &Id, _: &OneTimeCode) -> Result<()> {
+      unused()
+    }
+
+    async fn count_otp_attempt(&self, _: &Id, _: i32) -> Result<OneTimeCode> {
+      unused()
+    }
+
+    async fn take_otp(&self, _: &Id, _: &str) -> Result<bool> {
+      unused()
+    }
+
+    async fn delete_one(&self, user_id: &Id, id: &Id) -> Result<()> {
+      let mut factors = self.factors.lock().unwrap();
+      factors.retain(|factor| !(&factor.user_id == user_id && &factor.id == id));
+      Ok(())
+    }
+
+    async fn delete_unverified(&self, _: &Id, _: FactorType) -> Result<()> {
+      unused()
+    }
+  }
+
+  #[async_trait]
+  impl MfaChallengeRepo for MemoryRepo {
+    async fn insert(&self, challenge: &MfaChallenge) -> Result<()> {
+      self.mfa_challenges.lock().unwrap().push(challenge.clone());
+      Ok(())
+    }
+
+    async fn find_by_token(&self, hashed_token: &str) -> Result<MfaChallenge> {
+      let challenges = self.mfa_challenges.lock().unwrap();
+      challenges
+        .iter()
+        .find(|challenge| challenge.token == hashed_token)
+        .cloned()
+        .ok_or_else(|| not_found("MFA challenge not found"))
+    }
+
+    async fn delete_one(&self, id: &Id) -> Result<bool> {
+      let mut challenges = self.mfa_challenges.lock().unwrap();
+      let len = challenges.len();
+      challenges.retain(|challenge| &challenge.id != id);
+      Ok(challenges.len() < len)
+    }
+  }
+
+  #[async_trait]
+  impl WebauthnRepo for MemoryRepo {
+    async fn insert_challenge(&self, challenge: &WebauthnChallenge) -> Result<()> {
+      self
+        .webauthn_challenges
+        .lock()
+        .unwrap()
+        .push(challenge.clone());
+      Ok(())
+    }
+
+    async fn take_challenge(&self, id: &Id) -> Result<WebauthnChallenge> {
+      let mut challenges = self.webauthn_challenges.lock().unwrap();
+      let index = challenges
+        .iter()
+        .position(|challenge| &challenge.id == id)
+        .ok_or_else(|| not_found("passkey challenge not found"))?;
+      Ok(challenges.remove(index))
+    }
+
+    async fn insert_credential(&self, credential: &WebauthnCredential) -> Result<()> {
+      self.credentials.lock().unwrap().push(credential.clone());
+      Ok(())
+    }
+
+    async fn find_credentials_by_user_id(&self, user_id: &Id) -> Result<Vec<WebauthnCredential>> {
+      let credentials = self.credentials.lock().unwrap();
+      Ok(
+        credentials
+          .iter()
+          .filter(|credential| &credential.user_id == user_id)
+          .cloned()
+          .collect(),
+      )
+    }
+
+    async fn find_credential(&self, credential_id: &str) -> Result<WebauthnCredential> {
+      let credentials = self.credentials.lock().unwrap();
+      credentials
+        .iter()
+        .find(|credential| credential.credential_id == credential_id)
+        .cloned()
+        .ok_or_else(|| not_found("credential not found"))
+    }
+
+    async fn update_sign_count(&self, id: &Id, sign_count: i64) -> Result<()> {
+      let mut credentials = self.credentials.lock().unwrap();
+      if let Some(credential) = credentials
+        .iter_mut()
+        .find(|credential| &credential.id == id)
+      {
+        credential.sign_count = sign_count;
+      }
+      Ok(())
+    }
+  }
+
+  // the repos are in memory, the pool never connects
+  fn run<T, F>(test: T)
+  where
+    T: FnOnce(Context) -> F + 'static,
+    F: Future<Output = ()> + 'static,
+  {
+    System::new("test").block_on(async {
+      let pool = PgPool::connect_lazy("postgres://localhost/ez_auth_test").unwrap();
+      test(Context::without_request(pool, init_config())).await
+    });
+  }
+
+  async fn insert_user(repo: &MemoryRepo, phone: &str) -> User {
+    let user = User::new_with_phone("test".to_string(), phone.to_string());
+    UserRepo::insert(repo, &user).await.unwrap();
+    user
+  }
+
+  fn claims_of(user: &User, methods: &[AuthMethod]) -> Claims {
+    let now = Utc::now().timestamp() as usize;
+    Claims::new(
+      user.id.clone(),
+      "test".to_string(),
+      now + 300,
+      false,
+      now,
+      methods
+        .iter()
+        .map(|method| method.name().to_string())
+        .collect(),
+      vec![],
+      None,
+    )
+  }
+
+  async fn register(
+    ctx: &Context,
+    repo: &MemoryRepo,
+    claims: &Claims,
+    authenticator: &SoftwareAuthenticator,
+  ) -> Result<MfaFactor> {
+    let options = passkey_registration_options(ctx, repo, repo, repo, claims).await?;
+    let input = authenticator.register(&options.challenge_id, &options.challenge);
+    register_passkey(ctx, repo, repo, claims, input).await
+  }
+
+  // the MFA challenge of a password sign in, it returns the challenge token
+  async fn password_sign_in(repo: &MemoryRepo, user: &User) -> String {
+    let (challenge, challenge_token) =
+      MfaChallenge::new(&user.id, vec![AuthMethod::Password.name().to_string()], 300);
+    MfaChallengeRepo::insert(repo, &challenge).await.unwrap();
+    challenge_token
+  }
+
+  #[test]
+  fn registers_a_passkey_and_completes_an_mfa_challenge_with_it() {
+    run(|ctx| async move {
+      let repo = MemoryRepo::default();
+      let user = insert_user(&repo, "+14155550100").await;
+      let mut authenticator = SoftwareAuthenticator::new(COSE_ALG_ES256, &relying_party(&ctx));
+
+      let factor = register(
+        &ctx,
+        &repo,
+        &claims_of(&user, &[AuthMethod::Password]),
+        &authenticator,
+      )
+      .await
+      .unwrap();
+      assert!(factor.is_verified());
+      let credential_id = base64url_encode(&authenticator.credential_id);
+      let stored = repo.find_credential(&credential_id).await.unwrap();
+      assert_eq!(stored.factor_id, factor.id);
+      assert_eq!(stored.user_id, user.id);
+
+      let challenge_token = password_sign_in(&repo, &user).await;
+      let options = passkey_authentication_options(&ctx, &repo, &repo, Some(challenge_token))
+        .await
+        .unwrap();
+      assert_eq!(options.allow_credentials, vec![credential_id.clone()]);
+
+      // a second factor only needs the user to be present
+      authenticator.flags = FLAGS_USER_PRESENT;
+      authenticator.sign_count = 7;
+      let assertion = authenticator.assert(&options.challenge_id, &options.challenge);
+      let credential = verify_passkey_assertion(&ctx, &repo, Some(&user.id), &assertion)
+        .await
+        .unwrap();
+      assert_eq!(credential.id, stored.id);
+      assert_eq!(
+        repo
+          .find_credential(&credential_id)
+          .await
+          .unwrap()
+          .sign_count,
+        7
+      );
+
+      // the challenge is taken by the first answer
+      let err = verify_passkey_assertion(&ctx, &repo, Some(&user.id), &assertion)
+        .await
+        .unwrap_err();
+      assert_eq!(err.get_code(), "UNAUTHENTICATED");
+    });
+  }
+
+  #[test]
+  fn signs_in_with_a_discoverable_passkey() {
+    run(|ctx| async move {
+      let repo = MemoryRepo::default();
+      let user = insert_user(&repo, "+14155550101").await;
+      let mut authenticator = SoftwareAuthenticator::new(COSE_ALG_EDDSA, &relying_party(&ctx));
+      register(
+        &ctx,
+        &repo,
+        &claims_of(&user, &[AuthMethod::Password]),
+        &authenticator,
+      )
+      .await
+      .unwrap();
+
+      let options = passkey_authentication_options(&ctx, &repo, &repo, None)
+        .await
+        .unwrap();
+      assert!(options.allow_credentials.is_empty());
+
+      // a passwordless sign in requires user verification
+      authenticator.flags = FLAGS_USER_PRESENT;
+      let assertion = authenticator.assert(&options.challenge_id, &options.challenge);
+      assert!(verify_passkey_assertion(&ctx, &repo, None, &assertion)
+        .await
+        .is_err());
+
+      let options = passkey_authentication_options(&ctx, &repo, &repo, None)
+        .await
+        .unwrap();
+      authenticator.flags = FLAGS_USER_VERIFIED;
+      authenticator.sign_count = 1;
+      let assertion = authenticator.assert(&options.challenge_id, &options.challenge);
+      let credential = verify_passkey_assertion(&ctx, &repo, None, &assertion)
+        .await
+        .unwrap();
+      assert_eq!(credential.user_id, user.id);
+    });
+  }
+
+  #[test]
+  fn rejects_a_reused_registration_challenge() {
+    run(|ctx| async move {
+      let repo = MemoryRepo::default();
+      let user = insert_user(&repo, "+14155550102").await;
+      // the second registration needs aal2 once a factor is verified
+      let claims = claims_of(&user, &[AuthMethod::Password, AuthMethod::Webauthn]);
+      let authenticator = SoftwareAuthenticator::new(COSE_ALG_ES256, &relying_party(&ctx));
+
+      let options = passkey_registration_options(&ctx, &repo, &repo, &repo, &claims)
+        .await
+        .unwrap();
+      let input = authenticator.register(&options.challenge_id, &options.challenge);
+      register_passkey(&ctx, &repo, &repo, &claims, input)
+        .await
+        .unwrap();
+
+      let input = authenticator.register(&options.challenge_id, &options.challenge);
+      let err = register_passkey(&ctx, &repo, &repo, &claims, input)
+        .await
+        .unwrap_err();
+      assert_eq!(err.get_code(), "UNAUTHENTICATED");
+      assert_eq!(repo.credentials.lock().unwrap().len(), 1);
+    });
+  }
+
+  #[test]
+  fn rejects_the_passkey_of_another_user() {
+    run(|ctx| async move {
+      let repo = MemoryRepo::default();
+      let user = insert_user(&repo, "+14155550103").await;
+      let other_user = insert_user(&repo, "+14155550104").await;
+      let claims = claims_of(&user, &[AuthMethod::Password]);
+      let other_claims = claims_of(&other_user, &[AuthMethod::Password]);
+      let authenticator = SoftwareAuthenticator::new(COSE_ALG_ES256, &relying_party(&ctx));
+      let other_authenticator = SoftwareAuthenticator::new(COSE_ALG_EDDSA, &relying_party(&ctx));
+
+      // a registration challenge belongs to the user who asked for it
+      let options = passkey_registration_options(&ctx, &repo, &repo, &repo, &claims)
+        .await
+        .unwrap();
+      let input = other_authenticator.register(&options.challenge_id, &options.challenge);
+      let err = register_passkey(&ctx, &repo, &repo, &other_claims, input)
+        .await
+        .unwrap_err();
+      assert_eq!(err.get_code(), "UNAUTHENTICATED");
+
+      register(&ctx, &repo, &claims, &authenticator)
+        .await
+        .unwrap();
+      register(&ctx, &repo, &other_claims, &other_authenticator)
+        .await
+        .unwrap();
+
+      // the MFA challenge of the user cannot be completed with the passkey of another user
+      let challenge_token = password_sign_in(&repo, &user).await;
+      let options = passkey_authentication_options(&ctx, &repo, &repo, Some(challenge_token))
+        .await
+        .unwrap();
+      let assertion = other_authenticator.assert(&options.challenge_id, &options.challenge);
+      let err = verify_passkey_assertion(&ctx, &repo, Some(&user.id), &assertion)
+        .await
+        .unwrap_err();
+      assert_eq!(err.get_code(), "UNAUTHENTICATED");
+      assert_eq!(
+        format!("{}", err),
+        "Unauthenticated: passkey belongs to another user"
+      );
+
+      // the authentication challenge is bound to the user as well
+      let challenge_token = password_sign_in(&repo, &user).await;
+      let options = passkey_authentication_options(&ctx, &repo, &repo, Some(challenge_token))
+        .await
+        .unwrap();
+      let assertion = other_authenticator.assert(&options.challenge_id, &options.challenge);
+      assert!(verify_passkey_assertion(&ctx, &repo, None, &assertion)
+        .await
+        .is_err());
+    });
+  }
+}